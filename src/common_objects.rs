@@ -6,7 +6,7 @@ use nalgebra as na;
 /**
   Creates a cube that is centered on the specified axis
 */
-pub fn centered_cube(size: na::Vector3<f32>, centering: (bool, bool, bool)) -> ScadObject {
+pub fn centered_cube(size: na::Vector3<f64>, centering: (bool, bool, bool)) -> ScadObject {
     let (x, y, z) = centering;
 
     let mut offset = vec3(0., 0., 0.);
@@ -21,9 +21,9 @@ pub fn centered_cube(size: na::Vector3<f32>, centering: (bool, bool, bool)) -> S
     translation
 }
 
-pub fn centered_square(size: na::Vector2<f32>, centering: (bool, bool)) -> ScadObject {
+pub fn centered_square(size: na::Vector2<f64>, centering: (bool, bool)) -> ScadObject {
     let (x, y) = centering;
-    let offset = vec2(if x { size.x } else { 0. }, if y { size.y } else { 0. }) / -2.;
+    let offset = dvec2(if x { size.x } else { 0. }, if y { size.y } else { 0. }) / -2.;
 
     let mut translation = ScadObject::new(Translate2d(offset));
     translation.add_child(ScadObject::new(Square(size)));
@@ -33,8 +33,8 @@ pub fn centered_square(size: na::Vector2<f32>, centering: (bool, bool)) -> ScadO
 /**
   Creates a cylinder that is centered along all axis
 */
-pub fn centered_cylinder(height: f32, size: CircleType) -> ScadObject {
-    let mut translation = ScadObject::new(Translate(vec3(0., 0., -height / 2.)));
+pub fn centered_cylinder(height: f64, size: CircleType) -> ScadObject {
+    let mut translation = ScadObject::new(Translate(dvec3(0., 0., -height / 2.)));
     let cylinder = ScadObject::new(Cylinder(height, size));
 
     translation.add_child(cylinder);
@@ -52,7 +52,7 @@ mod tests {
 
         assert_eq!(
             obj.get_code(),
-            "translate([-0.5,-0,-0])\n{\n\tcube([1,1,1]);\n}"
+            "translate([-0.5,0,0])\n{\n\tcube([1,1,1]);\n}"
         );
     }
     #[test]
@@ -61,7 +61,7 @@ mod tests {
 
         assert_eq!(
             obj.get_code(),
-            "translate([-0,-1,-2])\n{\n\tcube([1,2,4]);\n}"
+            "translate([0,-1,-2])\n{\n\tcube([1,2,4]);\n}"
         );
     }
 
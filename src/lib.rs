@@ -89,7 +89,7 @@
   ## Object parameters
   Almost all `ScadElements` take additional parameters that describe them. They
   are enum parameters so you specify them as you would with enums. Some parameters
-  are regular built in types like `f32` but there are some special ones which are
+  are regular built in types like `f64` but there are some special ones which are
   described below.
 
   ### Vectors
@@ -98,11 +98,68 @@
   why the library contains the functions `vec3(x, y, z)` and `vec2(x, y)`. They are simply
   functions that call the equivalent nalgebra constructor.
 
+  All geometry uses `f64`. `vec3` and `vec2` take `f32` components and widen them without
+  loss, `dvec3` and `dvec2` take `f64` components. All of them can be used in constants.
+  Existing `f32` nalgebra vectors can be converted with `.cast::<f64>()`.
+
+  ```
+  # use scad::*;
+  # use nalgebra as na;
+  let width: f32 = 2.5;
+  let depth: f64 = 0.125;
+  let old_size = na::Vector3::new(1.0f32, 2.0, 3.0);
+
+  scad!(Translate(vec3(width, 0., 0.)); scad!(Cube(old_size.cast::<f64>())));
+  scad!(Translate(dvec3(0., depth, 0.)));
+  ```
+
   ### Circle radii and diameters.
   Just like regular OpenSCAD, you can create round objects by either specifying the diameter
   or radius of the circle. This is done using the `CircleType` enum which is either
   `Diameter(d)` or `Radius(r)`.
 
+  ## Inspecting and transforming trees
+  The parts of an object can be read and changed with `element`, `children` and
+  their `_mut` variants. To work on a whole tree, implement `ScadVisitor` and pass
+  it to `visit`, or implement `ScadFold` and pass it to `fold` to get a rebuilt
+  tree back. Both work on a `ScadObject` as well as on all objects of a `ScadFile`,
+  including the bodies of its modules.
+
+  ### Transformation matrices
+  `MultMatrix` takes a homogeneous `na::Matrix4`. Transformations from nalgebra,
+  like `Isometry3`, `Similarity3`, `Affine3` and `Projective3`, can be converted
+  into the element directly. The 2D versions become `MultMatrix2d`.
+
+  ```
+  # use scad::*;
+  # use nalgebra as na;
+  let pose = na::Isometry3::translation(0., 0., 5.);
+
+  scad!(pose.into(); scad!(Cube(vec3(1., 1., 1.))));
+  ```
+
+  ## Serialization
+  With the `serde` feature enabled, `ScadFile`, `ScadObject`, `ScadElement` and the
+  parameter types implement `Serialize` and `Deserialize`. The shape of the data
+  follows the rust types, which in JSON looks like this:
+
+  * Objects are `{"element": ..., "children": [...], "modifier": "Root", "params": {"center": true, "detail": 32}}`.
+    Only `element` is required when deserializing, `modifier` and `params`
+    are only written for objects that have them. The `"important": true` of
+    older versions is still read as the `Root` modifier.
+  * Files are `{"objects": [...], "variables": {"detail": 32}}`, the
+    `FormatOptions` of a file are not serialized. The top level `"detail"`
+    of older versions is still read as `$fn`. Files with libraries or
+    module definitions also have `"libraries": [{"Use": "BOSL2/std.scad"}]` and
+    `"modules": [{"name": ..., "params": [...], "body": [...]}]`, files
+    with customizer variables have `"customizer": [{"name": ..., "value": {"Number": 20.0}}]`
+    and files with heightmaps have `"heightmaps": [["name.dat", {"rows": [...]}]]`.
+  * Elements and other enums are externally tagged: `"Union"`,
+    `{"Translate": [1.0, 2.0, 3.0]}` or `{"Cylinder": [10.0, {"Radius": 3.0}]}`.
+  * Vectors are arrays of numbers and parameter structs are objects with one
+    field per parameter, for example
+    `{"height": 1.0, "center": false, "convexity": 10, "twist": 0.0, "slices": 1}`.
+
   ## Creating objects in loops
   In most cases, the `scad!` macro should be good enoough to create objects, but one
   case where it is not,  is when you want to create several objects in a loop and
@@ -118,23 +175,133 @@
       parent.add_child(scad!(Cube(vec3(0., i as f32, 0.))));
   }
   ```
+
+  ### Loops and conditions in OpenSCAD
+  Loops in rust end up as one object per iteration in the generated code. To keep
+  the loop in the OpenSCAD code instead, use the `For`, `If`/`Else`, `Let`, `Echo`
+  and `Assert` elements. Their conditions and values are `ScadExpr`s, so they
+  can use the loop variables and other variables of the OpenSCAD code.
+
+  ```
+  # use scad::*;
+  let mut file = ScadFile::new();
+  let steps = LoopValues::Range { start: 0., step: 90., end: 270. };
+  file.add_object(scad!(For(vec![(String::from("angle"), steps)]); {
+      scad!(If(ScadExpr::var("angle").gt(90)); scad!(Cube(vec3(1., 1., 1.)))),
+      scad!(Else; scad!(Sphere(Radius(1.))).with_detail(64))
+  }));
+
+  assert_eq!(
+      file.get_code(),
+      "for(angle=[0:90:270])\n{\n\tif(angle>90)\n\t{\n\t\tcube([1,1,1]);\n\t}\n\telse\n\t{\n\t\tsphere(r=1,$fn=64);\n\t}\n}\n"
+  );
+  ```
+
+  ### Modules
+  Parts that are used many times can be defined once with `ScadFile::define_module`
+  and placed with `Call` objects. `ScadFile::deduplicate` does this
+  automatically for subtrees that occur more than once, which keeps the code of
+  large models short.
+
+  Modules from OpenSCAD libraries are loaded with `ScadFile::use_library` or
+  `include_library`, and called the same way. The arguments of a `ScadCall`
+  can be any value that converts to a `ScadExpr`.
+
+  ```
+  # use scad::*;
+  let mut file = ScadFile::new();
+  file.use_library("MCAD/nuts_and_bolts.scad");
+  file.add_object(scad!(Call(ScadCall::new("nutHole").arg(3).named("tolerance", 0.01))));
+
+  assert_eq!(
+      file.get_code(),
+      "use <MCAD/nuts_and_bolts.scad>\nnutHole(3,tolerance=0.01);\n"
+  );
+  ```
+
+  ### Customizer
+  Values that should be adjustable in the Customizer panel of OpenSCAD are
+  added as `CustomizerVariable`s, with a widget like a slider or a dropdown,
+  a description and a tab. Objects use them through `reference`. The members
+  of a `qstruct!` struct can all be exported at once with its
+  `customizer_variables` function.
+
+  ```
+  # use scad::*;
+  let mut file = ScadFile::new();
+  let height = CustomizerVariable::new("height", &12.).range(5., 30.).tab("Size");
+  file.add_object(scad!(Call(
+      ScadCall::new("cylinder").named("h", height.reference()).named("r", 5)
+  )));
+  file.add_customizer_variable(height);
+
+  assert_eq!(
+      file.get_code(),
+      "/* [Size] */\nheight=12; // [5:30]\ncylinder(h=height,r=5);\n"
+  );
+  ```
+
+  ### Expressions
+  The arguments of elements are numbers and vectors, so the generated code
+  only contains the values that were computed in rust. To keep the
+  relationships between dimensions, any argument of an object can be replaced
+  by a `ScadExpr` with `ScadObject::with_expression`, using the name of the
+  argument in OpenSCAD. Expressions are built with the usual operators and
+  refer to variables by name, for example to customizer variables.
+
+  ```
+  # use scad::*;
+  let wall = ScadExpr::var("wall");
+  let height = ScadExpr::var("height");
+  let lid = scad!(Translate(vec3(0., 0., 7.5)); scad!(Cube(vec3(10., 10., 2.))))
+      .with_expression("v", ScadExpr::vec3(0, 0, wall.clone() + height / 2.));
+
+  assert_eq!(
+      lid.get_code(),
+      "translate([0,0,wall+height/2])\n{\n\tcube([10,10,2]);\n}"
+  );
+  ```
 */
 
 pub mod common_objects;
+mod scad_color;
+mod scad_compare;
+mod scad_customizer;
 mod scad_element;
+mod scad_error;
+mod scad_expr;
 mod scad_file;
+mod scad_format;
+mod scad_heightmap;
+mod scad_import;
+mod scad_library;
+mod scad_module;
 mod scad_object;
+mod scad_parser;
 mod scad_type;
+mod scad_visit;
 
 pub mod scad_macros;
 
+pub use scad_color::*;
+pub use scad_compare::*;
+pub use scad_customizer::*;
 pub use scad_element::CircleType::*;
 pub use scad_element::ScadElement::*;
 pub use scad_element::*;
+pub use scad_error::*;
+pub use scad_expr::*;
 pub use scad_macros::*;
 pub use scad_object::*;
+pub use scad_parser::*;
 
 pub use scad_file::*;
+pub use scad_format::*;
+pub use scad_heightmap::*;
+pub use scad_import::*;
+pub use scad_library::*;
+pub use scad_module::*;
 pub use scad_type::*;
+pub use scad_visit::*;
 
 pub use common_objects::*;
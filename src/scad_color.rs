@@ -0,0 +1,489 @@
+use crate::scad_element::ScadElement;
+use crate::scad_error::{ScadError, ScadResult};
+use nalgebra as na;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+macro_rules! color_names {
+    ($($variant:ident $name:literal $rgb:literal,)*) => {
+        /**
+          The colour names that OpenSCAD accepts, which are the CSS/SVG colour names.
+
+          ```
+          # use scad::*;
+          assert_eq!("SteelBlue".parse::<ColorName>().unwrap(), ColorName::SteelBlue);
+          assert_eq!(scad!(ColorName::SteelBlue.into()).get_code(), "color(\"steelblue\");");
+          ```
+        */
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        pub enum ColorName {
+            $($variant,)*
+        }
+
+        impl ColorName {
+            /// Every colour name, in alphabetical order
+            pub const ALL: &'static [ColorName] = &[$(ColorName::$variant,)*];
+
+            /// The name as written in OpenSCAD code
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(ColorName::$variant => $name,)*
+                }
+            }
+
+            fn packed_rgb(self) -> u32 {
+                match self {
+                    $(ColorName::$variant => $rgb,)*
+                }
+            }
+        }
+    };
+}
+
+color_names! {
+    AliceBlue "aliceblue" 0xf0f8ff,
+    AntiqueWhite "antiquewhite" 0xfaebd7,
+    Aqua "aqua" 0x00ffff,
+    Aquamarine "aquamarine" 0x7fffd4,
+    Azure "azure" 0xf0ffff,
+    Beige "beige" 0xf5f5dc,
+    Bisque "bisque" 0xffe4c4,
+    Black "black" 0x000000,
+    BlanchedAlmond "blanchedalmond" 0xffebcd,
+    Blue "blue" 0x0000ff,
+    BlueViolet "blueviolet" 0x8a2be2,
+    Brown "brown" 0xa52a2a,
+    BurlyWood "burlywood" 0xdeb887,
+    CadetBlue "cadetblue" 0x5f9ea0,
+    Chartreuse "chartreuse" 0x7fff00,
+    Chocolate "chocolate" 0xd2691e,
+    Coral "coral" 0xff7f50,
+    CornflowerBlue "cornflowerblue" 0x6495ed,
+    Cornsilk "cornsilk" 0xfff8dc,
+    Crimson "crimson" 0xdc143c,
+    Cyan "cyan" 0x00ffff,
+    DarkBlue "darkblue" 0x00008b,
+    DarkCyan "darkcyan" 0x008b8b,
+    DarkGoldenrod "darkgoldenrod" 0xb8860b,
+    DarkGray "darkgray" 0xa9a9a9,
+    DarkGreen "darkgreen" 0x006400,
+    DarkGrey "darkgrey" 0xa9a9a9,
+    DarkKhaki "darkkhaki" 0xbdb76b,
+    DarkMagenta "darkmagenta" 0x8b008b,
+    DarkOliveGreen "darkolivegreen" 0x556b2f,
+    DarkOrange "darkorange" 0xff8c00,
+    DarkOrchid "darkorchid" 0x9932cc,
+    DarkRed "darkred" 0x8b0000,
+    DarkSalmon "darksalmon" 0xe9967a,
+    DarkSeaGreen "darkseagreen" 0x8fbc8f,
+    DarkSlateBlue "darkslateblue" 0x483d8b,
+    DarkSlateGray "darkslategray" 0x2f4f4f,
+    DarkSlateGrey "darkslategrey" 0x2f4f4f,
+    DarkTurquoise "darkturquoise" 0x00ced1,
+    DarkViolet "darkviolet" 0x9400d3,
+    DeepPink "deeppink" 0xff1493,
+    DeepSkyBlue "deepskyblue" 0x00bfff,
+    DimGray "dimgray" 0x696969,
+    DimGrey "dimgrey" 0x696969,
+    DodgerBlue "dodgerblue" 0x1e90ff,
+    FireBrick "firebrick" 0xb22222,
+    FloralWhite "floralwhite" 0xfffaf0,
+    ForestGreen "forestgreen" 0x228b22,
+    Fuchsia "fuchsia" 0xff00ff,
+    Gainsboro "gainsboro" 0xdcdcdc,
+    GhostWhite "ghostwhite" 0xf8f8ff,
+    Gold "gold" 0xffd700,
+    Goldenrod "goldenrod" 0xdaa520,
+    Gray "gray" 0x808080,
+    Green "green" 0x008000,
+    GreenYellow "greenyellow" 0xadff2f,
+    Grey "grey" 0x808080,
+    Honeydew "honeydew" 0xf0fff0,
+    HotPink "hotpink" 0xff69b4,
+    IndianRed "indianred" 0xcd5c5c,
+    Indigo "indigo" 0x4b0082,
+    Ivory "ivory" 0xfffff0,
+    Khaki "khaki" 0xf0e68c,
+    Lavender "lavender" 0xe6e6fa,
+    LavenderBlush "lavenderblush" 0xfff0f5,
+    LawnGreen "lawngreen" 0x7cfc00,
+    LemonChiffon "lemonchiffon" 0xfffacd,
+    LightBlue "lightblue" 0xadd8e6,
+    LightCoral "lightcoral" 0xf08080,
+    LightCyan "lightcyan" 0xe0ffff,
+    LightGoldenrodYellow "lightgoldenrodyellow" 0xfafad2,
+    LightGray "lightgray" 0xd3d3d3,
+    LightGreen "lightgreen" 0x90ee90,
+    LightGrey "lightgrey" 0xd3d3d3,
+    LightPink "lightpink" 0xffb6c1,
+    LightSalmon "lightsalmon" 0xffa07a,
+    LightSeaGreen "lightseagreen" 0x20b2aa,
+    LightSkyBlue "lightskyblue" 0x87cefa,
+    LightSlateGray "lightslategray" 0x778899,
+    LightSlateGrey "lightslategrey" 0x778899,
+    LightSteelBlue "lightsteelblue" 0xb0c4de,
+    LightYellow "lightyellow" 0xffffe0,
+    Lime "lime" 0x00ff00,
+    LimeGreen "limegreen" 0x32cd32,
+    Linen "linen" 0xfaf0e6,
+    Magenta "magenta" 0xff00ff,
+    Maroon "maroon" 0x800000,
+    MediumAquamarine "mediumaquamarine" 0x66cdaa,
+    MediumBlue "mediumblue" 0x0000cd,
+    MediumOrchid "mediumorchid" 0xba55d3,
+    MediumPurple "mediumpurple" 0x9370db,
+    MediumSeaGreen "mediumseagreen" 0x3cb371,
+    MediumSlateBlue "mediumslateblue" 0x7b68ee,
+    MediumSpringGreen "mediumspringgreen" 0x00fa9a,
+    MediumTurquoise "mediumturquoise" 0x48d1cc,
+    MediumVioletRed "mediumvioletred" 0xc71585,
+    MidnightBlue "midnightblue" 0x191970,
+    MintCream "mintcream" 0xf5fffa,
+    MistyRose "mistyrose" 0xffe4e1,
+    Moccasin "moccasin" 0xffe4b5,
+    NavajoWhite "navajowhite" 0xffdead,
+    Navy "navy" 0x000080,
+    OldLace "oldlace" 0xfdf5e6,
+    Olive "olive" 0x808000,
+    OliveDrab "olivedrab" 0x6b8e23,
+    Orange "orange" 0xffa500,
+    OrangeRed "orangered" 0xff4500,
+    Orchid "orchid" 0xda70d6,
+    PaleGoldenrod "palegoldenrod" 0xeee8aa,
+    PaleGreen "palegreen" 0x98fb98,
+    PaleTurquoise "paleturquoise" 0xafeeee,
+    PaleVioletRed "palevioletred" 0xdb7093,
+    PapayaWhip "papayawhip" 0xffefd5,
+    PeachPuff "peachpuff" 0xffdab9,
+    Peru "peru" 0xcd853f,
+    Pink "pink" 0xffc0cb,
+    Plum "plum" 0xdda0dd,
+    PowderBlue "powderblue" 0xb0e0e6,
+    Purple "purple" 0x800080,
+    Red "red" 0xff0000,
+    RosyBrown "rosybrown" 0xbc8f8f,
+    RoyalBlue "royalblue" 0x4169e1,
+    SaddleBrown "saddlebrown" 0x8b4513,
+    Salmon "salmon" 0xfa8072,
+    SandyBrown "sandybrown" 0xf4a460,
+    SeaGreen "seagreen" 0x2e8b57,
+    Seashell "seashell" 0xfff5ee,
+    Sienna "sienna" 0xa0522d,
+    Silver "silver" 0xc0c0c0,
+    SkyBlue "skyblue" 0x87ceeb,
+    SlateBlue "slateblue" 0x6a5acd,
+    SlateGray "slategray" 0x708090,
+    SlateGrey "slategrey" 0x708090,
+    Snow "snow" 0xfffafa,
+    SpringGreen "springgreen" 0x00ff7f,
+    SteelBlue "steelblue" 0x4682b4,
+    Tan "tan" 0xd2b48c,
+    Teal "teal" 0x008080,
+    Thistle "thistle" 0xd8bfd8,
+    Tomato "tomato" 0xff6347,
+    Turquoise "turquoise" 0x40e0d0,
+    Violet "violet" 0xee82ee,
+    Wheat "wheat" 0xf5deb3,
+    White "white" 0xffffff,
+    WhiteSmoke "whitesmoke" 0xf5f5f5,
+    Yellow "yellow" 0xffff00,
+    YellowGreen "yellowgreen" 0x9acd32,
+}
+
+impl ColorName {
+    /// The colour as RGB components between 0 and 1
+    pub fn rgb(self) -> na::Vector3<f64> {
+        let rgb = self.packed_rgb();
+        na::Vector3::new(
+            f64::from((rgb >> 16) & 0xff),
+            f64::from((rgb >> 8) & 0xff),
+            f64::from(rgb & 0xff),
+        ) / 255.
+    }
+}
+
+/// Names are matched without regard to case, like OpenSCAD does
+impl FromStr for ColorName {
+    type Err = ScadError;
+
+    fn from_str(name: &str) -> ScadResult<ColorName> {
+        ColorName::ALL
+            .iter()
+            .find(|color| color.name().eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or_else(|| ScadError::UnknownColor(name.to_string()))
+    }
+}
+
+impl fmt::Display for ColorName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/**
+  A colour in any of the ways OpenSCAD can take it, with conversions between them.
+
+  Colours are turned into `ScadElement`s with `into()`, RGB colours become
+  `Color`, RGBA colours `ColorAlpha` and the others `NamedColor`.
+
+  ```
+  # use scad::*;
+  let orange: ScadColor = "#ff8800cc".parse().unwrap();
+  assert_eq!(orange.to_rgba().unwrap().w, 0.8);
+  assert_eq!(ScadColor::from(ColorName::Teal).to_hex().unwrap(), "#008080");
+
+  scad!(orange.into(); scad!(Cube(vec3(1., 1., 1.))));
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScadColor {
+    Rgb(na::Vector3<f64>),
+    Rgba(na::Vector4<f64>),
+    Named(ColorName),
+    /// `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`
+    Hex(String),
+}
+
+impl ScadColor {
+    /// Checks that `hex` is a valid hex colour and wraps it
+    pub fn from_hex(hex: &str) -> ScadResult<ScadColor> {
+        parse_hex(hex)?;
+        Ok(ScadColor::Hex(hex.to_string()))
+    }
+
+    /// Returns the colour as RGBA components between 0 and 1
+    pub fn to_rgba(&self) -> ScadResult<na::Vector4<f64>> {
+        let rgba = match *self {
+            ScadColor::Rgb(ref rgb) => rgb.push(1.),
+            ScadColor::Rgba(ref rgba) => *rgba,
+            ScadColor::Named(name) => name.rgb().push(1.),
+            ScadColor::Hex(ref hex) => parse_hex(hex)?,
+        };
+        for &component in rgba.iter() {
+            check_component(component)?;
+        }
+        Ok(rgba)
+    }
+
+    /// Returns the colour as `#rrggbb`, or `#rrggbbaa` if it isn't opaque
+    pub fn to_hex(&self) -> ScadResult<String> {
+        let rgba = self.to_rgba()?;
+        let byte = |component: f64| (component * 255.).round() as u8;
+
+        let mut hex = format!(
+            "#{:02x}{:02x}{:02x}",
+            byte(rgba.x),
+            byte(rgba.y),
+            byte(rgba.z)
+        );
+        if byte(rgba.w) != 255 {
+            hex += &format!("{:02x}", byte(rgba.w));
+        }
+        Ok(hex)
+    }
+}
+
+/// Parses hex colours starting with `#` and colour names
+impl FromStr for ScadColor {
+    type Err = ScadError;
+
+    fn from_str(color: &str) -> ScadResult<ScadColor> {
+        if color.starts_with('#') {
+            ScadColor::from_hex(color)
+        } else {
+            color.parse().map(ScadColor::Named)
+        }
+    }
+}
+
+impl From<na::Vector3<f64>> for ScadColor {
+    fn from(rgb: na::Vector3<f64>) -> ScadColor {
+        ScadColor::Rgb(rgb)
+    }
+}
+
+impl From<na::Vector4<f64>> for ScadColor {
+    fn from(rgba: na::Vector4<f64>) -> ScadColor {
+        ScadColor::Rgba(rgba)
+    }
+}
+
+impl From<ColorName> for ScadColor {
+    fn from(name: ColorName) -> ScadColor {
+        ScadColor::Named(name)
+    }
+}
+
+impl From<ScadColor> for ScadElement {
+    fn from(color: ScadColor) -> ScadElement {
+        match color {
+            ScadColor::Rgb(rgb) => ScadElement::Color(rgb),
+            ScadColor::Rgba(rgba) => ScadElement::ColorAlpha(rgba),
+            ScadColor::Named(name) => ScadElement::NamedColor(name.name().to_string()),
+            ScadColor::Hex(hex) => ScadElement::NamedColor(hex),
+        }
+    }
+}
+
+impl From<ColorName> for ScadElement {
+    fn from(name: ColorName) -> ScadElement {
+        ScadElement::NamedColor(name.name().to_string())
+    }
+}
+
+/// Colour components and alpha values have to be between 0 and 1
+pub(crate) fn check_component(value: f64) -> ScadResult {
+    if (0. ..=1.).contains(&value) {
+        Ok(())
+    } else {
+        Err(ScadError::InvalidColor(value))
+    }
+}
+
+/// Strings passed to `color` have to be a colour name or a hex colour
+pub(crate) fn check_color_string(color: &str) -> ScadResult {
+    color.parse::<ScadColor>().map(|_| ())
+}
+
+fn parse_hex(hex: &str) -> ScadResult<na::Vector4<f64>> {
+    let invalid = || ScadError::UnknownColor(hex.to_string());
+
+    let digits = hex.strip_prefix('#').ok_or_else(invalid)?;
+    let values = digits
+        .chars()
+        .map(|c| c.to_digit(16).ok_or_else(invalid))
+        .collect::<ScadResult<Vec<u32>>>()?;
+
+    let bytes: Vec<u32> = match values.len() {
+        3 | 4 => values.iter().map(|v| v * 17).collect(),
+        6 | 8 => values
+            .chunks(2)
+            .map(|pair| pair[0] * 16 + pair[1])
+            .collect(),
+        _ => return Err(invalid()),
+    };
+    let component = |i: usize| bytes.get(i).map_or(1., |&b| f64::from(b) / 255.);
+    Ok(na::Vector4::new(
+        component(0),
+        component(1),
+        component(2),
+        component(3),
+    ))
+}
+
+#[cfg(test)]
+mod color_tests {
+    use super::*;
+
+    #[test]
+    fn name_test() {
+        assert_eq!(ColorName::ALL.len(), 147);
+        assert_eq!(ColorName::Teal.name(), "teal");
+        assert_eq!(
+            ColorName::Teal.rgb(),
+            na::Vector3::new(0., 128. / 255., 128. / 255.)
+        );
+        assert_eq!(
+            "LightGoldenrodYellow".parse::<ColorName>().unwrap(),
+            ColorName::LightGoldenrodYellow
+        );
+        assert_eq!(ColorName::Gray.to_string(), "gray");
+
+        for name in ColorName::ALL {
+            assert_eq!(name.name().parse::<ColorName>().unwrap(), *name);
+        }
+
+        match "bleu".parse::<ColorName>() {
+            Err(ScadError::UnknownColor(name)) => assert_eq!(name, "bleu"),
+            _ => panic!("unknown colour name was accepted"),
+        }
+    }
+
+    #[test]
+    fn hex_test() {
+        let rgba = |hex: &str| hex.parse::<ScadColor>().unwrap().to_rgba().unwrap();
+        assert_eq!(
+            rgba("#ff8800cc"),
+            na::Vector4::new(1., 136. / 255., 0., 0.8)
+        );
+        assert_eq!(rgba("#FF8800"), na::Vector4::new(1., 136. / 255., 0., 1.));
+        assert_eq!(rgba("#f80c"), rgba("#ff8800cc"));
+        assert_eq!(rgba("#f80"), rgba("#ff8800"));
+
+        for invalid in [
+            "ff8800",
+            "#ff880",
+            "#ff8800c",
+            "#gg8800",
+            "#",
+            "#ff8800cc00",
+        ]
+        .iter()
+        {
+            assert!(
+                invalid.parse::<ScadColor>().is_err(),
+                "{} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn conversion_test() {
+        assert_eq!(
+            ScadColor::from(ColorName::Orange).to_hex().unwrap(),
+            "#ffa500"
+        );
+        assert_eq!(
+            ScadColor::from_hex("#F80C").unwrap().to_hex().unwrap(),
+            "#ff8800cc"
+        );
+        assert_eq!(
+            ScadColor::from(na::Vector4::new(0., 0., 1., 0.5))
+                .to_hex()
+                .unwrap(),
+            "#0000ff80"
+        );
+        assert_eq!(
+            ScadColor::from(na::Vector3::new(1., 1., 1.))
+                .to_rgba()
+                .unwrap(),
+            na::Vector4::new(1., 1., 1., 1.)
+        );
+
+        match ScadColor::Rgba(na::Vector4::new(0., 0., 0., 1.5)).to_hex() {
+            Err(ScadError::InvalidColor(value)) => assert_eq!(value, 1.5),
+            _ => panic!("alpha above 1 was accepted"),
+        }
+        assert!(ScadColor::Hex(String::from("red")).to_rgba().is_err());
+    }
+
+    #[test]
+    fn element_test() {
+        assert_eq!(
+            ScadElement::from(ScadColor::Rgb(na::Vector3::new(1., 0., 0.))),
+            ScadElement::Color(na::Vector3::new(1., 0., 0.))
+        );
+        assert_eq!(
+            ScadElement::from(ScadColor::Rgba(na::Vector4::new(1., 0., 0., 0.5))),
+            ScadElement::ColorAlpha(na::Vector4::new(1., 0., 0., 0.5))
+        );
+        assert_eq!(
+            ScadElement::from(ScadColor::Named(ColorName::Navy)),
+            ScadElement::NamedColor(String::from("navy"))
+        );
+        assert_eq!(
+            ScadElement::from("#abc".parse::<ScadColor>().unwrap()),
+            ScadElement::NamedColor(String::from("#abc"))
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::scad_format::FormatOptions;
+use crate::scad_type::{ScadType, ScadWriter};
+use nalgebra as na;
+use std::fmt;
+
+/**
+  Structural comparison where floating point values are allowed to differ
+  by up to `epsilon`. Everything else, like the structure of the tree or
+  integer parameters, has to match exactly.
+
+  ```
+  # use scad::*;
+  let a = scad!(Translate(dvec3(0.1 + 0.2, 0., 0.)); scad!(Cube(vec3(1., 1., 1.))));
+  let b = scad!(Translate(dvec3(0.3, 0., 0.)); scad!(Cube(vec3(1., 1., 1.))));
+
+  assert!(a != b);
+  assert!(a.approx_eq(&b, 1e-9));
+  ```
+*/
+pub trait ApproxEq {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool;
+}
+
+impl ApproxEq for f64 {
+    fn approx_eq(&self, other: &f64, epsilon: f64) -> bool {
+        self == other || (self - other).abs() <= epsilon
+    }
+}
+
+impl ApproxEq for f32 {
+    fn approx_eq(&self, other: &f32, epsilon: f64) -> bool {
+        f64::from(*self).approx_eq(&f64::from(*other), epsilon)
+    }
+}
+
+impl<T: ApproxEq + na::Scalar> ApproxEq for na::Vector3<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+impl<T: ApproxEq + na::Scalar> ApproxEq for na::Vector4<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+impl<T: ApproxEq + na::Scalar> ApproxEq for na::Vector2<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+impl<T: ApproxEq + na::Scalar> ApproxEq for na::Matrix4<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+impl<T: ApproxEq + na::Scalar> ApproxEq for na::Matrix3<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+impl<T: ApproxEq> ApproxEq for Option<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.approx_eq(b, epsilon),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: ApproxEq> ApproxEq for Vec<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/**
+  The first place where two trees differ, as returned by
+  `ScadObject::first_difference` and `ScadFile::first_difference`.
+
+  `path` has the same meaning as in `ScadError::At`, `left` and `right`
+  describe the differing part of each tree.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScadDiff {
+    pub path: Vec<usize>,
+    pub left: String,
+    pub right: String,
+}
+
+impl ScadDiff {
+    pub(crate) fn new(left: String, right: String) -> ScadDiff {
+        ScadDiff {
+            path: Vec::new(),
+            left,
+            right,
+        }
+    }
+
+    pub(crate) fn in_child(mut self, index: usize) -> ScadDiff {
+        self.path.insert(0, index);
+        self
+    }
+}
+
+impl fmt::Display for ScadDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "trees differ at the root")?;
+        } else {
+            write!(f, "trees differ in child ")?;
+            for (i, index) in self.path.iter().enumerate() {
+                if i != 0 {
+                    write!(f, "/")?;
+                }
+                write!(f, "{}", index)?;
+            }
+        }
+        write!(f, "\n  left:  {}\n  right: {}", self.left, self.right)
+    }
+}
+
+/// Describes a value for a `ScadDiff`. The scad code is the most readable
+/// description, it is written with enough precision to show small differences.
+/// Values that can't be written as code fall back to their `Debug` output
+pub(crate) fn describe<T: ScadType + fmt::Debug>(value: &T) -> String {
+    let options = FormatOptions {
+        precision: 15,
+        ..FormatOptions::compact()
+    };
+
+    let mut code = String::new();
+    match value.write_code(&mut ScadWriter::with_options(&mut code, options)) {
+        Ok(()) => code,
+        Err(_) => format!("{:?}", value),
+    }
+}
+
+#[cfg(test)]
+mod compare_tests {
+    use super::*;
+
+    #[test]
+    fn float_test() {
+        assert!(1.0f64.approx_eq(&1.05, 0.1));
+        assert!(!1.0f64.approx_eq(&1.2, 0.1));
+        assert!(f64::INFINITY.approx_eq(&f64::INFINITY, 0.));
+        assert!(!f64::NAN.approx_eq(&f64::NAN, 1.));
+        assert!(0.1f32.approx_eq(&0.100_001, 1e-5));
+    }
+
+    #[test]
+    fn vector_test() {
+        assert!(na::Vector3::new(1., 2., 3.).approx_eq(&na::Vector3::new(1., 2.001, 3.), 0.01));
+        assert!(!na::Vector2::new(1., 2.).approx_eq(&na::Vector2::new(1., 2.1), 0.01));
+        assert!(!vec![1., 2.].approx_eq(&vec![1.], 0.01));
+    }
+
+    #[test]
+    fn diff_display_test() {
+        let diff = ScadDiff::new(String::from("cube([1,1,1])"), String::from("cube([1,1,2])"))
+            .in_child(2)
+            .in_child(0);
+
+        assert_eq!(
+            diff.to_string(),
+            "trees differ in child 0/2\n  left:  cube([1,1,1])\n  right: cube([1,1,2])"
+        );
+    }
+}
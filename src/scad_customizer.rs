@@ -0,0 +1,386 @@
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_type::{write_identifier, ScadType, ScadWriter};
+use nalgebra as na;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/**
+  The value of a customizer variable. OpenSCAD shows numbers, booleans,
+  strings and vectors of up to 4 numbers in the Customizer.
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CustomizerValue {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    Vector(Vec<f64>),
+}
+
+impl ScadType for CustomizerValue {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            CustomizerValue::Number(value) => value.write_code(w),
+            CustomizerValue::Bool(value) => value.write_code(w),
+            CustomizerValue::Str(ref value) => value.write_code(w),
+            CustomizerValue::Vector(ref values) => {
+                w.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    value.write_code(w)?;
+                }
+                w.write_char(']')
+            }
+        }
+    }
+}
+
+/**
+  Types that can be the value of a customizer variable, see
+  `CustomizerVariable::new`. Members of `qstruct!` structs with these types
+  can be exported with the generated `customizer_variables` function.
+*/
+pub trait CustomizerType {
+    fn customizer_value(&self) -> CustomizerValue;
+}
+
+impl<T: CustomizerType + ?Sized> CustomizerType for &T {
+    fn customizer_value(&self) -> CustomizerValue {
+        (**self).customizer_value()
+    }
+}
+
+macro_rules! number_customizer_type {
+    ($($type:ty),*) => {
+        $(
+            impl CustomizerType for $type {
+                fn customizer_value(&self) -> CustomizerValue {
+                    CustomizerValue::Number(*self as f64)
+                }
+            }
+        )*
+    };
+}
+number_customizer_type!(f64, f32, i8, i16, i32, u8, u16, u32, usize);
+
+impl CustomizerType for bool {
+    fn customizer_value(&self) -> CustomizerValue {
+        CustomizerValue::Bool(*self)
+    }
+}
+
+impl CustomizerType for str {
+    fn customizer_value(&self) -> CustomizerValue {
+        CustomizerValue::Str(self.to_string())
+    }
+}
+
+impl CustomizerType for String {
+    fn customizer_value(&self) -> CustomizerValue {
+        CustomizerValue::Str(self.clone())
+    }
+}
+
+impl CustomizerType for na::Vector2<f64> {
+    fn customizer_value(&self) -> CustomizerValue {
+        CustomizerValue::Vector(self.iter().cloned().collect())
+    }
+}
+
+impl CustomizerType for na::Vector3<f64> {
+    fn customizer_value(&self) -> CustomizerValue {
+        CustomizerValue::Vector(self.iter().cloned().collect())
+    }
+}
+
+impl CustomizerType for na::Vector4<f64> {
+    fn customizer_value(&self) -> CustomizerValue {
+        CustomizerValue::Vector(self.iter().cloned().collect())
+    }
+}
+
+/**
+  How the Customizer lets the user change a variable. It's written as a
+  comment behind the assignment.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CustomizerWidget {
+    /// The default input for the type of the value
+    #[default]
+    Plain,
+    /// `// [min:max]` or `// [min:step:max]`, a slider for numbers
+    Slider {
+        min: f64,
+        step: Option<f64>,
+        max: f64,
+    },
+    /// `// step`, a spin box for numbers
+    Step(f64),
+    /// `// [10:Small, 20:Large]`, a dropdown of numbers with optional labels
+    Numbers(Vec<(f64, Option<String>)>),
+    /// `// [S:Small, L:Large]`, a dropdown of strings with optional labels
+    Strings(Vec<(String, Option<String>)>),
+    /// `// length`, a text box for strings of at most `length` characters
+    MaxLength(usize),
+}
+
+/// Checks that `text` can be written in a comment without ending it or
+/// changing the meaning of the annotation
+pub(crate) fn check_comment_text(text: &str, forbidden: &[char]) -> ScadResult {
+    if text.contains(['\n', '\r']) || text.contains(forbidden) || text.contains("*/") {
+        return Err(ScadError::InvalidCustomizerText(text.to_string()));
+    }
+    Ok(())
+}
+
+/// Writes `value:label` for dropdown options
+fn write_label(w: &mut ScadWriter, label: &Option<String>) -> ScadResult {
+    if let Some(ref label) = *label {
+        check_comment_text(label, &[',', ':', '[', ']'])?;
+        write!(w, ":{}", label)?;
+    }
+    Ok(())
+}
+
+impl ScadType for CustomizerWidget {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            CustomizerWidget::Plain => Ok(()),
+            CustomizerWidget::Slider { min, step, max } => {
+                w.write_char('[')?;
+                min.write_code(w)?;
+                if let Some(step) = step {
+                    w.write_char(':')?;
+                    step.write_code(w)?;
+                }
+                w.write_char(':')?;
+                max.write_code(w)?;
+                w.write_char(']')
+            }
+            CustomizerWidget::Step(step) => step.write_code(w),
+            CustomizerWidget::Numbers(ref options) => {
+                w.write_char('[')?;
+                for (i, (value, label)) in options.iter().enumerate() {
+                    if i != 0 {
+                        w.write_str(", ")?;
+                    }
+                    value.write_code(w)?;
+                    write_label(w, label)?;
+                }
+                w.write_char(']')
+            }
+            CustomizerWidget::Strings(ref options) => {
+                w.write_char('[')?;
+                for (i, (value, label)) in options.iter().enumerate() {
+                    if i != 0 {
+                        w.write_str(", ")?;
+                    }
+                    check_comment_text(value, &[',', ':', '[', ']'])?;
+                    w.write_str(value)?;
+                    write_label(w, label)?;
+                }
+                w.write_char(']')
+            }
+            CustomizerWidget::MaxLength(length) => length.write_code(w),
+        }
+    }
+}
+
+/**
+  A top level variable that can be changed in the Customizer of OpenSCAD,
+  added to a file with `ScadFile::add_customizer_variable`. Objects refer to
+  it with `reference`, for example as an argument of a `ScadCall`.
+
+  The description is written as a comment above the assignment and the
+  variables are grouped in the Customizer by their tab. Variables in the tab
+  `Hidden` are not shown.
+
+  ```
+  # use scad::*;
+  let width = CustomizerVariable::new("width", &20.)
+      .range(10., 100.)
+      .description("Outer width of the box")
+      .tab("Dimensions");
+  assert_eq!(width.get_code(), "// Outer width of the box\nwidth=20; // [10:100]");
+  assert_eq!(width.reference().get_code(), "width");
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CustomizerVariable {
+    pub name: String,
+    pub value: CustomizerValue,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub widget: CustomizerWidget,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tab: Option<String>,
+}
+
+impl CustomizerVariable {
+    pub fn new<T: CustomizerType + ?Sized>(name: &str, value: &T) -> CustomizerVariable {
+        CustomizerVariable {
+            name: name.to_string(),
+            value: value.customizer_value(),
+            widget: CustomizerWidget::Plain,
+            description: None,
+            tab: None,
+        }
+    }
+
+    pub fn widget(mut self, widget: CustomizerWidget) -> CustomizerVariable {
+        self.widget = widget;
+        self
+    }
+
+    /// Shows the variable as a slider from `min` to `max`
+    pub fn range(self, min: f64, max: f64) -> CustomizerVariable {
+        self.widget(CustomizerWidget::Slider {
+            min,
+            step: None,
+            max,
+        })
+    }
+
+    pub fn description(mut self, description: &str) -> CustomizerVariable {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn tab(mut self, tab: &str) -> CustomizerVariable {
+        self.tab = Some(tab.to_string());
+        self
+    }
+
+    /// A reference to the variable that can be used as a value in the code
+    pub fn reference(&self) -> ScadVariable {
+        ScadVariable(self.name.clone())
+    }
+}
+
+impl ScadType for CustomizerVariable {
+    /**
+      Writes the description, the assignment and the widget. The comments
+      always end at a line break, even if the `FormatOptions` are compact.
+    */
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        if let Some(ref description) = self.description {
+            check_comment_text(description, &[])?;
+            writeln!(w, "// {}", description)?;
+        }
+
+        write_identifier(w, &self.name)?;
+        w.write_char('=')?;
+        self.value.write_code(w)?;
+        w.write_char(';')?;
+
+        if self.widget != CustomizerWidget::Plain {
+            w.write_str(" // ")?;
+            self.widget.write_code(w)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+  A reference to a top level variable of the file, like a
+  `CustomizerVariable`. It's written as the name of the variable.
+
+  ```
+  # use scad::*;
+  let size = ScadVariable(String::from("size"));
+  assert_eq!(
+      scad!(Call(ScadCall::new("cube").arg(size).named("center", true))).get_code(),
+      "cube(size,center=true);"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScadVariable(pub String);
+
+impl ScadType for ScadVariable {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write_identifier(w, &self.0)
+    }
+}
+
+#[cfg(test)]
+mod customizer_tests {
+    use super::*;
+
+    #[test]
+    fn variable_test() {
+        assert_eq!(
+            CustomizerVariable::new("label", "Box").get_code(),
+            "label=\"Box\";"
+        );
+        assert_eq!(
+            CustomizerVariable::new("offset", &na::Vector3::new(1., 0., 2.5)).get_code(),
+            "offset=[1,0,2.5];"
+        );
+        assert_eq!(
+            CustomizerVariable::new("lid", &true)
+                .description("Print the lid as well")
+                .get_code(),
+            "// Print the lid as well\nlid=true;"
+        );
+
+        let widgets = vec![
+            (
+                CustomizerWidget::Slider {
+                    min: 0.,
+                    step: Some(0.5),
+                    max: 10.,
+                },
+                "[0:0.5:10]",
+            ),
+            (CustomizerWidget::Step(0.1), "0.1"),
+            (
+                CustomizerWidget::Numbers(vec![(3., Some(String::from("M3"))), (4., None)]),
+                "[3:M3, 4]",
+            ),
+            (
+                CustomizerWidget::Strings(vec![
+                    (String::from("S"), Some(String::from("Small"))),
+                    (String::from("L"), Some(String::from("Large"))),
+                ]),
+                "[S:Small, L:Large]",
+            ),
+            (CustomizerWidget::MaxLength(8), "8"),
+        ];
+        for (widget, code) in widgets {
+            assert_eq!(
+                CustomizerVariable::new("x", &3u8).widget(widget).get_code(),
+                format!("x=3; // {}", code)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_variable_test() {
+        let invalid = vec![
+            CustomizerVariable::new("x", &1).description("two\nlines"),
+            CustomizerVariable::new("x", &1).widget(CustomizerWidget::Numbers(vec![(
+                1.,
+                Some(String::from("a, b")),
+            )])),
+            CustomizerVariable::new("x", "a")
+                .widget(CustomizerWidget::Strings(vec![(String::from("]"), None)])),
+        ];
+        for variable in invalid {
+            match variable.try_get_code() {
+                Err(ScadError::InvalidCustomizerText(_)) => {}
+                _ => panic!("{:?} was accepted", variable),
+            }
+        }
+
+        assert!(CustomizerVariable::new("2x", &1).try_get_code().is_err());
+        assert!(ScadVariable(String::from("a b")).try_get_code().is_err());
+    }
+}
@@ -2,14 +2,25 @@ use nalgebra as na;
 use std::string::*;
 use std::vec::Vec;
 
+use crate::scad_color::{check_color_string, check_component};
+use crate::scad_compare::ApproxEq;
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_expr::ScadExpr;
+use crate::scad_format::OpenScadVersion;
+use crate::scad_import::ImportParams;
+use crate::scad_library::ScadCall;
 use crate::scad_type::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /// Since scad allows creation of circle like objects using either radius or diameter,
 /// this enum specifies which format to use
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CircleType {
-    Radius(f32),
-    Diameter(f32),
+    Radius(f64),
+    Diameter(f64),
 }
 
 /////////////////////////////////////////////////////////////////////////////
@@ -18,13 +29,31 @@ pub enum CircleType {
 ///
 /// These are in a struct because  there are so many of them and
 /// most of them  can have a default value.
-#[derive(Clone)]
+///
+/// `scale` is the size of the top relative to the bottom along x and y, use
+/// the same value twice for uniform tapering. `v` extrudes along a direction
+/// instead of the z axis, and `segments` adds points along the edges of the
+/// shape for smoother twists. Both of them need an OpenSCAD snapshot.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct LinExtrudeParams {
-    pub height: f32,
+    pub height: f64,
     pub center: bool,
     pub convexity: i32,
-    pub twist: f32,
+    pub twist: f64,
     pub slices: i32,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_unscaled"))]
+    pub scale: na::Vector2<f64>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub v: Option<na::Vector3<f64>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub segments: Option<u64>,
+}
+
+#[cfg(feature = "serde")]
+fn is_unscaled(scale: &na::Vector2<f64>) -> bool {
+    *scale == na::Vector2::new(1., 1.)
 }
 
 impl Default for LinExtrudeParams {
@@ -35,32 +64,62 @@ impl Default for LinExtrudeParams {
             convexity: 10,
             twist: 0.,
             slices: 1,
+            scale: na::Vector2::new(1., 1.),
+            v: None,
+            segments: None,
         }
     }
 }
 
 impl ScadType for LinExtrudeParams {
-    fn get_code(&self) -> String {
-        String::from("height=")
-            + &self.height.get_code()
-            + ",center="
-            + &self.center.get_code()
-            + ",convexity="
-            + &self.convexity.get_code()
-            + ",twist="
-            + &self.twist.get_code()
-            + ",slices="
-            + &self.slices.get_code()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_str("height=")?;
+        w.argument("height", &self.height)?;
+        if self.v.is_some() || w.has_expression("v") {
+            w.require("linear_extrude with v", OpenScadVersion::Snapshot)?;
+            w.write_str(",v=")?;
+            w.argument("v", &self.v)?;
+        }
+        w.write_str(",center=")?;
+        w.argument("center", &self.center)?;
+        w.write_str(",convexity=")?;
+        w.argument("convexity", &self.convexity)?;
+        w.write_str(",twist=")?;
+        w.argument("twist", &self.twist)?;
+        w.write_str(",slices=")?;
+        w.argument("slices", &self.slices)?;
+        if self.segments.is_some() || w.has_expression("segments") {
+            w.require("linear_extrude with segments", OpenScadVersion::Snapshot)?;
+            w.write_str(",segments=")?;
+            w.argument("segments", &self.segments)?;
+        }
+        if self.scale.x != self.scale.y {
+            w.write_str(",scale=")?;
+            w.argument("scale", &self.scale)?;
+        } else if self.scale.x != 1. || w.has_expression("scale") {
+            w.write_str(",scale=")?;
+            w.argument("scale", &self.scale.x)?;
+        }
+        Ok(())
     }
 }
 
 /////////////////////////////////////////////////////////////////////////////
 
 /// Parameters for the rotate extrude function
-#[derive(Clone)]
+///
+/// `angle` needs OpenSCAD 2019.05, older versions always do a full revolution.
+/// `start` is the angle that the revolution starts at and needs an OpenSCAD
+/// snapshot. Without it the revolution starts at 180° for full revolutions
+/// and at 0° otherwise.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct RotateExtrudeParams {
-    pub angle: f32,
+    pub angle: f64,
     pub convexity: usize,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub start: Option<f64>,
 }
 
 impl Default for RotateExtrudeParams {
@@ -68,44 +127,83 @@ impl Default for RotateExtrudeParams {
         RotateExtrudeParams {
             angle: 360.,
             convexity: 10,
+            start: None,
         }
     }
 }
 
 impl ScadType for RotateExtrudeParams {
-    fn get_code(&self) -> String {
-        String::from("angle=") + &self.angle.get_code() + ",convexity=" + &self.convexity.get_code()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        let angle = self.angle != 360. || w.has_expression("angle");
+        if angle || w.options().target >= OpenScadVersion::V2019_05 {
+            w.require("rotate_extrude with angle", OpenScadVersion::V2019_05)?;
+            w.write_str("angle=")?;
+            w.argument("angle", &self.angle)?;
+            w.write_char(',')?;
+        }
+        if self.start.is_some() || w.has_expression("start") {
+            w.require("rotate_extrude with start", OpenScadVersion::Snapshot)?;
+            w.write_str("start=")?;
+            w.argument("start", &self.start)?;
+            w.write_char(',')?;
+        }
+        w.write_str("convexity=")?;
+        w.argument("convexity", &self.convexity)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// How `ScadElement::Roof` builds the roof over a 2D shape
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RoofMethod {
+    /// Rounded roofs from the voronoi diagram of the shape, detail is
+    /// controlled by `$fn`, `$fa` and `$fs`
+    Voronoi,
+    /// Flat faces from the straight skeleton of the shape
+    Straight,
+}
+
+impl ScadType for RoofMethod {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            RoofMethod::Voronoi => w.write_str("\"voronoi\""),
+            RoofMethod::Straight => w.write_str("\"straight\""),
+        }
     }
 }
 /////////////////////////////////////////////////////////////////////////////
 /**
   Parameters for the polygon function.
 */
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 enum PolygonPathType {
     Default,
     SingleVector(Vec<usize>),
     MultipleVectors(Vec<Vec<usize>>),
 }
 impl ScadType for PolygonPathType {
-    fn get_code(&self) -> String {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
         match *self {
-            PolygonPathType::Default => String::from("undef"),
-            PolygonPathType::SingleVector(ref val) => val.get_code(),
-            PolygonPathType::MultipleVectors(ref val) => val.get_code(),
+            PolygonPathType::Default => w.write_str("undef"),
+            PolygonPathType::SingleVector(ref val) => val.write_code(w),
+            PolygonPathType::MultipleVectors(ref val) => val.write_code(w),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PolygonParameters {
-    points: Vec<na::Vector2<f32>>,
+    points: Vec<na::Vector2<f64>>,
     path: PolygonPathType,
     convexity: u64,
 }
 
 impl PolygonParameters {
-    pub fn new(points: Vec<na::Vector2<f32>>) -> PolygonParameters {
+    pub fn new(points: Vec<na::Vector2<f64>>) -> PolygonParameters {
         PolygonParameters {
             points,
             path: PolygonPathType::Default,
@@ -130,30 +228,307 @@ impl PolygonParameters {
 }
 
 impl ScadType for PolygonParameters {
-    fn get_code(&self) -> String {
-        String::from("points=")
-            + &self.points.get_code()
-            + ",paths="
-            + &self.path.get_code()
-            + ",convexity="
-            + &self.convexity.get_code()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_str("points=")?;
+        w.argument("points", &self.points)?;
+        w.write_str(",paths=")?;
+        w.argument("paths", &self.path)?;
+        w.write_str(",convexity=")?;
+        w.argument("convexity", &self.convexity)
     }
 }
 /////////////////////////////////////////////////////////////////////////////
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum OffsetType {
-    Delta(f32),
-    Radius(f32),
+    Delta(f64),
+    Radius(f64),
 }
 
 impl ScadType for OffsetType {
-    fn get_code(&self) -> String {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            OffsetType::Delta(val) => {
+                w.write_str("delta=")?;
+                w.argument("delta", &val)
+            }
+            OffsetType::Radius(val) => {
+                w.write_str("r=")?;
+                w.argument("r", &val)
+            }
+        }
+    }
+}
+/////////////////////////////////////////////////////////////////////////////
+
+/**
+  Parameters for the surface function, which turns a heightmap into a 3D object.
+
+  `file` is either a `.dat` file, which can be generated with `Heightmap`,
+  or a png image. Relative paths are relative to the scad file.
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SurfaceParams {
+    pub file: String,
+    pub center: bool,
+    pub invert: bool,
+    pub convexity: u64,
+}
+
+impl SurfaceParams {
+    pub fn new(file: &str) -> SurfaceParams {
+        SurfaceParams {
+            file: file.to_string(),
+            center: false,
+            invert: false,
+            convexity: 1,
+        }
+    }
+}
+
+impl ScadType for SurfaceParams {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_str("file=")?;
+        w.argument("file", &self.file)?;
+        w.write_str(",center=")?;
+        w.argument("center", &self.center)?;
+        w.write_str(",invert=")?;
+        w.argument("invert", &self.invert)?;
+        w.write_str(",convexity=")?;
+        w.argument("convexity", &self.convexity)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Horizontal alignment of text relative to the origin
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HorizontalAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl ScadType for HorizontalAlign {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            HorizontalAlign::Left => w.write_str("\"left\""),
+            HorizontalAlign::Center => w.write_str("\"center\""),
+            HorizontalAlign::Right => w.write_str("\"right\""),
+        }
+    }
+}
+
+/// Vertical alignment of text relative to the origin
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VerticalAlign {
+    Top,
+    Center,
+    Baseline,
+    Bottom,
+}
+
+impl ScadType for VerticalAlign {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            VerticalAlign::Top => w.write_str("\"top\""),
+            VerticalAlign::Center => w.write_str("\"center\""),
+            VerticalAlign::Baseline => w.write_str("\"baseline\""),
+            VerticalAlign::Bottom => w.write_str("\"bottom\""),
+        }
+    }
+}
+
+/// The direction in which text is laid out
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TextDirection {
+    LeftToRight,
+    RightToLeft,
+    TopToBottom,
+    BottomToTop,
+}
+
+impl ScadType for TextDirection {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            TextDirection::LeftToRight => w.write_str("\"ltr\""),
+            TextDirection::RightToLeft => w.write_str("\"rtl\""),
+            TextDirection::TopToBottom => w.write_str("\"ttb\""),
+            TextDirection::BottomToTop => w.write_str("\"btt\""),
+        }
+    }
+}
+
+/**
+  Parameters for the text function.
+
+  The defaults are the same as in OpenSCAD, so usually only the text and
+  a few other fields have to be set. The `$fn` of the curves of the letters
+  is set on the object with `ScadObject::with_detail`.
+
+  ```
+  # use scad::*;
+  let label = TextParams {
+      size: 5.,
+      halign: HorizontalAlign::Center,
+      ..TextParams::new("v1.2")
+  };
+
+  assert_eq!(
+      scad!(Text(label)).get_code(),
+      "text(\"v1.2\",size=5,halign=\"center\",valign=\"baseline\",spacing=1);"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextParams {
+    pub text: String,
+    pub size: f64,
+    /// Font name in fontconfig format, for example `"Liberation Sans:style=Bold"`.
+    /// The OpenSCAD default font is used if this is `None`.
+    pub font: Option<String>,
+    pub halign: HorizontalAlign,
+    pub valign: VerticalAlign,
+    pub spacing: f64,
+    /// The direction, language and script are detected from the text by
+    /// OpenSCAD if they are `None`
+    pub direction: Option<TextDirection>,
+    pub language: Option<String>,
+    pub script: Option<String>,
+}
+
+impl TextParams {
+    pub fn new(text: &str) -> TextParams {
+        TextParams {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for TextParams {
+    fn default() -> TextParams {
+        TextParams {
+            text: String::new(),
+            size: 10.,
+            font: None,
+            halign: HorizontalAlign::Left,
+            valign: VerticalAlign::Baseline,
+            spacing: 1.,
+            direction: None,
+            language: None,
+            script: None,
+        }
+    }
+}
+
+impl ScadType for TextParams {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.argument("text", &self.text)?;
+        w.write_str(",size=")?;
+        w.argument("size", &self.size)?;
+        if self.font.is_some() || w.has_expression("font") {
+            w.write_str(",font=")?;
+            w.argument("font", &self.font)?;
+        }
+        w.write_str(",halign=")?;
+        w.argument("halign", &self.halign)?;
+        w.write_str(",valign=")?;
+        w.argument("valign", &self.valign)?;
+        w.write_str(",spacing=")?;
+        w.argument("spacing", &self.spacing)?;
+        if self.direction.is_some() || w.has_expression("direction") {
+            w.write_str(",direction=")?;
+            w.argument("direction", &self.direction)?;
+        }
+        if self.language.is_some() || w.has_expression("language") {
+            w.write_str(",language=")?;
+            w.argument("language", &self.language)?;
+        }
+        if self.script.is_some() || w.has_expression("script") {
+            w.write_str(",script=")?;
+            w.argument("script", &self.script)?;
+        }
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/**
+  The values that a loop variable takes, for example in `intersection_for`.
+
+  ```
+  # use scad::*;
+  let steps = LoopValues::Range { start: 0., step: 60., end: 300. };
+  assert_eq!(
+      scad!(IntersectionFor(String::from("a"), steps)).get_code(),
+      "intersection_for(a=[0:60:300]);"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LoopValues {
+    /// `[start:step:end]`, `end` is included if the steps land on it
+    Range {
+        start: f64,
+        step: f64,
+        end: f64,
+    },
+    Numbers(Vec<f64>),
+    Vectors(Vec<na::Vector3<f64>>),
+    /// Any expression that evaluates to a vector or range, like
+    /// `ScadExpr::range(0, ScadExpr::var("n") - 1)`
+    Expression(ScadExpr),
+}
+
+impl ScadType for LoopValues {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            LoopValues::Range { start, step, end } => {
+                w.write_char('[')?;
+                start.write_code(w)?;
+                w.write_char(':')?;
+                step.write_code(w)?;
+                w.write_char(':')?;
+                end.write_code(w)?;
+                w.write_char(']')
+            }
+            LoopValues::Numbers(ref values) => values.write_code(w),
+            LoopValues::Vectors(ref values) => values.write_code(w),
+            LoopValues::Expression(ref expression) => expression.write_code(w),
+        }
+    }
+}
+
+/// Which children of a module call `children()` refers to
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ChildSelection {
+    All,
+    Index(usize),
+    Indices(Vec<usize>),
+    /// The children from the first to the second index, both included
+    Range(usize, usize),
+}
+
+impl ScadType for ChildSelection {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
         match *self {
-            OffsetType::Delta(val) => String::from("delta=") + &val.get_code(),
-            OffsetType::Radius(val) => String::from("r=") + &val.get_code(),
+            ChildSelection::All => Ok(()),
+            ChildSelection::Index(index) => index.write_code(w),
+            ChildSelection::Indices(ref indices) => indices.write_code(w),
+            ChildSelection::Range(first, last) => write!(w, "[{}:{}]", first, last),
         }
     }
 }
+
 /////////////////////////////////////////////////////////////////////////////
 
 /// Different kinds of scad modules and function. These are parameters
@@ -161,14 +536,18 @@ impl ScadType for OffsetType {
 ///
 /// Most of these have  the same name as the openscad counterparts so see
 /// their documentation for details
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ScadElement {
     //Transformation stuff
-    Translate(na::Vector3<f32>),
-    Scale(na::Vector3<f32>),
-    Resize(na::Vector3<f32>, bool),
-    Rotate(f32, na::Vector3<f32>),
-    Mirror(na::Vector3<f32>),
+    Translate(na::Vector3<f64>),
+    Scale(na::Vector3<f64>),
+    Resize(na::Vector3<f64>, bool),
+    Rotate(f64, na::Vector3<f64>),
+    Mirror(na::Vector3<f64>),
+    /// A homogeneous transformation matrix. Can also be created from the
+    /// nalgebra transformation types using `From`
+    MultMatrix(na::Matrix4<f64>),
     LinearExtrude(LinExtrudeParams),
     RotateExtrude(RotateExtrudeParams),
 
@@ -177,144 +556,647 @@ pub enum ScadElement {
     Hull,
     Intersection,
     Minkowski,
+    /// Renders the children with CGAL, even in preview, and caches the result.
+    /// The argument is the convexity
+    Render(u64),
+    /// The intersection of the children for every value of the loop variable
+    IntersectionFor(String, LoopValues),
+    /// The children of the current module call. Only useful in module definitions
+    Children(ChildSelection),
+    /// Calls any module with positional and named arguments, like one from a
+    /// library or one defined with `ScadFile::define_module`, see `ScadCall`
+    Call(ScadCall),
+
+    //Control flow. Conditions and values are OpenSCAD expressions, which can
+    //use the variables of the surrounding code
+    /// Repeats the children for every combination of the loop variables
+    For(Vec<(String, LoopValues)>),
+    If(ScadExpr),
+    /// The children are used if the condition of the `If` object right before
+    /// this one is false. Only valid directly after an `If`
+    Else,
+    Let(Vec<(String, ScadExpr)>),
+    /// Prints the values, arguments with a name are printed as `name = value`
+    Echo(Vec<(Option<String>, ScadExpr)>),
+    /// A condition and an optional message which is shown if it fails
+    Assert(ScadExpr, Option<ScadExpr>),
 
     //Object stuff
-    Cube(na::Vector3<f32>),
-    Cylinder(f32, CircleType),
+    Cube(na::Vector3<f64>),
+    Cylinder(f64, CircleType),
     Sphere(CircleType),
-    Cone(f32, CircleType, CircleType),
+    Cone(f64, CircleType, CircleType),
 
-    Polyhedron(Vec<na::Vector3<f32>>, Vec<Vec<i32>>),
+    Polyhedron(Vec<na::Vector3<f64>>, Vec<Vec<i32>>),
+    /// Imports a file by its path as written in the scad file
     Import(String),
+    /// Imports a file with extra options, see `ImportParams`
+    ImportFile(ImportParams),
+    Surface(SurfaceParams),
 
     //2D stuff
-    Square(na::Vector2<f32>),
+    Square(na::Vector2<f64>),
     Circle(CircleType),
     Polygon(PolygonParameters),
     Offset(OffsetType, bool),
     Projection(bool),
+    /// Extrudes a 2D shape into a roof with 45° slopes. Needs an OpenSCAD snapshot
+    Roof(RoofMethod),
+    /// Removes the holes from 2D shapes. Needs an OpenSCAD snapshot
+    Fill,
+    Text(TextParams),
 
-    Rotate2d(f32),
-    Translate2d(na::Vector2<f32>),
-    Scale2d(na::Vector2<f32>),
+    Rotate2d(f64),
+    Translate2d(na::Vector2<f64>),
+    Scale2d(na::Vector2<f64>),
+    /// A 2D homogeneous transformation matrix. OpenSCAD only has 4x4 matrices
+    /// so it is written as the equivalent 3D transformation
+    MultMatrix2d(na::Matrix3<f64>),
 
-    Color(na::Vector3<f32>),
+    Color(na::Vector3<f64>),
+    /// A colour with an alpha value as its last component
+    ColorAlpha(na::Vector4<f64>),
+    /// A colour name or a hex colour like `"#ff8800cc"`, see also `ScadColor`
     NamedColor(String),
+    NamedColorAlpha(String, f64),
+}
+
+impl CircleType {
+    /// Writes the size as a named argument, `r` or `d` followed by `suffix`
+    fn write_argument(&self, w: &mut ScadWriter, suffix: &str) -> ScadResult {
+        let (name, val) = match *self {
+            CircleType::Radius(val) => ("r", val),
+            CircleType::Diameter(val) => ("d", val),
+        };
+
+        write!(w, "{}{}=", name, suffix)?;
+        w.argument(&format!("{}{}", name, suffix), &val)
+    }
+}
+
+/// Inserts an identity z axis into a 2D homogeneous matrix
+#[rustfmt::skip]
+fn matrix_2d_to_3d(m: &na::Matrix3<f64>) -> na::Matrix4<f64> {
+    na::Matrix4::new(
+        m[(0, 0)], m[(0, 1)], 0., m[(0, 2)],
+        m[(1, 0)], m[(1, 1)], 0., m[(1, 2)],
+        0., 0., 1., 0.,
+        m[(2, 0)], m[(2, 1)], 0., m[(2, 2)],
+    )
+}
+
+impl From<na::Matrix4<f64>> for ScadElement {
+    fn from(matrix: na::Matrix4<f64>) -> ScadElement {
+        ScadElement::MultMatrix(matrix)
+    }
+}
+
+impl From<na::Isometry3<f64>> for ScadElement {
+    fn from(transform: na::Isometry3<f64>) -> ScadElement {
+        ScadElement::MultMatrix(transform.to_homogeneous())
+    }
+}
+
+impl From<na::Similarity3<f64>> for ScadElement {
+    fn from(transform: na::Similarity3<f64>) -> ScadElement {
+        ScadElement::MultMatrix(transform.to_homogeneous())
+    }
+}
+
+impl<C: na::TCategory> From<na::Transform<f64, C, 3>> for ScadElement {
+    fn from(transform: na::Transform<f64, C, 3>) -> ScadElement {
+        ScadElement::MultMatrix(transform.into_inner())
+    }
+}
+
+impl From<na::Matrix3<f64>> for ScadElement {
+    fn from(matrix: na::Matrix3<f64>) -> ScadElement {
+        ScadElement::MultMatrix2d(matrix)
+    }
+}
+
+impl From<na::Isometry2<f64>> for ScadElement {
+    fn from(transform: na::Isometry2<f64>) -> ScadElement {
+        ScadElement::MultMatrix2d(transform.to_homogeneous())
+    }
+}
+
+impl From<na::Similarity2<f64>> for ScadElement {
+    fn from(transform: na::Similarity2<f64>) -> ScadElement {
+        ScadElement::MultMatrix2d(transform.to_homogeneous())
+    }
+}
+
+impl<C: na::TCategory> From<na::Transform<f64, C, 2>> for ScadElement {
+    fn from(transform: na::Transform<f64, C, 2>) -> ScadElement {
+        ScadElement::MultMatrix2d(transform.into_inner())
+    }
 }
 
 impl ScadElement {
     /// Returns scad code for each of the elements
     pub fn get_code(self) -> String {
-        match self {
+        ScadType::get_code(&self)
+    }
+
+    /// Elements that can be centered with `ObjectParams::center`
+    pub fn accepts_center(&self) -> bool {
+        matches!(
+            *self,
+            ScadElement::Cube(_)
+                | ScadElement::Cylinder(..)
+                | ScadElement::Cone(..)
+                | ScadElement::Square(_)
+        )
+    }
+
+    /// Control flow elements aren't module calls and can't take `ObjectParams`
+    pub fn accepts_params(&self) -> bool {
+        self.keyword().is_none()
+    }
+
+    /// The keyword that control flow elements are written with
+    pub fn keyword(&self) -> Option<&'static str> {
+        match *self {
+            ScadElement::For(_) => Some("for"),
+            ScadElement::If(_) => Some("if"),
+            ScadElement::Else => Some("else"),
+            ScadElement::Let(_) => Some("let"),
+            ScadElement::Echo(_) => Some("echo"),
+            ScadElement::Assert(..) => Some("assert"),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the call of an element without arguments
+fn write_empty_call(w: &mut ScadWriter, module: &str) -> ScadResult {
+    w.begin_call(module)?;
+    w.end_call()
+}
+
+impl ScadType for ScadElement {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
             //Transformation things
-            ScadElement::Translate(value) => String::from("translate(") + &value.get_code() + ")",
-            ScadElement::Scale(value) => String::from("scale(") + &value.get_code() + ")",
-            ScadElement::Resize(vector, auto) => {
-                String::from("resize(") + &vector.get_code() + ", auto = " + &auto.get_code() + ")"
+            ScadElement::Translate(ref value) => {
+                w.begin_call("translate")?;
+                w.argument("v", value)?;
+                w.end_call()
+            }
+            ScadElement::Scale(ref value) => {
+                w.begin_call("scale")?;
+                w.argument("v", value)?;
+                w.end_call()
+            }
+            ScadElement::Resize(ref vector, auto) => {
+                w.begin_call("resize")?;
+                w.argument("newsize", vector)?;
+                w.write_str(", auto = ")?;
+                w.argument("auto", &auto)?;
+                w.end_call()
             }
-            ScadElement::Rotate(angle, vector) => {
-                String::from("rotate(") + &angle.get_code() + "," + &vector.get_code() + ")"
+            ScadElement::Rotate(angle, ref vector) => {
+                w.begin_call("rotate")?;
+                w.argument("a", &angle)?;
+                w.write_str(",")?;
+                w.argument("v", vector)?;
+                w.end_call()
             }
-            ScadElement::Mirror(vector) => String::from("mirror(") + &vector.get_code() + ")",
-            ScadElement::LinearExtrude(params) => {
-                String::from("linear_extrude(") + &params.get_code() + ")"
+            ScadElement::Mirror(ref vector) => {
+                w.begin_call("mirror")?;
+                w.argument("v", vector)?;
+                w.end_call()
             }
-            ScadElement::RotateExtrude(params) => {
-                String::from("rotate_extrude(") + &params.get_code() + ")"
+            ScadElement::MultMatrix(ref matrix) => {
+                w.begin_call("multmatrix")?;
+                w.argument("m", matrix)?;
+                w.end_call()
+            }
+            ScadElement::LinearExtrude(ref params) => {
+                w.begin_call("linear_extrude")?;
+                params.write_code(w)?;
+                w.end_call()
+            }
+            ScadElement::RotateExtrude(ref params) => {
+                w.begin_call("rotate_extrude")?;
+                params.write_code(w)?;
+                w.end_call()
             }
 
             //Primitive objects
-            ScadElement::Cube(value) => String::from("cube(") + &value.get_code() + ")",
-            ScadElement::Cylinder(height, width) => {
-                let width_str = match width {
-                    CircleType::Radius(val) => String::from("r=") + &val.get_code(),
-                    CircleType::Diameter(val) => String::from("d=") + &val.get_code(),
-                };
-
-                String::from("cylinder(h=") + &height.get_code() + "," + &width_str + ")"
-            }
-            ScadElement::Sphere(size) => {
-                let size_str = match size {
-                    CircleType::Radius(val) => String::from("r=") + &val.get_code(),
-                    CircleType::Diameter(val) => String::from("d=") + &val.get_code(),
-                };
-
-                String::from("sphere(") + &size_str + ")"
-            }
-            ScadElement::Cone(height, size1, size2) => {
-                let size1_str = match size1 {
-                    CircleType::Radius(val) => String::from("r1=") + &val.get_code(),
-                    CircleType::Diameter(val) => String::from("d1=") + &val.get_code(),
-                };
-                let size2_str = match size2 {
-                    CircleType::Radius(val) => String::from("r2=") + &val.get_code(),
-                    CircleType::Diameter(val) => String::from("d2=") + &val.get_code(),
-                };
-
-                String::from("cylinder(h=")
-                    + &height.get_code()
-                    + ","
-                    + &size1_str
-                    + ","
-                    + &size2_str
-                    + ")"
-            }
-
-            ScadElement::Polyhedron(points, faces) => {
-                String::from("polyhedron(points=")
-                    + &points.get_code()
-                    + ",faces="
-                    + &faces.get_code()
-                    + ")"
-            }
-            ScadElement::Import(path) => String::from("import(") + &path.get_code() + ")",
+            ScadElement::Cube(ref value) => {
+                w.begin_call("cube")?;
+                w.argument("size", value)?;
+                w.end_call()
+            }
+            ScadElement::Cylinder(height, ref width) => {
+                w.begin_call("cylinder")?;
+                w.write_str("h=")?;
+                w.argument("h", &height)?;
+                w.write_str(",")?;
+                width.write_argument(w, "")?;
+                w.end_call()
+            }
+            ScadElement::Sphere(ref size) => {
+                w.begin_call("sphere")?;
+                size.write_argument(w, "")?;
+                w.end_call()
+            }
+            ScadElement::Cone(height, ref size1, ref size2) => {
+                w.begin_call("cylinder")?;
+                w.write_str("h=")?;
+                w.argument("h", &height)?;
+                w.write_str(",")?;
+                size1.write_argument(w, "1")?;
+                w.write_str(",")?;
+                size2.write_argument(w, "2")?;
+                w.end_call()
+            }
 
-            //primitive 2d objects
-            ScadElement::Square(value) => String::from("square(") + &value.get_code() + ")",
-            ScadElement::Circle(circle_type) => {
-                let size = match circle_type {
-                    CircleType::Radius(val) => String::from("r=") + &val.get_code(),
-                    CircleType::Diameter(val) => String::from("d=") + &val.get_code(),
-                };
+            ScadElement::Polyhedron(ref points, ref faces) => {
+                for (face, indices) in faces.iter().enumerate() {
+                    for &index in indices {
+                        if index < 0 || index as usize >= points.len() {
+                            return Err(ScadError::InvalidPolyhedronIndex {
+                                face,
+                                index,
+                                point_count: points.len(),
+                            });
+                        }
+                    }
+                }
 
-                String::from("circle(") + &size + ")"
+                w.begin_call("polyhedron")?;
+                w.write_str("points=")?;
+                w.argument("points", points)?;
+                w.write_str(",faces=")?;
+                w.argument("faces", faces)?;
+                w.end_call()
+            }
+            ScadElement::Import(ref path) => {
+                w.begin_call("import")?;
+                w.argument("file", path)?;
+                w.end_call()
+            }
+            ScadElement::ImportFile(ref params) => {
+                w.begin_call("import")?;
+                params.write_code(w)?;
+                w.end_call()
+            }
+            ScadElement::Surface(ref params) => {
+                w.begin_call("surface")?;
+                params.write_code(w)?;
+                w.end_call()
             }
 
-            ScadElement::Polygon(parameters) => {
-                String::from("polygon(") + &parameters.get_code() + ")"
+            //primitive 2d objects
+            ScadElement::Square(ref value) => {
+                w.begin_call("square")?;
+                w.argument("size", value)?;
+                w.end_call()
             }
-            ScadElement::Offset(offset_type, chamfer) => {
-                String::from("offset(")
-                    + &offset_type.get_code()
-                    + ",chamfer="
-                    + &chamfer.get_code()
-                    + ")"
+            ScadElement::Circle(ref circle_type) => {
+                w.begin_call("circle")?;
+                circle_type.write_argument(w, "")?;
+                w.end_call()
             }
 
-            ScadElement::Rotate2d(angle) => String::from("rotate(") + &angle.get_code() + ")",
-            ScadElement::Translate2d(position) => {
-                String::from("translate(") + &position.get_code() + ")"
+            ScadElement::Polygon(ref parameters) => {
+                w.begin_call("polygon")?;
+                parameters.write_code(w)?;
+                w.end_call()
+            }
+            ScadElement::Offset(ref offset_type, chamfer) => {
+                w.begin_call("offset")?;
+                offset_type.write_code(w)?;
+                w.write_str(",chamfer=")?;
+                w.argument("chamfer", &chamfer)?;
+                w.end_call()
+            }
+
+            ScadElement::Rotate2d(angle) => {
+                w.begin_call("rotate")?;
+                w.argument("a", &angle)?;
+                w.end_call()
+            }
+            ScadElement::Translate2d(ref position) => {
+                w.begin_call("translate")?;
+                w.argument("v", position)?;
+                w.end_call()
+            }
+            ScadElement::Scale2d(ref scale) => {
+                w.begin_call("scale")?;
+                w.argument("v", scale)?;
+                w.end_call()
+            }
+            ScadElement::MultMatrix2d(ref matrix) => {
+                w.begin_call("multmatrix")?;
+                w.argument("m", &matrix_2d_to_3d(matrix))?;
+                w.end_call()
+            }
+            ScadElement::Projection(cut) => {
+                w.begin_call("projection")?;
+                w.write_str("cut=")?;
+                w.argument("cut", &cut)?;
+                w.end_call()
+            }
+            ScadElement::Roof(method) => {
+                w.require("roof", OpenScadVersion::Snapshot)?;
+                w.begin_call("roof")?;
+                w.write_str("method=")?;
+                w.argument("method", &method)?;
+                w.end_call()
+            }
+            ScadElement::Fill => {
+                w.require("fill", OpenScadVersion::Snapshot)?;
+                write_empty_call(w, "fill")
+            }
+            ScadElement::Text(ref params) => {
+                w.begin_call("text")?;
+                params.write_code(w)?;
+                w.end_call()
             }
-            ScadElement::Scale2d(scale) => String::from("scale(") + &scale.get_code() + ")",
-            ScadElement::Projection(cut) => format!("projection(cut={})", cut),
 
             //Colors
-            ScadElement::Color(value) => {
+            ScadElement::Color(ref value) => {
                 //Ensure that this is a valid color
-                assert!(value.x >= 0. && value.x <= 1.);
-                assert!(value.y >= 0. && value.y <= 1.);
-                assert!(value.z >= 0. && value.z <= 1.);
+                for &component in value.iter() {
+                    check_component(component)?;
+                }
+
+                w.begin_call("color")?;
+                w.argument("c", value)?;
+                w.end_call()
+            }
+            ScadElement::ColorAlpha(ref value) => {
+                for &component in value.iter() {
+                    check_component(component)?;
+                }
+
+                w.begin_call("color")?;
+                w.argument("c", value)?;
+                w.end_call()
+            }
+            ScadElement::NamedColor(ref value) => {
+                check_color_string(value)?;
 
-                String::from("color(") + &value.get_code() + ")"
+                w.begin_call("color")?;
+                w.argument("c", value)?;
+                w.end_call()
+            }
+            ScadElement::NamedColorAlpha(ref value, alpha) => {
+                check_color_string(value)?;
+                check_component(alpha)?;
+
+                w.begin_call("color")?;
+                w.argument("c", value)?;
+                w.write_str(",alpha=")?;
+                w.argument("alpha", &alpha)?;
+                w.end_call()
             }
-            ScadElement::NamedColor(value) => String::from("color(") + &value.get_code() + ")",
 
             //Combination constructs
-            ScadElement::Difference => String::from("difference()"),
-            ScadElement::Union => String::from("union()"),
-            ScadElement::Hull => String::from("hull()"),
-            ScadElement::Minkowski => String::from("minkowski()"),
-            ScadElement::Intersection => String::from("intersection()"),
+            ScadElement::Difference => write_empty_call(w, "difference"),
+            ScadElement::Union => write_empty_call(w, "union"),
+            ScadElement::Hull => write_empty_call(w, "hull"),
+            ScadElement::Minkowski => write_empty_call(w, "minkowski"),
+            ScadElement::Intersection => write_empty_call(w, "intersection"),
+            ScadElement::Render(convexity) => {
+                w.begin_call("render")?;
+                w.write_str("convexity=")?;
+                w.argument("convexity", &convexity)?;
+                w.end_call()
+            }
+            ScadElement::IntersectionFor(ref variable, ref values) => {
+                w.begin_call("intersection_for")?;
+                write_identifier(w, variable)?;
+                w.write_str("=")?;
+                values.write_code(w)?;
+                w.end_call()
+            }
+            ScadElement::Children(ref selection) => {
+                w.begin_call("children")?;
+                w.argument("index", selection)?;
+                w.end_call()
+            }
+            ScadElement::Call(ref call) => call.write_code(w),
+
+            //Control flow
+            ScadElement::For(ref loops) => {
+                w.write_str("for(")?;
+                for (i, (variable, values)) in loops.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    write_identifier(w, variable)?;
+                    w.write_char('=')?;
+                    values.write_code(w)?;
+                }
+                w.write_str(")")
+            }
+            ScadElement::If(ref condition) => {
+                w.write_str("if(")?;
+                condition.write_code(w)?;
+                w.write_str(")")
+            }
+            ScadElement::Else => w.write_str("else"),
+            ScadElement::Let(ref assignments) => {
+                w.write_str("let(")?;
+                for (i, (variable, value)) in assignments.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    write_identifier(w, variable)?;
+                    w.write_char('=')?;
+                    value.write_code(w)?;
+                }
+                w.write_str(")")
+            }
+            ScadElement::Echo(ref values) => {
+                w.write_str("echo(")?;
+                for (i, (name, value)) in values.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    if let Some(ref name) = *name {
+                        write_identifier(w, name)?;
+                        w.write_char('=')?;
+                    }
+                    value.write_code(w)?;
+                }
+                w.write_str(")")
+            }
+            ScadElement::Assert(ref condition, ref message) => {
+                w.write_str("assert(")?;
+                condition.write_code(w)?;
+                if let Some(ref message) = *message {
+                    w.write_char(',')?;
+                    message.write_code(w)?;
+                }
+                w.write_str(")")
+            }
+        }
+    }
+}
+/////////////////////////////////////////////////////////////////////////////
+
+impl ApproxEq for CircleType {
+    fn approx_eq(&self, other: &CircleType, epsilon: f64) -> bool {
+        match (self, other) {
+            (CircleType::Radius(a), CircleType::Radius(b))
+            | (CircleType::Diameter(a), CircleType::Diameter(b)) => a.approx_eq(b, epsilon),
+            _ => false,
+        }
+    }
+}
+
+impl ApproxEq for LinExtrudeParams {
+    fn approx_eq(&self, other: &LinExtrudeParams, epsilon: f64) -> bool {
+        self.height.approx_eq(&other.height, epsilon)
+            && self.center == other.center
+            && self.convexity == other.convexity
+            && self.twist.approx_eq(&other.twist, epsilon)
+            && self.slices == other.slices
+            && self.scale.approx_eq(&other.scale, epsilon)
+            && self.v.approx_eq(&other.v, epsilon)
+            && self.segments == other.segments
+    }
+}
+
+impl ApproxEq for RotateExtrudeParams {
+    fn approx_eq(&self, other: &RotateExtrudeParams, epsilon: f64) -> bool {
+        self.angle.approx_eq(&other.angle, epsilon)
+            && self.convexity == other.convexity
+            && self.start.approx_eq(&other.start, epsilon)
+    }
+}
+
+impl ApproxEq for PolygonParameters {
+    fn approx_eq(&self, other: &PolygonParameters, epsilon: f64) -> bool {
+        self.points.approx_eq(&other.points, epsilon)
+            && self.path == other.path
+            && self.convexity == other.convexity
+    }
+}
+
+impl ApproxEq for OffsetType {
+    fn approx_eq(&self, other: &OffsetType, epsilon: f64) -> bool {
+        match (self, other) {
+            (OffsetType::Delta(a), OffsetType::Delta(b))
+            | (OffsetType::Radius(a), OffsetType::Radius(b)) => a.approx_eq(b, epsilon),
+            _ => false,
+        }
+    }
+}
+
+impl ApproxEq for TextParams {
+    fn approx_eq(&self, other: &TextParams, epsilon: f64) -> bool {
+        self.size.approx_eq(&other.size, epsilon)
+            && self.spacing.approx_eq(&other.spacing, epsilon)
+            && TextParams {
+                size: other.size,
+                spacing: other.spacing,
+                ..self.clone()
+            } == *other
+    }
+}
+
+impl ApproxEq for LoopValues {
+    fn approx_eq(&self, other: &LoopValues, epsilon: f64) -> bool {
+        match (self, other) {
+            (
+                LoopValues::Range { start, step, end },
+                LoopValues::Range {
+                    start: other_start,
+                    step: other_step,
+                    end: other_end,
+                },
+            ) => {
+                start.approx_eq(other_start, epsilon)
+                    && step.approx_eq(other_step, epsilon)
+                    && end.approx_eq(other_end, epsilon)
+            }
+            (LoopValues::Numbers(a), LoopValues::Numbers(b)) => a.approx_eq(b, epsilon),
+            (LoopValues::Vectors(a), LoopValues::Vectors(b)) => a.approx_eq(b, epsilon),
+            (LoopValues::Expression(a), LoopValues::Expression(b)) => a.approx_eq(b, epsilon),
+            _ => false,
+        }
+    }
+}
+
+impl ApproxEq for ScadElement {
+    fn approx_eq(&self, other: &ScadElement, epsilon: f64) -> bool {
+        use ScadElement::*;
+
+        match (self, other) {
+            (Translate(a), Translate(b))
+            | (Scale(a), Scale(b))
+            | (Mirror(a), Mirror(b))
+            | (Cube(a), Cube(b))
+            | (Color(a), Color(b)) => a.approx_eq(b, epsilon),
+            (Translate2d(a), Translate2d(b))
+            | (Scale2d(a), Scale2d(b))
+            | (Square(a), Square(b)) => a.approx_eq(b, epsilon),
+            (Rotate2d(a), Rotate2d(b)) => a.approx_eq(b, epsilon),
+            (ColorAlpha(a), ColorAlpha(b)) => a.approx_eq(b, epsilon),
+            (NamedColorAlpha(a, alpha_a), NamedColorAlpha(b, alpha_b)) => {
+                a == b && alpha_a.approx_eq(alpha_b, epsilon)
+            }
+            (MultMatrix(a), MultMatrix(b)) => a.approx_eq(b, epsilon),
+            (MultMatrix2d(a), MultMatrix2d(b)) => a.approx_eq(b, epsilon),
+            (Resize(a, auto_a), Resize(b, auto_b)) => auto_a == auto_b && a.approx_eq(b, epsilon),
+            (Rotate(angle_a, a), Rotate(angle_b, b)) => {
+                angle_a.approx_eq(angle_b, epsilon) && a.approx_eq(b, epsilon)
+            }
+            (LinearExtrude(a), LinearExtrude(b)) => a.approx_eq(b, epsilon),
+            (RotateExtrude(a), RotateExtrude(b)) => a.approx_eq(b, epsilon),
+            (Cylinder(height_a, a), Cylinder(height_b, b)) => {
+                height_a.approx_eq(height_b, epsilon) && a.approx_eq(b, epsilon)
+            }
+            (Sphere(a), Sphere(b)) | (Circle(a), Circle(b)) => a.approx_eq(b, epsilon),
+            (Cone(height_a, a1, a2), Cone(height_b, b1, b2)) => {
+                height_a.approx_eq(height_b, epsilon)
+                    && a1.approx_eq(b1, epsilon)
+                    && a2.approx_eq(b2, epsilon)
+            }
+            (Polyhedron(points_a, faces_a), Polyhedron(points_b, faces_b)) => {
+                faces_a == faces_b && points_a.approx_eq(points_b, epsilon)
+            }
+            (Polygon(a), Polygon(b)) => a.approx_eq(b, epsilon),
+            (Text(a), Text(b)) => a.approx_eq(b, epsilon),
+            (ImportFile(a), ImportFile(b)) => a.approx_eq(b, epsilon),
+            (Offset(a, chamfer_a), Offset(b, chamfer_b)) => {
+                chamfer_a == chamfer_b && a.approx_eq(b, epsilon)
+            }
+            (IntersectionFor(variable_a, a), IntersectionFor(variable_b, b)) => {
+                variable_a == variable_b && a.approx_eq(b, epsilon)
+            }
+            (Call(a), Call(b)) => a.approx_eq(b, epsilon),
+            (For(a), For(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|((variable_a, a), (variable_b, b))| {
+                        variable_a == variable_b && a.approx_eq(b, epsilon)
+                    })
+            }
+            (If(a), If(b)) => a.approx_eq(b, epsilon),
+            (Let(a), Let(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|((variable_a, a), (variable_b, b))| {
+                        variable_a == variable_b && a.approx_eq(b, epsilon)
+                    })
+            }
+            (Echo(a), Echo(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|((name_a, a), (name_b, b))| {
+                        name_a == name_b && a.approx_eq(b, epsilon)
+                    })
+            }
+            (Assert(a, message_a), Assert(b, message_b)) => {
+                a.approx_eq(b, epsilon) && message_a.approx_eq(message_b, epsilon)
+            }
+            //Everything else has no floating point parameters
+            _ => self == other,
         }
     }
 }
@@ -323,6 +1205,8 @@ impl ScadElement {
 #[cfg(test)]
 mod scad_tests {
     use super::*;
+    use crate::scad_format::FormatOptions;
+    use crate::scad_object::ScadObject;
 
     #[test]
     fn simple_enum_test() {
@@ -367,6 +1251,376 @@ mod scad_tests {
         );
     }
 
+    #[test]
+    fn render_test() {
+        assert_eq!(ScadElement::Render(4).get_code(), "render(convexity=4)");
+    }
+
+    #[test]
+    fn intersection_for_test() {
+        assert_eq!(
+            ScadElement::IntersectionFor(
+                String::from("i"),
+                LoopValues::Range {
+                    start: 0.,
+                    step: 1.,
+                    end: 3.,
+                }
+            )
+            .get_code(),
+            "intersection_for(i=[0:1:3])"
+        );
+        assert_eq!(
+            ScadElement::IntersectionFor(
+                String::from("$angle"),
+                LoopValues::Numbers(vec![0., 45.5])
+            )
+            .get_code(),
+            "intersection_for($angle=[0,45.5,])"
+        );
+        assert_eq!(
+            ScadElement::IntersectionFor(
+                String::from("rot"),
+                LoopValues::Vectors(vec![na::Vector3::new(0., 0., 90.)])
+            )
+            .get_code(),
+            "intersection_for(rot=[[0,0,90],])"
+        );
+
+        for name in ["", "2nd", "for", "a-b", "$"].iter() {
+            match ScadElement::IntersectionFor(name.to_string(), LoopValues::Numbers(vec![]))
+                .try_get_code()
+            {
+                Err(ScadError::InvalidIdentifier(value)) => assert_eq!(value, *name),
+                _ => panic!("{:?} should be rejected as a loop variable", name),
+            }
+        }
+    }
+
+    #[test]
+    fn children_test() {
+        assert_eq!(
+            ScadElement::Children(ChildSelection::All).get_code(),
+            "children()"
+        );
+        assert_eq!(
+            ScadElement::Children(ChildSelection::Index(2)).get_code(),
+            "children(2)"
+        );
+        assert_eq!(
+            ScadElement::Children(ChildSelection::Indices(vec![0, 2])).get_code(),
+            "children([0,2,])"
+        );
+        assert_eq!(
+            ScadElement::Children(ChildSelection::Range(1, 3)).get_code(),
+            "children([1:3])"
+        );
+    }
+
+    #[test]
+    fn control_flow_test() {
+        assert_eq!(
+            ScadElement::For(vec![
+                (
+                    String::from("x"),
+                    LoopValues::Range {
+                        start: -10.,
+                        step: 5.,
+                        end: 10.,
+                    }
+                ),
+                (String::from("y"), LoopValues::Numbers(vec![1., 2.])),
+            ])
+            .get_code(),
+            "for(x=[-10:5:10],y=[1,2,])"
+        );
+        let holes = ScadExpr::var("holes");
+        assert_eq!(
+            ScadElement::If(
+                ScadExpr::var("$preview").and(ScadExpr::call("len", vec![holes]).gt(0))
+            )
+            .get_code(),
+            "if($preview&&len(holes)>0)"
+        );
+        assert_eq!(ScadElement::Else.get_code(), "else");
+        let size = ScadExpr::var("size");
+        let wall = ScadExpr::var("wall");
+        assert_eq!(
+            ScadElement::Let(vec![
+                (String::from("wall"), 2.into()),
+                (String::from("inner"), size.clone() - wall.clone() * 2),
+            ])
+            .get_code(),
+            "let(wall=2,inner=size-wall*2)"
+        );
+        assert_eq!(
+            ScadElement::Echo(vec![
+                (None, "size".into()),
+                (Some(String::from("size")), size)
+            ])
+            .get_code(),
+            "echo(\"size\",size=size)"
+        );
+        assert_eq!(
+            ScadElement::Assert(wall.clone().gt(0), None).get_code(),
+            "assert(wall>0)"
+        );
+        assert_eq!(
+            ScadElement::Assert(wall.gt(0), Some("\"thin\" wall".into())).get_code(),
+            "assert(wall>0,\"\\\"thin\\\" wall\")"
+        );
+
+        match ScadElement::Let(vec![(String::from("if"), 1.into())]).try_get_code() {
+            Err(ScadError::InvalidIdentifier(name)) => assert_eq!(name, "if"),
+            _ => panic!("keywords can't be assigned to"),
+        }
+        match ScadElement::If(ScadExpr::Number(f64::NAN)).try_get_code() {
+            Err(ScadError::NonFiniteNumber(_)) => {}
+            _ => panic!("NaN was accepted in a condition"),
+        }
+    }
+
+    #[test]
+    fn multmatrix_test() {
+        let matrix = na::Matrix4::new(
+            1., 0., 0., 10., 0., 1., 0., 20., 0., 0., 1., 30., 0., 0., 0., 1.,
+        );
+        assert_eq!(
+            ScadElement::MultMatrix(matrix).get_code(),
+            "multmatrix([[1,0,0,10],[0,1,0,20],[0,0,1,30],[0,0,0,1]])"
+        );
+
+        let isometry = na::Isometry3::new(
+            na::Vector3::new(10., 20., 30.),
+            na::Vector3::new(0., 0., std::f64::consts::FRAC_PI_2),
+        );
+        assert_eq!(
+            ScadElement::from(isometry).get_code(),
+            "multmatrix([[0,-1,0,10],[1,0,0,20],[0,0,1,30],[0,0,0,1]])"
+        );
+        let similarity = na::Similarity3::from_isometry(isometry, 2.);
+        assert!(ScadElement::from(similarity).approx_eq(
+            &ScadElement::MultMatrix(na::Matrix4::new(
+                0., -2., 0., 10., 2., 0., 0., 20., 0., 0., 2., 30., 0., 0., 0., 1.
+            )),
+            1e-12
+        ));
+
+        let affine: na::Affine3<f64> = na::convert(na::Translation3::new(1., 2., 3.));
+        let projective: na::Projective3<f64> = na::convert(affine);
+        assert_eq!(
+            ScadElement::from(affine),
+            ScadElement::MultMatrix(na::Matrix4::new_translation(&na::Vector3::new(1., 2., 3.)))
+        );
+        assert_eq!(ScadElement::from(projective), ScadElement::from(affine));
+    }
+
+    #[test]
+    fn multmatrix_2d_test() {
+        let isometry = na::Isometry2::new(na::Vector2::new(5., -5.), std::f64::consts::PI);
+        assert_eq!(
+            ScadElement::from(isometry).get_code(),
+            "multmatrix([[-1,0,0,5],[0,-1,0,-5],[0,0,1,0],[0,0,0,1]])"
+        );
+
+        let skew = na::Matrix3::new(1., 0.5, 0., 0., 1., 0., 0., 0., 1.);
+        assert_eq!(ScadElement::from(skew), ScadElement::MultMatrix2d(skew));
+        assert_eq!(
+            ScadElement::MultMatrix2d(skew).get_code(),
+            "multmatrix([[1,0.5,0,0],[0,1,0,0],[0,0,1,0],[0,0,0,1]])"
+        );
+
+        let affine: na::Affine2<f64> =
+            na::convert(na::Similarity2::new(na::Vector2::new(1., 0.), 0., 3.));
+        assert_eq!(
+            ScadElement::from(affine).get_code(),
+            "multmatrix([[3,0,0,1],[0,3,0,0],[0,0,1,0],[0,0,0,1]])"
+        );
+    }
+
+    #[test]
+    fn surface_test() {
+        assert_eq!(
+            ScadElement::Surface(SurfaceParams::new("terrain.dat")).get_code(),
+            "surface(file=\"terrain.dat\",center=false,invert=false,convexity=1)"
+        );
+        assert_eq!(
+            ScadElement::Surface(SurfaceParams {
+                center: true,
+                invert: true,
+                convexity: 5,
+                ..SurfaceParams::new("maps\\logo.png")
+            })
+            .get_code(),
+            "surface(file=\"maps\\\\logo.png\",center=true,invert=true,convexity=5)"
+        );
+    }
+
+    #[test]
+    fn text_test() {
+        assert_eq!(
+            ScadElement::Text(TextParams::new("hi")).get_code(),
+            "text(\"hi\",size=10,halign=\"left\",valign=\"baseline\",spacing=1)"
+        );
+        assert_eq!(
+            ScadElement::Text(TextParams {
+                font: Some(String::from("Noto \"Sans\"")),
+                valign: VerticalAlign::Top,
+                direction: Some(TextDirection::RightToLeft),
+                ..TextParams::new("a\\b\n")
+            })
+            .get_code(),
+            "text(\"a\\\\b\\n\",size=10,font=\"Noto \\\"Sans\\\"\",halign=\"left\",valign=\"top\",spacing=1,direction=\"rtl\")"
+        );
+        assert!(ScadElement::Text(TextParams::new("nul\0"))
+            .try_get_code()
+            .is_err());
+    }
+
+    #[test]
+    fn precision_test() {
+        //Large dimensions with sub-micron features survive the trip through f64
+        assert_eq!(
+            ScadElement::Translate(na::Vector3::new(123_456.000_5, -0.000_1, 1e6 + 0.25))
+                .get_code(),
+            "translate([123456.0005,-0.0001,1000000.25])"
+        );
+    }
+
+    #[test]
+    fn invalid_element_test() {
+        match ScadElement::Color(na::Vector3::new(0.5, 1.5, 0.)).try_get_code() {
+            Err(ScadError::InvalidColor(value)) => assert_eq!(value, 1.5),
+            _ => panic!("colour components above 1 should be rejected"),
+        }
+        match ScadElement::Color(na::Vector3::new(0.5, -0.1, 0.)).try_get_code() {
+            Err(ScadError::InvalidColor(_)) => {}
+            _ => panic!("negative colour components should be rejected"),
+        }
+
+        let points = vec![na::Vector3::new(0., 0., 0.); 3];
+        assert!(ScadElement::Polyhedron(points.clone(), vec![vec![0, 1, 2]])
+            .try_get_code()
+            .is_ok());
+        match ScadElement::Polyhedron(points.clone(), vec![vec![0, 1, 2], vec![2, 3, 0]])
+            .try_get_code()
+        {
+            Err(ScadError::InvalidPolyhedronIndex {
+                face: 1,
+                index: 3,
+                point_count: 3,
+            }) => {}
+            _ => panic!("out of range index should be rejected"),
+        }
+        assert!(ScadElement::Polyhedron(points, vec![vec![-1, 1, 2]])
+            .try_get_code()
+            .is_err());
+    }
+
+    #[test]
+    fn color_alpha_test() {
+        assert_eq!(
+            ScadElement::ColorAlpha(na::Vector4::new(1., 0.5, 0., 0.25)).get_code(),
+            "color([1,0.5,0,0.25])"
+        );
+        assert_eq!(
+            ScadElement::NamedColorAlpha(String::from("Red"), 0.5).get_code(),
+            "color(\"Red\",alpha=0.5)"
+        );
+        assert_eq!(
+            ScadElement::NamedColor(String::from("#ff8800cc")).get_code(),
+            "color(\"#ff8800cc\")"
+        );
+
+        match ScadElement::ColorAlpha(na::Vector4::new(1., 0.5, 0., -1.)).try_get_code() {
+            Err(ScadError::InvalidColor(value)) => assert_eq!(value, -1.),
+            _ => panic!("negative alpha should be rejected"),
+        }
+        match ScadElement::NamedColorAlpha(String::from("red"), 2.).try_get_code() {
+            Err(ScadError::InvalidColor(value)) => assert_eq!(value, 2.),
+            _ => panic!("alpha above 1 should be rejected"),
+        }
+        match ScadElement::NamedColor(String::from("reddish")).try_get_code() {
+            Err(ScadError::UnknownColor(name)) => assert_eq!(name, "reddish"),
+            _ => panic!("unknown colour names should be rejected"),
+        }
+        assert!(ScadElement::NamedColor(String::from("#12345"))
+            .try_get_code()
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_color_panic_test() {
+        ScadElement::Color(na::Vector3::new(2., 0., 0.)).get_code();
+    }
+
+    #[test]
+    fn import_escape_test() {
+        use crate::scad_type::type_tests::unescape;
+
+        let paths = [
+            "C:\\Users\\me\\parts\\bracket.stl",
+            "\\\\server\\share\\\"quoted\".stl",
+            "weird\nname\t.stl",
+            "ünïcødé/ファイル.stl",
+            "ends with backslash\\",
+        ];
+
+        for path in paths.iter() {
+            let code = ScadElement::Import(path.to_string()).get_code();
+
+            assert!(code.starts_with("import(\"") && code.ends_with("\")"));
+            assert!(!code.contains('\n'));
+            assert_eq!(unescape(&code["import(".len()..code.len() - 1]), *path);
+        }
+
+        assert_eq!(
+            ScadElement::Import("C:\\parts\\a.stl".to_string()).get_code(),
+            "import(\"C:\\\\parts\\\\a.stl\")"
+        );
+    }
+
+    #[test]
+    fn approx_eq_test() {
+        let close = 1e-9;
+        assert!(
+            ScadElement::Translate(na::Vector3::new(0.1 + 0.2, 0., 0.)).approx_eq(
+                &ScadElement::Translate(na::Vector3::new(0.3, 0., 0.)),
+                close
+            )
+        );
+        assert!(
+            !ScadElement::Translate(na::Vector3::new(0.3, 0., 0.)).approx_eq(
+                &ScadElement::Translate(na::Vector3::new(0.31, 0., 0.)),
+                close
+            )
+        );
+        assert!(!ScadElement::Translate(na::Vector3::new(1., 0., 0.))
+            .approx_eq(&ScadElement::Scale(na::Vector3::new(1., 0., 0.)), close));
+        assert!(!ScadElement::Sphere(CircleType::Radius(1.))
+            .approx_eq(&ScadElement::Sphere(CircleType::Diameter(1.)), close));
+        assert!(
+            ScadElement::Cone(1., CircleType::Radius(1.0000001), CircleType::Diameter(2.))
+                .approx_eq(
+                    &ScadElement::Cone(1., CircleType::Radius(1.), CircleType::Diameter(2.)),
+                    1e-6
+                )
+        );
+        assert!(
+            !ScadElement::LinearExtrude(LinExtrudeParams::default()).approx_eq(
+                &ScadElement::LinearExtrude(LinExtrudeParams {
+                    slices: 2,
+                    ..Default::default()
+                }),
+                1.
+            )
+        );
+        assert!(ScadElement::NamedColor("red".to_string())
+            .approx_eq(&ScadElement::NamedColor("red".to_string()), 0.));
+        assert!(!ScadElement::Union.approx_eq(&ScadElement::Hull, 1.));
+    }
+
     #[test]
     fn lin_extrude_test() {
         assert_eq!(
@@ -384,6 +1638,48 @@ mod scad_tests {
         );
     }
 
+    #[test]
+    fn lin_extrude_scale_test() {
+        assert_eq!(
+            LinExtrudeParams {
+                scale: na::Vector2::new(0.5, 0.5),
+                ..Default::default()
+            }
+            .get_code(),
+            "height=1,center=false,convexity=10,twist=0,slices=1,scale=0.5"
+        );
+        assert_eq!(
+            LinExtrudeParams {
+                scale: na::Vector2::new(2., 0.),
+                ..Default::default()
+            }
+            .get_code(),
+            "height=1,center=false,convexity=10,twist=0,slices=1,scale=[2,0]"
+        );
+
+        let slanted = LinExtrudeParams {
+            v: Some(na::Vector3::new(1., 0., 1.)),
+            segments: Some(4),
+            ..Default::default()
+        };
+        match slanted.try_get_code() {
+            Err(ScadError::UnsupportedVersion {
+                feature, required, ..
+            }) => {
+                assert_eq!(feature, "linear_extrude with v");
+                assert_eq!(required, OpenScadVersion::Snapshot);
+            }
+            _ => panic!("v was accepted for OpenSCAD 2021.01"),
+        }
+        assert_eq!(
+            ScadObject::new(ScadElement::LinearExtrude(slanted)).get_code_with(&FormatOptions {
+                target: OpenScadVersion::Snapshot,
+                ..Default::default()
+            }),
+            "linear_extrude(height=1,v=[1,0,1],center=false,convexity=10,twist=0,slices=1,segments=4);"
+        );
+    }
+
     #[test]
     fn rotate_extrude_test() {
         let obj = ScadElement::RotateExtrude(Default::default());
@@ -391,6 +1687,66 @@ mod scad_tests {
         assert_eq!(obj.get_code(), "rotate_extrude(angle=360,convexity=10)");
     }
 
+    #[test]
+    fn rotate_extrude_version_test() {
+        let old = |target| FormatOptions {
+            target,
+            ..Default::default()
+        };
+        let code = |params: RotateExtrudeParams, target| {
+            ScadObject::new(ScadElement::RotateExtrude(params)).try_get_code_with(&old(target))
+        };
+
+        assert_eq!(
+            code(Default::default(), OpenScadVersion::V2015_03).unwrap(),
+            "rotate_extrude(convexity=10);"
+        );
+        let partial = RotateExtrudeParams {
+            angle: 90.,
+            ..Default::default()
+        };
+        assert!(code(partial.clone(), OpenScadVersion::V2015_03).is_err());
+        assert_eq!(
+            code(partial, OpenScadVersion::V2019_05).unwrap(),
+            "rotate_extrude(angle=90,convexity=10);"
+        );
+
+        let started = RotateExtrudeParams {
+            angle: 90.,
+            start: Some(45.),
+            ..Default::default()
+        };
+        assert!(code(started.clone(), OpenScadVersion::V2021_01).is_err());
+        assert_eq!(
+            code(started, OpenScadVersion::Snapshot).unwrap(),
+            "rotate_extrude(angle=90,start=45,convexity=10);"
+        );
+    }
+
+    #[test]
+    fn roof_fill_test() {
+        let snapshot = FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..Default::default()
+        };
+        let roof = ScadObject::new(ScadElement::Roof(RoofMethod::Straight));
+        assert_eq!(roof.get_code_with(&snapshot), "roof(method=\"straight\");");
+        assert_eq!(
+            ScadObject::new(ScadElement::Fill).get_code_with(&snapshot),
+            "fill();"
+        );
+
+        match ScadElement::Fill.try_get_code() {
+            Err(error) => assert_eq!(
+                error.to_string(),
+                "fill requires an OpenSCAD development snapshot or newer \
+                 but the code is generated for OpenSCAD 2021.01"
+            ),
+            Ok(_) => panic!("fill was accepted for OpenSCAD 2021.01"),
+        }
+        assert!(roof.try_get_code().is_err());
+    }
+
     #[test]
     fn polygon_parameter_type() {
         assert_eq!(
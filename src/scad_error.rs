@@ -0,0 +1,260 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::scad_format::OpenScadVersion;
+
+/**
+  Errors that can occur when generating scad code.
+
+  Errors caused by an object somewhere in a tree are wrapped in `ScadError::At`
+  which stores the path to the object that failed.
+*/
+#[derive(Debug)]
+pub enum ScadError {
+    /// A colour component outside of the range 0 to 1
+    InvalidColor(f64),
+    /// A string that is neither an OpenSCAD colour name nor a hex colour
+    UnknownColor(String),
+    /// NaN or infinite numbers, which OpenSCAD has no syntax for
+    NonFiniteNumber(f64),
+    /// A string that can not be written as an OpenSCAD string literal
+    InvalidString(String),
+    /// A variable name that is not a valid OpenSCAD identifier
+    InvalidIdentifier(String),
+    /// A polyhedron face refers to a point that does not exist
+    InvalidPolyhedronIndex {
+        face: usize,
+        index: i32,
+        point_count: usize,
+    },
+    /// A heightmap row with a different number of values than the first row
+    RaggedHeightmap {
+        row: usize,
+        length: usize,
+        expected: usize,
+    },
+    /// A heightmap name that isn't a plain file name, so the heightmap would
+    /// be written outside of the directory of the scad file
+    InvalidHeightmapName(String),
+    /// An `Else` object that doesn't directly follow an `If`, or has a modifier
+    MisplacedElse,
+    /// A file that should be imported doesn't exist, see `ImportParams::check_exists`
+    MissingImport(String),
+    /// A library path that can't be written in a `use` or `include` statement
+    InvalidLibraryPath(String),
+    /// A description, tab name or dropdown label that would end the comment
+    /// it's written in, see `CustomizerVariable`
+    InvalidCustomizerText(String),
+    /// An argument was set on an object whose module doesn't take it
+    UnsupportedArgument { module: String, argument: String },
+    /// An argument that is set both by the element and by the `ObjectParams`
+    /// of the object, for example a `$fn` argument of a `ScadCall` and `with_detail`
+    DuplicateArgument { module: String, argument: String },
+    /// An element or argument that the targeted OpenSCAD version doesn't support,
+    /// see `FormatOptions::target`
+    UnsupportedVersion {
+        feature: String,
+        required: OpenScadVersion,
+        target: OpenScadVersion,
+    },
+    /// Writing the generated code failed
+    Io(io::Error),
+    /// The `fmt::Write` that the code was written to failed
+    Format(fmt::Error),
+    /// The error was caused by the definition of the module called `module`
+    InModule {
+        module: String,
+        error: Box<ScadError>,
+    },
+    /**
+      The error was caused by a child object. `path` contains the index of the
+      child on each level starting from the object (or file) where generation
+      started.
+    */
+    At {
+        path: Vec<usize>,
+        error: Box<ScadError>,
+    },
+}
+
+pub type ScadResult<T = ()> = Result<T, ScadError>;
+
+impl ScadError {
+    /// Marks the error as caused by the child at `index`
+    pub fn in_child(self, index: usize) -> ScadError {
+        match self {
+            ScadError::Io(_) | ScadError::Format(_) => self,
+            ScadError::At { mut path, error } => {
+                path.insert(0, index);
+                ScadError::At { path, error }
+            }
+            error => ScadError::At {
+                path: vec![index],
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Returns the error without the information about where it happened
+    pub fn root_cause(&self) -> &ScadError {
+        match *self {
+            ScadError::At { ref error, .. } | ScadError::InModule { ref error, .. } => {
+                error.root_cause()
+            }
+            ref error => error,
+        }
+    }
+}
+
+impl fmt::Display for ScadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScadError::InvalidColor(value) => write!(
+                f,
+                "colour component {} is outside of the range 0 to 1",
+                value
+            ),
+            ScadError::UnknownColor(ref value) => write!(
+                f,
+                "{:?} is neither an OpenSCAD colour name nor a hex colour",
+                value
+            ),
+            ScadError::NonFiniteNumber(value) => {
+                write!(f, "OpenSCAD can not represent the number {}", value)
+            }
+            ScadError::InvalidString(ref value) => write!(
+                f,
+                "OpenSCAD strings can not contain NUL characters: {:?}",
+                value
+            ),
+            ScadError::InvalidIdentifier(ref value) => {
+                write!(f, "{:?} is not a valid OpenSCAD identifier", value)
+            }
+            ScadError::InvalidPolyhedronIndex {
+                face,
+                index,
+                point_count,
+            } => write!(
+                f,
+                "polyhedron face {} refers to point {} but there are only {} points",
+                face, index, point_count
+            ),
+            ScadError::RaggedHeightmap {
+                row,
+                length,
+                expected,
+            } => write!(
+                f,
+                "heightmap row {} has {} values but the first row has {}",
+                row, length, expected
+            ),
+            ScadError::InvalidHeightmapName(ref name) => {
+                write!(f, "heightmap name {:?} is not a plain file name", name)
+            }
+            ScadError::MisplacedElse => write!(
+                f,
+                "else must directly follow an if and can not have a modifier"
+            ),
+            ScadError::MissingImport(ref file) => {
+                write!(f, "imported file {} does not exist", file)
+            }
+            ScadError::InvalidLibraryPath(ref path) => write!(
+                f,
+                "library path {:?} is empty or contains '>' or a line break",
+                path
+            ),
+            ScadError::InvalidCustomizerText(ref text) => {
+                write!(f, "{:?} can not be written in a customizer comment", text)
+            }
+            ScadError::UnsupportedArgument {
+                ref module,
+                ref argument,
+            } => write!(f, "{} does not take a {} argument", module, argument),
+            ScadError::DuplicateArgument {
+                ref module,
+                ref argument,
+            } => write!(f, "the {} argument of {} is set twice", argument, module),
+            ScadError::UnsupportedVersion {
+                ref feature,
+                required,
+                target,
+            } => write!(
+                f,
+                "{} requires {} or newer but the code is generated for {}",
+                feature, required, target
+            ),
+            ScadError::Io(ref e) => write!(f, "failed to write scad code: {}", e),
+            ScadError::Format(_) => write!(f, "failed to write scad code"),
+            ScadError::InModule {
+                ref module,
+                ref error,
+            } => write!(f, "in module {}: {}", module, error),
+            ScadError::At {
+                ref path,
+                ref error,
+            } => {
+                write!(f, "in child ")?;
+                for (i, index) in path.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, "/")?;
+                    }
+                    write!(f, "{}", index)?;
+                }
+                write!(f, ": {}", error)
+            }
+        }
+    }
+}
+
+impl Error for ScadError {
+    /// Errors that are already part of the message aren't returned as the
+    /// source, `root_cause` returns the error inside `At` and `InModule`
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ScadError::Format(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScadError {
+    fn from(e: io::Error) -> ScadError {
+        ScadError::Io(e)
+    }
+}
+
+impl From<fmt::Error> for ScadError {
+    fn from(e: fmt::Error) -> ScadError {
+        ScadError::Format(e)
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn path_test() {
+        let error = ScadError::InvalidColor(2.).in_child(3).in_child(0);
+
+        match error {
+            ScadError::At { ref path, .. } => assert_eq!(path, &vec![0, 3]),
+            _ => panic!("expected a path"),
+        }
+        assert_eq!(
+            error.to_string(),
+            "in child 0/3: colour component 2 is outside of the range 0 to 1"
+        );
+        assert!(error.source().is_none());
+        match *error.root_cause() {
+            ScadError::InvalidColor(value) => assert_eq!(value, 2.),
+            _ => panic!("wrong root cause"),
+        }
+
+        match ScadError::Format(fmt::Error).in_child(1) {
+            ScadError::Format(_) => {}
+            _ => panic!("write errors are not tied to an object"),
+        }
+    }
+}
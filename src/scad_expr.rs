@@ -0,0 +1,630 @@
+use crate::scad_compare::ApproxEq;
+use crate::scad_customizer::ScadVariable;
+use crate::scad_error::ScadResult;
+use crate::scad_format::OpenScadVersion;
+use crate::scad_type::{write_identifier, ScadType, ScadWriter};
+use nalgebra as na;
+
+use std::ops;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UnaryOp {
+    /// `-a`
+    Neg,
+    /// `!a`
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    /// `a^b`, written as `pow(a,b)` for OpenSCAD versions before 2021.01
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => OR,
+            BinaryOp::And => AND,
+            BinaryOp::Eq | BinaryOp::Ne => EQUALITY,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => COMPARISON,
+            BinaryOp::Add | BinaryOp::Sub => ADDITIVE,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => MULTIPLICATIVE,
+            BinaryOp::Pow => POWER,
+        }
+    }
+}
+
+//Precedence of the OpenSCAD operators, from loosest to tightest
+const UNKNOWN: u8 = 0;
+const TERNARY: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const ADDITIVE: u8 = 6;
+const MULTIPLICATIVE: u8 = 7;
+const UNARY: u8 = 8;
+const POWER: u8 = 9;
+const PRIMARY: u8 = 10;
+
+/**
+  An OpenSCAD expression. Expressions keep the relationships between values
+  in the generated code, so a change of one variable in OpenSCAD updates
+  everything that depends on it.
+
+  Expressions are built from literals, variables and the arithmetic
+  operators of rust, and written with as few parentheses as possible.
+  They are used as the arguments of `ScadCall`, the values of `LoopValues`
+  and through `ScadObject::with_expression` for the arguments of any element.
+
+  Elements themselves only take concrete values. `with_expression` replaces
+  the value of an argument by its OpenSCAD name, so the element still needs
+  a valid placeholder value for it. Optional arguments are written when they
+  have an expression. Numbers, strings, `Option`s and nalgebra vectors and
+  matrices convert into expressions with `into`.
+
+  ```
+  # use scad::*;
+  let wall = ScadExpr::var("wall");
+  let height = ScadExpr::var("height");
+
+  let lid = ScadExpr::vec3(0., 0., wall.clone() + height / 2.);
+  assert_eq!(lid.get_code(), "[0,0,wall+height/2]");
+  assert_eq!(((wall.clone() + 1.) * 2.).get_code(), "(wall+1)*2");
+  assert_eq!(
+      ScadExpr::ternary(wall.clone().gt(3.), ScadExpr::call("sqrt", vec![wall]), 0.into())
+          .get_code(),
+      "wall>3?sqrt(wall):0"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScadExpr {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    Undef,
+    Variable(String),
+    Vector(Vec<ScadExpr>),
+    /// `[start:step:end]`, the step is 1 if it's `None`
+    Range(Box<ScadExpr>, Option<Box<ScadExpr>>, Box<ScadExpr>),
+    Unary(UnaryOp, Box<ScadExpr>),
+    Binary(BinaryOp, Box<ScadExpr>, Box<ScadExpr>),
+    /// `condition ? value : otherwise`
+    Ternary(Box<ScadExpr>, Box<ScadExpr>, Box<ScadExpr>),
+    /// A call of a builtin function like `sin` or `norm`, or a function from
+    /// a library
+    Call(String, Vec<ScadExpr>),
+    /// `vector[index]`
+    Index(Box<ScadExpr>, Box<ScadExpr>),
+    /// OpenSCAD code that is written as it is, for expressions that the other
+    /// variants can't represent, like list comprehensions. The parser uses it
+    /// for those. It's put in parentheses when it's an operand
+    Code(String),
+}
+
+impl ScadExpr {
+    pub fn var(name: &str) -> ScadExpr {
+        ScadExpr::Variable(name.to_string())
+    }
+
+    pub fn vec2<X: Into<ScadExpr>, Y: Into<ScadExpr>>(x: X, y: Y) -> ScadExpr {
+        ScadExpr::Vector(vec![x.into(), y.into()])
+    }
+
+    pub fn vec3<X, Y, Z>(x: X, y: Y, z: Z) -> ScadExpr
+    where
+        X: Into<ScadExpr>,
+        Y: Into<ScadExpr>,
+        Z: Into<ScadExpr>,
+    {
+        ScadExpr::Vector(vec![x.into(), y.into(), z.into()])
+    }
+
+    pub fn range<S: Into<ScadExpr>, E: Into<ScadExpr>>(start: S, end: E) -> ScadExpr {
+        ScadExpr::Range(Box::new(start.into()), None, Box::new(end.into()))
+    }
+
+    pub fn call(function: &str, args: Vec<ScadExpr>) -> ScadExpr {
+        ScadExpr::Call(function.to_string(), args)
+    }
+
+    pub fn ternary(condition: ScadExpr, value: ScadExpr, otherwise: ScadExpr) -> ScadExpr {
+        ScadExpr::Ternary(Box::new(condition), Box::new(value), Box::new(otherwise))
+    }
+
+    pub fn binary<T: Into<ScadExpr>>(self, op: BinaryOp, other: T) -> ScadExpr {
+        ScadExpr::Binary(op, Box::new(self), Box::new(other.into()))
+    }
+
+    pub fn pow<T: Into<ScadExpr>>(self, exponent: T) -> ScadExpr {
+        self.binary(BinaryOp::Pow, exponent)
+    }
+
+    pub fn lt<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Lt, other)
+    }
+
+    pub fn le<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Le, other)
+    }
+
+    pub fn gt<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Gt, other)
+    }
+
+    pub fn ge<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Ge, other)
+    }
+
+    /// `self == other` in OpenSCAD, `==` in rust compares the expressions
+    pub fn equals<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Eq, other)
+    }
+
+    pub fn not_equals<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Ne, other)
+    }
+
+    pub fn and<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::And, other)
+    }
+
+    pub fn or<T: Into<ScadExpr>>(self, other: T) -> ScadExpr {
+        self.binary(BinaryOp::Or, other)
+    }
+
+    pub fn index<T: Into<ScadExpr>>(self, index: T) -> ScadExpr {
+        ScadExpr::Index(Box::new(self), Box::new(index.into()))
+    }
+
+    fn precedence(&self) -> u8 {
+        match *self {
+            //Negative numbers are written with a unary minus
+            ScadExpr::Number(value) if value.is_sign_negative() => UNARY,
+            ScadExpr::Unary(..) => UNARY,
+            ScadExpr::Binary(op, ..) => op.precedence(),
+            ScadExpr::Ternary(..) => TERNARY,
+            ScadExpr::Code(_) => UNKNOWN,
+            _ => PRIMARY,
+        }
+    }
+
+    /// Writes the expression, in parentheses if it binds looser than `min`
+    fn write_operand(&self, w: &mut ScadWriter, min: u8) -> ScadResult {
+        if self.precedence() >= min {
+            return self.write_code(w);
+        }
+        w.write_char('(')?;
+        self.write_code(w)?;
+        w.write_char(')')
+    }
+}
+
+impl ScadType for ScadExpr {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            ScadExpr::Number(value) => value.write_code(w),
+            ScadExpr::Bool(value) => value.write_code(w),
+            ScadExpr::Str(ref value) => value.write_code(w),
+            ScadExpr::Undef => w.write_str("undef"),
+            ScadExpr::Variable(ref name) => write_identifier(w, name),
+            ScadExpr::Vector(ref values) => {
+                w.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    value.write_code(w)?;
+                }
+                w.write_char(']')
+            }
+            ScadExpr::Range(ref start, ref step, ref end) => {
+                w.write_char('[')?;
+                start.write_code(w)?;
+                if let Some(ref step) = *step {
+                    w.write_char(':')?;
+                    step.write_code(w)?;
+                }
+                w.write_char(':')?;
+                end.write_code(w)?;
+                w.write_char(']')
+            }
+            ScadExpr::Unary(op, ref operand) => {
+                w.write_char(match op {
+                    UnaryOp::Neg => '-',
+                    UnaryOp::Not => '!',
+                })?;
+                operand.write_operand(w, UNARY)
+            }
+            ScadExpr::Binary(BinaryOp::Pow, ref base, ref exponent)
+                if w.options().target < OpenScadVersion::V2021_01 =>
+            {
+                w.write_str("pow(")?;
+                base.write_code(w)?;
+                w.write_char(',')?;
+                exponent.write_code(w)?;
+                w.write_char(')')
+            }
+            ScadExpr::Binary(BinaryOp::Pow, ref base, ref exponent) => {
+                base.write_operand(w, PRIMARY)?;
+                w.write_char('^')?;
+                exponent.write_operand(w, UNARY)
+            }
+            ScadExpr::Binary(op, ref left, ref right) => {
+                left.write_operand(w, op.precedence())?;
+                w.write_str(op.symbol())?;
+                right.write_operand(w, op.precedence() + 1)
+            }
+            ScadExpr::Ternary(ref condition, ref value, ref otherwise) => {
+                condition.write_operand(w, TERNARY + 1)?;
+                w.write_char('?')?;
+                value.write_code(w)?;
+                w.write_char(':')?;
+                otherwise.write_code(w)
+            }
+            ScadExpr::Call(ref function, ref args) => {
+                write_identifier(w, function)?;
+                w.write_char('(')?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    arg.write_code(w)?;
+                }
+                w.write_char(')')
+            }
+            ScadExpr::Index(ref vector, ref index) => {
+                vector.write_operand(w, PRIMARY)?;
+                w.write_char('[')?;
+                index.write_code(w)?;
+                w.write_char(']')
+            }
+            ScadExpr::Code(ref code) => w.write_str(code),
+        }
+    }
+}
+
+impl ApproxEq for ScadExpr {
+    fn approx_eq(&self, other: &ScadExpr, epsilon: f64) -> bool {
+        match (self, other) {
+            (ScadExpr::Number(a), ScadExpr::Number(b)) => a.approx_eq(b, epsilon),
+            (ScadExpr::Vector(a), ScadExpr::Vector(b)) => a.approx_eq(b, epsilon),
+            (ScadExpr::Range(a0, a1, a2), ScadExpr::Range(b0, b1, b2)) => {
+                let steps_equal = match (a1, b1) {
+                    (Some(a), Some(b)) => a.approx_eq(b, epsilon),
+                    (None, None) => true,
+                    _ => false,
+                };
+                a0.approx_eq(b0, epsilon) && steps_equal && a2.approx_eq(b2, epsilon)
+            }
+            (ScadExpr::Unary(a_op, a), ScadExpr::Unary(b_op, b)) => {
+                a_op == b_op && a.approx_eq(b, epsilon)
+            }
+            (ScadExpr::Binary(a_op, a0, a1), ScadExpr::Binary(b_op, b0, b1)) => {
+                a_op == b_op && a0.approx_eq(b0, epsilon) && a1.approx_eq(b1, epsilon)
+            }
+            (ScadExpr::Ternary(a0, a1, a2), ScadExpr::Ternary(b0, b1, b2)) => {
+                a0.approx_eq(b0, epsilon) && a1.approx_eq(b1, epsilon) && a2.approx_eq(b2, epsilon)
+            }
+            (ScadExpr::Call(a_name, a), ScadExpr::Call(b_name, b)) => {
+                a_name == b_name && a.approx_eq(b, epsilon)
+            }
+            (ScadExpr::Index(a0, a1), ScadExpr::Index(b0, b1)) => {
+                a0.approx_eq(b0, epsilon) && a1.approx_eq(b1, epsilon)
+            }
+            _ => self == other,
+        }
+    }
+}
+
+macro_rules! number_expr {
+    ($($type:ty),*) => {
+        $(
+            impl From<$type> for ScadExpr {
+                fn from(value: $type) -> ScadExpr {
+                    ScadExpr::Number(f64::from(value))
+                }
+            }
+        )*
+    };
+}
+number_expr!(f64, f32, i8, i16, i32, u8, u16, u32);
+
+/// Integers that don't fit in an `f64` exactly are rounded like in OpenSCAD
+macro_rules! wide_number_expr {
+    ($($type:ty),*) => {
+        $(
+            impl From<$type> for ScadExpr {
+                fn from(value: $type) -> ScadExpr {
+                    ScadExpr::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+wide_number_expr!(i64, u64, isize, usize);
+
+impl From<bool> for ScadExpr {
+    fn from(value: bool) -> ScadExpr {
+        ScadExpr::Bool(value)
+    }
+}
+
+/// String literals, use `ScadExpr::var` for variables
+impl<'a> From<&'a str> for ScadExpr {
+    fn from(value: &'a str) -> ScadExpr {
+        ScadExpr::Str(value.to_string())
+    }
+}
+
+impl From<String> for ScadExpr {
+    fn from(value: String) -> ScadExpr {
+        ScadExpr::Str(value)
+    }
+}
+
+impl<T: Into<ScadExpr>> From<Vec<T>> for ScadExpr {
+    fn from(values: Vec<T>) -> ScadExpr {
+        ScadExpr::Vector(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// `None` is written as `undef`
+impl<T: Into<ScadExpr>> From<Option<T>> for ScadExpr {
+    fn from(value: Option<T>) -> ScadExpr {
+        value.map_or(ScadExpr::Undef, Into::into)
+    }
+}
+
+impl From<ScadVariable> for ScadExpr {
+    fn from(variable: ScadVariable) -> ScadExpr {
+        ScadExpr::Variable(variable.0)
+    }
+}
+
+/// Vectors like `na::Vector3` become a vector of numbers, matrices like
+/// `na::Matrix4` a vector of rows, the way `MultMatrix` writes them
+impl<const R: usize, const C: usize> From<na::SMatrix<f64, R, C>> for ScadExpr {
+    fn from(value: na::SMatrix<f64, R, C>) -> ScadExpr {
+        let number = |&x: &f64| ScadExpr::Number(x);
+        if C == 1 {
+            return ScadExpr::Vector(value.iter().map(number).collect());
+        }
+        ScadExpr::Vector(
+            value
+                .row_iter()
+                .map(|row| ScadExpr::Vector(row.iter().map(number).collect()))
+                .collect(),
+        )
+    }
+}
+
+macro_rules! binary_operator {
+    ($($trait:ident, $method:ident, $op:expr);*) => {
+        $(
+            impl<T: Into<ScadExpr>> ops::$trait<T> for ScadExpr {
+                type Output = ScadExpr;
+
+                fn $method(self, other: T) -> ScadExpr {
+                    self.binary($op, other)
+                }
+            }
+
+            impl ops::$trait<ScadExpr> for f64 {
+                type Output = ScadExpr;
+
+                fn $method(self, other: ScadExpr) -> ScadExpr {
+                    ScadExpr::from(self).binary($op, other)
+                }
+            }
+        )*
+    };
+}
+binary_operator!(
+    Add, add, BinaryOp::Add;
+    Sub, sub, BinaryOp::Sub;
+    Mul, mul, BinaryOp::Mul;
+    Div, div, BinaryOp::Div;
+    Rem, rem, BinaryOp::Mod
+);
+
+impl ops::Neg for ScadExpr {
+    type Output = ScadExpr;
+
+    fn neg(self) -> ScadExpr {
+        ScadExpr::Unary(UnaryOp::Neg, Box::new(self))
+    }
+}
+
+impl ops::Not for ScadExpr {
+    type Output = ScadExpr;
+
+    fn not(self) -> ScadExpr {
+        ScadExpr::Unary(UnaryOp::Not, Box::new(self))
+    }
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use super::*;
+    use crate::scad_format::FormatOptions;
+
+    fn var(name: &str) -> ScadExpr {
+        ScadExpr::var(name)
+    }
+
+    #[test]
+    fn precedence_test() {
+        let (a, b, c) = (var("a"), var("b"), var("c"));
+        let cases = vec![
+            (a.clone() + b.clone() * c.clone(), "a+b*c"),
+            ((a.clone() + b.clone()) * c.clone(), "(a+b)*c"),
+            (a.clone() - (b.clone() - c.clone()), "a-(b-c)"),
+            (a.clone() - b.clone() - c.clone(), "a-b-c"),
+            (a.clone() / (b.clone() * c.clone()), "a/(b*c)"),
+            (-(a.clone() + b.clone()), "-(a+b)"),
+            (-a.clone().pow(2), "-a^2"),
+            ((-a.clone()).pow(2), "(-a)^2"),
+            (ScadExpr::from(-2.).pow(b.clone()), "(-2)^b"),
+            (a.clone().pow(b.clone().pow(c.clone())), "a^b^c"),
+            (a.clone().pow(b.clone()).pow(c.clone()), "(a^b)^c"),
+            (a.clone().pow(-b.clone()), "a^-b"),
+            (a.clone() - -2., "a--2"),
+            (2. * a.clone() % 3, "2*a%3"),
+            (
+                a.clone()
+                    .lt(b.clone())
+                    .and(b.clone().le(c.clone()))
+                    .or(!a.clone()),
+                "a<b&&b<=c||!a",
+            ),
+            (
+                a.clone().or(b.clone()).and(c.clone().equals(1)),
+                "(a||b)&&c==1",
+            ),
+            (
+                ScadExpr::ternary(
+                    a.clone().ge(1),
+                    ScadExpr::ternary(b.clone(), 1.into(), 2.into()),
+                    ScadExpr::ternary(c.clone(), 3.into(), 4.into()),
+                ),
+                "a>=1?b?1:2:c?3:4",
+            ),
+            (
+                ScadExpr::ternary(
+                    ScadExpr::ternary(a.clone(), b.clone(), c.clone()),
+                    1.into(),
+                    2.into(),
+                ),
+                "(a?b:c)?1:2",
+            ),
+            (
+                ScadExpr::ternary(a.clone(), b.clone(), c.clone()) + 1,
+                "(a?b:c)+1",
+            ),
+            ((a.clone() + b.clone()).index(0), "(a+b)[0]"),
+            (a.clone().index(b.clone() - 1), "a[b-1]"),
+            (ScadExpr::Code(String::from("a ? b : c")), "a ? b : c"),
+            (-ScadExpr::Code(String::from("a + b")), "-(a + b)"),
+        ];
+        for (expr, code) in cases {
+            assert_eq!(expr.get_code(), code);
+        }
+    }
+
+    #[test]
+    fn literal_test() {
+        assert_eq!(
+            ScadExpr::Vector(vec![
+                1.5.into(),
+                true.into(),
+                "M3".into(),
+                ScadExpr::Undef,
+                na::Vector2::new(1., 2.).into(),
+                vec![1, 2].into(),
+            ])
+            .get_code(),
+            "[1.5,true,\"M3\",undef,[1,2],[1,2]]"
+        );
+        assert_eq!(ScadExpr::range(0, var("n") - 1).get_code(), "[0:n-1]");
+        assert_eq!(
+            ScadExpr::Range(
+                Box::new(0.into()),
+                Some(Box::new(0.5.into())),
+                Box::new(2.into())
+            )
+            .get_code(),
+            "[0:0.5:2]"
+        );
+        assert_eq!(
+            ScadExpr::call("max", vec![var("a"), ScadExpr::call("len", vec![var("v")])]).get_code(),
+            "max(a,len(v))"
+        );
+        assert_eq!(
+            ScadExpr::from(ScadVariable(String::from("width"))),
+            var("width")
+        );
+        assert_eq!(
+            ScadExpr::Vector(vec![
+                5u8.into(),
+                (-3i64).into(),
+                7usize.into(),
+                Some(2).into(),
+                None::<f64>.into(),
+                na::Vector4::new(1., 0., 0., 0.5).into(),
+            ])
+            .get_code(),
+            "[5,-3,7,2,undef,[1,0,0,0.5]]"
+        );
+        assert_eq!(
+            ScadExpr::from(na::Matrix2x3::new(1., 2., 3., 4., 5., 6.)).get_code(),
+            "[[1,2,3],[4,5,6]]"
+        );
+
+        assert!(ScadExpr::from(f64::NAN).try_get_code().is_err());
+        assert!(var("a b").try_get_code().is_err());
+        assert!(ScadExpr::call("2d", Vec::new()).try_get_code().is_err());
+    }
+
+    #[test]
+    fn pow_version_test() {
+        let expr = (var("a") + 1).pow(2);
+        assert_eq!(expr.get_code(), "(a+1)^2");
+        let old = FormatOptions {
+            target: OpenScadVersion::V2019_05,
+            ..Default::default()
+        };
+        let mut code = String::new();
+        expr.write_code(&mut ScadWriter::with_options(&mut code, old))
+            .unwrap();
+        assert_eq!(code, "pow(a+1,2)");
+    }
+
+    #[test]
+    fn approx_eq_test() {
+        let a = var("a") * 0.3;
+        assert!(a.approx_eq(&(var("a") * (0.1 + 0.2)), 1e-9));
+        assert!(!a.approx_eq(&(var("b") * 0.3), 1e-9));
+        assert!(!a.approx_eq(&(var("a") / 0.3), 1e-9));
+    }
+}
@@ -1,58 +1,680 @@
+use crate::scad_compare::{describe, ApproxEq, ScadDiff};
+use crate::scad_customizer::{check_comment_text, CustomizerVariable, CustomizerWidget};
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_expr::ScadExpr;
+use crate::scad_format::FormatOptions;
+use crate::scad_heightmap::Heightmap;
+use crate::scad_library::LibraryImport;
+use crate::scad_module::{deduplicate, ScadModule};
 use crate::scad_object::*;
-use crate::scad_type::ScadType;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use crate::scad_type::{write_identifier, ScadType, ScadWriter};
+use crate::scad_visit::{walk, ScadFold, ScadVisitor};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
 use std::string::String;
 use std::vec::Vec;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /**
   Object that stores scad objects along with global parameters for
   the objects. Also has methods for writing the  data to files.
 */
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "ScadFileData"))]
 pub struct ScadFile {
     objects: Vec<ScadObject>,
 
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    libraries: Vec<LibraryImport>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    customizer: Vec<CustomizerVariable>,
+
+    //Special variables like $fn that are set for the whole file
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "ObjectParams::is_empty")
+    )]
+    variables: ObjectParams,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    modules: Vec<ScadModule>,
+
+    //Data files that are written next to the scad file
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    heightmaps: Vec<(String, Heightmap)>,
+
+    //Only affects how the code looks so it isn't part of the model
+    #[cfg_attr(feature = "serde", serde(skip))]
+    format: FormatOptions,
+}
+
+/// The deserialized form of `ScadFile`. Also reads the `detail` that was
+/// written before the file had other special variables
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct ScadFileData {
+    objects: Vec<ScadObject>,
+    #[serde(default)]
+    libraries: Vec<LibraryImport>,
+    #[serde(default)]
+    customizer: Vec<CustomizerVariable>,
+    #[serde(default)]
+    variables: ObjectParams,
+    #[serde(default)]
+    modules: Vec<ScadModule>,
+    #[serde(default)]
+    heightmaps: Vec<(String, Heightmap)>,
+    #[serde(default)]
     detail: i32,
 }
 
+#[cfg(feature = "serde")]
+impl From<ScadFileData> for ScadFile {
+    fn from(data: ScadFileData) -> ScadFile {
+        let mut file = ScadFile {
+            objects: data.objects,
+            libraries: data.libraries,
+            customizer: data.customizer,
+            variables: data.variables,
+            modules: data.modules,
+            heightmaps: data.heightmaps,
+            format: FormatOptions::default(),
+        };
+        if file.variables.detail.is_none() {
+            file.set_detail(data.detail);
+        }
+        file
+    }
+}
+
 impl ScadFile {
     pub fn new() -> ScadFile {
         ScadFile {
             objects: Vec::new(),
 
-            detail: 0,
+            libraries: Vec::new(),
+
+            customizer: Vec::new(),
+
+            variables: ObjectParams::default(),
+
+            modules: Vec::new(),
+
+            heightmaps: Vec::new(),
+
+            format: FormatOptions::default(),
         }
     }
 
     /**
       Returns the code for the global parameters as well as all the
       children in the file
+
+      ## Panics
+      Panics if an object can't be represented in OpenSCAD, use
+      `try_get_code` to handle that case.
     */
     pub fn get_code(&self) -> String {
-        let mut result = String::from("");
+        self.try_get_code().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /**
+      Returns the code for the global parameters as well as all the
+      children in the file, or the reason why it can't be generated.
+    */
+    pub fn try_get_code(&self) -> ScadResult<String> {
+        let mut result = String::new();
+        self.write_code(&mut ScadWriter::with_options(&mut result, self.format.clone()))?;
+        Ok(result)
+    }
+
+    /**
+      Writes the code for the global parameters as well as all the
+      children in the file to `w`. Layout is decided by the options of `w`
+      rather than the ones set on the file.
 
-        if self.detail != 0 {
-            result = result + "$fn=" + &self.detail.to_string() + ";\n";
+      Errors are wrapped in `ScadError::At` where the first index of the
+      path is the index of the object in the file, or in `ScadError::InModule`
+      for errors in module definitions.
+    */
+    pub fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        //Customizer annotations are line comments, so they need a line break
+        //even in compact code
+        let comment = Cell::new(false);
+        let mut first = true;
+        let mut separate = |w: &mut ScadWriter| {
+            if first {
+                first = false;
+                Ok(())
+            } else if comment.replace(false) {
+                w.write_char('\n')
+            } else {
+                w.newline()
+            }
+        };
+
+        for library in &self.libraries {
+            separate(w)?;
+            library.write_code(w)?;
         }
 
-        for object in &self.objects {
-            result = result + &object.get_code() + "\n";
+        //The Customizer puts variables in the tab of the closest tab comment
+        //above them, so variables without a tab come first
+        let mut tabs = vec![None];
+        for variable in &self.customizer {
+            if !tabs.contains(&variable.tab.as_deref()) {
+                tabs.push(variable.tab.as_deref());
+            }
+        }
+        for tab in tabs {
+            let variables = self
+                .customizer
+                .iter()
+                .filter(|variable| variable.tab.as_deref() == tab);
+            for (i, variable) in variables.enumerate() {
+                if let (0, Some(tab)) = (i, tab) {
+                    //Tab comments have to be on their own line
+                    check_comment_text(tab, &[']'])?;
+                    comment.set(true);
+                    separate(w)?;
+                    write!(w, "/* [{}] */", tab)?;
+                    comment.set(true);
+                }
+                //Descriptions have to be on the line above the variable
+                if variable.description.is_some() {
+                    comment.set(true);
+                }
+                separate(w)?;
+                variable.write_code(w)?;
+                comment.set(variable.widget != CustomizerWidget::Plain);
+            }
+        }
+
+        let centered = self.variables.center.is_some()
+            || self
+                .variables
+                .expressions
+                .iter()
+                .any(|(name, _)| name == "center");
+        if centered {
+            return Err(ScadError::UnsupportedArgument {
+                module: String::from("file"),
+                argument: String::from("center"),
+            });
+        }
+        let mut open = false;
+        let expressions = self.variables.expressions.clone();
+        self.variables.write_each(w, expressions, |w, name| {
+            if open {
+                w.write_char(';')?;
+            }
+            open = true;
+            separate(w)?;
+            write_identifier(w, name)?;
+            w.write_char('=')
+        })?;
+        if open {
+            w.write_char(';')?;
         }
 
-        result
+        for module in &self.modules {
+            separate(w)?;
+            module.write_code(w).map_err(|e| match e {
+                ScadError::Io(_) | ScadError::Format(_) => e,
+                e => ScadError::InModule {
+                    module: module.name.clone(),
+                    error: Box::new(e),
+                },
+            })?;
+        }
+
+        for (i, object) in self.objects.iter().enumerate() {
+            separate(w)?;
+            check_else(i.checked_sub(1).map(|i| &self.objects[i]), object)
+                .map_err(|e| e.in_child(i))?;
+            object.write_code(w).map_err(|e| e.in_child(i))?;
+        }
+
+        if !first && w.options().final_newline {
+            w.write_char('\n')?;
+        }
+
+        Ok(())
+    }
+
+    /**
+      Streams the code for the file into `out` without building the whole
+      file in memory first.
+
+      If an object can't be represented in OpenSCAD, the code before it
+      will already have been written when the error is returned.
+    */
+    pub fn write_to<W: io::Write>(&self, out: W) -> ScadResult {
+        let mut adapter = IoAdapter {
+            inner: out,
+            error: None,
+        };
+
+        let result =
+            self.write_code(&mut ScadWriter::with_options(&mut adapter, self.format.clone()));
+        match (result, adapter.error) {
+            (_, Some(e)) => Err(ScadError::Io(e)),
+            (Err(e), None) => Err(e),
+            (Ok(()), None) => Ok(adapter.inner.flush()?),
+        }
     }
 
     pub fn add_object(&mut self, object: ScadObject) {
         self.objects.push(object);
     }
 
+    pub fn objects(&self) -> &[ScadObject] {
+        &self.objects
+    }
+
+    /// Mutable access to the top level objects of the file
+    pub fn objects_mut(&mut self) -> &mut Vec<ScadObject> {
+        &mut self.objects
+    }
+
+    /**
+      Adds `use <path>` to the top of the file, which makes the modules of
+      the library available to `ScadElement::Call`. Libraries are only added
+      once.
+
+      ```
+      # use scad::*;
+      let mut file = ScadFile::new();
+      file.use_library("BOSL2/std.scad");
+      file.add_object(scad!(Call(ScadCall::new("cuboid").arg(10).named("rounding", 2))));
+
+      assert_eq!(
+          file.get_code(),
+          "use <BOSL2/std.scad>\ncuboid(10,rounding=2);\n"
+      );
+      ```
+    */
+    pub fn use_library(&mut self, path: &str) {
+        self.add_library(LibraryImport::Use(path.to_string()));
+    }
+
+    /// Adds `include <path>` to the top of the file, see `LibraryImport::Include`
+    pub fn include_library(&mut self, path: &str) {
+        self.add_library(LibraryImport::Include(path.to_string()));
+    }
+
+    pub fn add_library(&mut self, library: LibraryImport) {
+        if !self.libraries.contains(&library) {
+            self.libraries.push(library);
+        }
+    }
+
+    pub fn libraries(&self) -> &[LibraryImport] {
+        &self.libraries
+    }
+
+    /**
+      Adds a variable that can be changed in the Customizer of OpenSCAD, see
+      `CustomizerVariable`. The variables are written at the top of the file,
+      after the libraries and grouped by their tab. Adding a variable with a
+      name that is already used replaces the old one.
+
+      ```
+      # use scad::*;
+      let mut file = ScadFile::new();
+      let width = CustomizerVariable::new("width", &20.).range(10., 50.);
+      let sides = CustomizerVariable::new("sides", &6)
+          .widget(CustomizerWidget::Numbers(vec![(4., None), (6., None), (8., None)]))
+          .description("Number of sides")
+          .tab("Shape");
+      file.add_object(scad!(Call(
+          ScadCall::new("cylinder")
+              .named("h", 10)
+              .named("d", width.reference())
+              .named("$fn", sides.reference())
+      )));
+      file.add_customizer_variable(sides);
+      file.add_customizer_variable(width);
+
+      assert_eq!(
+          file.get_code(),
+          "width=20; // [10:50]\n/* [Shape] */\n// Number of sides\nsides=6; // [4, 6, 8]\n\
+           cylinder(h=10,d=width,$fn=sides);\n"
+      );
+      ```
+    */
+    pub fn add_customizer_variable(&mut self, variable: CustomizerVariable) {
+        match self
+            .customizer
+            .iter_mut()
+            .find(|existing| existing.name == variable.name)
+        {
+            Some(existing) => *existing = variable,
+            None => self.customizer.push(variable),
+        }
+    }
+
+    /**
+      Adds all the variables, for example the ones from the
+      `customizer_variables` function of a `qstruct!` struct.
+    */
+    pub fn add_customizer_variables<I>(&mut self, variables: I)
+    where
+        I: IntoIterator<Item = CustomizerVariable>,
+    {
+        for variable in variables {
+            self.add_customizer_variable(variable);
+        }
+    }
+
+    pub fn customizer_variables(&self) -> &[CustomizerVariable] {
+        &self.customizer
+    }
+
+    /// Mutable access to the customizer variables, for example to change
+    /// their widgets after adding them
+    pub fn customizer_variables_mut(&mut self) -> &mut Vec<CustomizerVariable> {
+        &mut self.customizer
+    }
+
+    /**
+      Adds a module definition to the file, which objects can call with a
+      `ScadCall`. Parameters are a name and an optional default
+      value, see `ScadModule`. Defining a module with a name that is already
+      used replaces the old one.
+
+      ```
+      # use scad::*;
+      let mut file = ScadFile::new();
+      file.define_module(
+          "hole",
+          vec![(String::from("d"), Some(3.into()))],
+          vec![scad!(Cylinder(10., Radius(1.5)))],
+      );
+      file.add_object(scad!(Call(ScadCall::new("hole"))));
+
+      assert_eq!(
+          file.get_code(),
+          "module hole(d=3)\n{\n\tcylinder(h=10,r=1.5);\n}\nhole();\n"
+      );
+      ```
+    */
+    pub fn define_module(
+        &mut self,
+        name: &str,
+        params: Vec<(String, Option<ScadExpr>)>,
+        body: Vec<ScadObject>,
+    ) {
+        let module = ScadModule {
+            name: name.to_string(),
+            params,
+            body,
+        };
+        match self
+            .modules
+            .iter_mut()
+            .find(|existing| existing.name == name)
+        {
+            Some(existing) => *existing = module,
+            None => self.modules.push(module),
+        }
+    }
+
+    pub fn modules(&self) -> &[ScadModule] {
+        &self.modules
+    }
+
+    /// Mutable access to the module definitions of the file
+    pub fn modules_mut(&mut self) -> &mut Vec<ScadModule> {
+        &mut self.modules
+    }
+
+    /**
+      Moves subtrees that occur more than once into generated modules called
+      `shared_0`, `shared_1` and so on, and replaces them by calls. This makes
+      the code of files with many copies of the same part much shorter and
+      faster for OpenSCAD to parse.
+
+      Subtrees are equal if they generate the same code with the current
+      `FormatOptions`, so numbers that only differ after the rounding
+      precision are merged. Only subtrees with children are moved, and none
+      that contain control flow or `Children` since those could refer to the
+      scope they're in. For the same reason, calls with arguments, objects
+      with `ScadExpr` arguments and loops over expressions are left alone
+      inside loops and modules with parameters. Returns the number of
+      modules that were added.
+
+      ```
+      # use scad::*;
+      let hole = || scad!(Translate(vec3(5., 0., 0.)); scad!(Cylinder(2., Radius(1.))));
+
+      let mut file = ScadFile::new();
+      file.add_object(scad!(Difference; {
+          scad!(Cube(vec3(10., 10., 1.))),
+          hole(),
+          scad!(Rotate(90., vec3(0., 0., 1.)); hole()),
+      }));
+
+      assert_eq!(file.deduplicate(), 1);
+      assert_eq!(file.modules()[0].name, "shared_0");
+      assert_eq!(file.modules()[0].body, vec![hole()]);
+      ```
+    */
+    pub fn deduplicate(&mut self) -> usize {
+        deduplicate(&mut self.objects, &mut self.modules, &self.format)
+    }
+
+    /**
+      Adds a heightmap that `try_write_to_file` saves as `name` in the same
+      directory as the scad file, so that `surface` elements can refer to it
+      by that name. Adding a heightmap with a name that is already used
+      replaces the old one.
+
+      The name has to be a plain file name without directories, otherwise
+      writing the file fails with `ScadError::InvalidHeightmapName`.
+    */
+    pub fn add_heightmap(&mut self, name: &str, heightmap: Heightmap) {
+        match self
+            .heightmaps
+            .iter_mut()
+            .find(|(existing, _)| existing == name)
+        {
+            Some(existing) => existing.1 = heightmap,
+            None => self.heightmaps.push((name.to_string(), heightmap)),
+        }
+    }
+
+    pub fn heightmaps(&self) -> &[(String, Heightmap)] {
+        &self.heightmaps
+    }
+
+    /// Returns the $fn value of the file, 0 if it isn't set
+    pub fn get_detail(&self) -> i32 {
+        self.variables.detail.map_or(0, |detail| detail as i32)
+    }
+
     /**
       Sets the $fn variable for the whole file. This varibale defines  the detail
-      amount for cylindrical objects
+      amount for cylindrical objects. Values of 0 or less unset it.
     */
     pub fn set_detail(&mut self, detail: i32) {
-        self.detail = detail;
+        self.variables.detail = u32::try_from(detail).ok().filter(|&detail| detail != 0);
+    }
+
+    /// The special variables that are set for the whole file, see `variables_mut`
+    pub fn variables(&self) -> &ObjectParams {
+        &self.variables
+    }
+
+    /**
+      Mutable access to the special variables of the file. They are written
+      as assignments at the top of the file, after the libraries. `center`
+      can't be set for a file. `expressions` with other names are assigned
+      after the special variables, which defines values that the objects of
+      the file can refer to.
+
+      ```
+      # use scad::*;
+      let mut file = ScadFile::new();
+      file.set_detail(64);
+      file.variables_mut().viewport_distance = Some(140.);
+      file.variables_mut().preview = Some(Box::new(ObjectParams {
+          detail: Some(16),
+          ..Default::default()
+      }));
+      file.add_object(scad!(Sphere(Radius(20.))));
+
+      assert_eq!(
+          file.get_code(),
+          "$fn=$preview?16:64;\n$vpd=140;\nsphere(r=20);\n"
+      );
+      ```
+    */
+    pub fn variables_mut(&mut self) -> &mut ObjectParams {
+        &mut self.variables
+    }
+
+    /**
+      Walks over all objects in the file, see `ScadVisitor`. The bodies of
+      modules come first, then the top level objects, which have depth 0 and
+      their index in the file as the path.
+    */
+    pub fn visit<V: ScadVisitor + ?Sized>(&self, visitor: &mut V) {
+        for module in &self.modules {
+            if visitor.enter_module(module) {
+                for (i, object) in module.body.iter().enumerate() {
+                    walk(object, visitor, 0, &mut vec![i]);
+                }
+            }
+            visitor.leave_module(module);
+        }
+        for (i, object) in self.objects.iter().enumerate() {
+            walk(object, visitor, 0, &mut vec![i]);
+        }
+    }
+
+    /**
+      Rebuilds the objects in the file with `folder`, see `ScadFold`. The
+      modules are folded with `fold_module` and the top level objects with
+      `fold_children`.
+    */
+    pub fn fold<F: ScadFold + ?Sized>(mut self, folder: &mut F) -> ScadFile {
+        self.modules = self
+            .modules
+            .into_iter()
+            .map(|module| folder.fold_module(module))
+            .collect();
+        self.objects = folder.fold_children(self.objects);
+        self
+    }
+
+    /**
+      Compares the files like `approx_eq` and returns where they differ first.
+      The first index of the path is the index of the object in the file.
+    */
+    pub fn first_difference(&self, other: &ScadFile, epsilon: f64) -> Option<ScadDiff> {
+        if !self.variables.approx_eq(&other.variables, epsilon) {
+            return Some(ScadDiff::new(
+                describe(&self.variables),
+                describe(&other.variables),
+            ));
+        }
+
+        if self.libraries != other.libraries {
+            return Some(ScadDiff::new(
+                format!("libraries {:?}", self.libraries),
+                format!("libraries {:?}", other.libraries),
+            ));
+        }
+
+        for (a, b) in self.customizer.iter().zip(other.customizer.iter()) {
+            if a != b {
+                return Some(ScadDiff::new(describe(a), describe(b)));
+            }
+        }
+        if self.customizer.len() != other.customizer.len() {
+            return Some(ScadDiff::new(
+                format!("{} customizer variables", self.customizer.len()),
+                format!("{} customizer variables", other.customizer.len()),
+            ));
+        }
+
+        let module_names = |file: &ScadFile| {
+            let names: Vec<_> = file
+                .modules
+                .iter()
+                .map(|module| module.name.as_str())
+                .collect();
+            format!("modules {:?}", names)
+        };
+        if self.modules.len() != other.modules.len()
+            || self
+                .modules
+                .iter()
+                .zip(other.modules.iter())
+                .any(|(a, b)| !a.approx_eq(b, epsilon))
+        {
+            return Some(ScadDiff::new(module_names(self), module_names(other)));
+        }
+
+        for (i, (a, b)) in self.objects.iter().zip(other.objects.iter()).enumerate() {
+            if let Some(diff) = a.first_difference(b, epsilon) {
+                return Some(diff.in_child(i));
+            }
+        }
+        if self.objects.len() != other.objects.len() {
+            return Some(ScadDiff::new(
+                format!("{} objects", self.objects.len()),
+                format!("{} objects", other.objects.len()),
+            ));
+        }
+
+        let names = |file: &ScadFile| {
+            let names: Vec<_> = file
+                .heightmaps
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            format!("heightmaps {:?}", names)
+        };
+        if self.heightmaps.len() != other.heightmaps.len()
+            || self
+                .heightmaps
+                .iter()
+                .zip(other.heightmaps.iter())
+                .any(|(a, b)| a.0 != b.0 || !a.1.approx_eq(&b.1, epsilon))
+        {
+            return Some(ScadDiff::new(names(self), names(other)));
+        }
+
+        None
+    }
+
+    /**
+      Sets the layout used by `get_code`, `write_to` and `write_to_file`
+    */
+    pub fn set_format_options(&mut self, options: FormatOptions) {
+        self.format = options;
     }
 
     /**
@@ -64,28 +686,91 @@ impl ScadFile {
         working directory.
 
       ## Returns
-      The function will return false and print a message to the console if
-      writing fails.
+      The function will return false if writing fails. Nothing is printed,
+      use `try_write_to_file` to get the reason instead.
     */
     pub fn write_to_file(&self, path: String) -> bool {
-        //Writing the result to file
-        let path = Path::new(&path);
+        self.try_write_to_file(path).is_ok()
+    }
 
-        // Open a file in write-only mode, returns `io::Result<File>`
-        let mut file = match File::create(&path) {
-            Err(_) => {
-                println!("Couldn't open file for writing");
-                return false;
-            }
-            Ok(file) => file,
-        };
+    /**
+      Writes the resulting code to a file, returning the reason if the file
+      couldn't be created or an object can't be represented in OpenSCAD.
+      Heightmaps added with `add_heightmap` are written to the same directory,
+      and the paths of `ImportParams` are written relative to it.
 
-        if file.write(self.get_code().as_bytes()).is_err() {
-            println!("Failed to write to output file");
-            return false;
-        };
+      The code is generated before the file is created, so invalid objects
+      don't leave a partially written file behind.
+    */
+    pub fn try_write_to_file<P: AsRef<Path>>(&self, path: P) -> ScadResult {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut code = String::new();
+        let mut writer = ScadWriter::with_options(&mut code, self.format.clone());
+        writer.set_output_dir(directory);
+        self.write_code(&mut writer)?;
 
-        true
+        let data = self
+            .heightmaps
+            .iter()
+            .map(|(name, heightmap)| {
+                //Only plain file names, so the heightmap stays next to the file
+                let mut components = Path::new(name).components();
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(_)), None) => {}
+                    _ => return Err(ScadError::InvalidHeightmapName(name.clone())),
+                }
+                let mut data = String::new();
+                heightmap.write_code(&mut ScadWriter::with_options(
+                    &mut data,
+                    self.format.clone(),
+                ))?;
+                Ok((name, data))
+            })
+            .collect::<ScadResult<Vec<_>>>()?;
+
+        fs::write(path, code)?;
+        for (name, data) in data {
+            fs::write(directory.join(name), data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lets the code writers output to an `io::Write`. `fmt::Error` can't carry
+/// any information so the actual io error is stored for later
+struct IoAdapter<W: io::Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// Files are equal if they contain the same objects, libraries, customizer
+/// variables, modules, heightmaps and global parameters, the `FormatOptions`
+/// are not compared
+impl PartialEq for ScadFile {
+    fn eq(&self, other: &ScadFile) -> bool {
+        self.objects == other.objects
+            && self.libraries == other.libraries
+            && self.customizer == other.customizer
+            && self.modules == other.modules
+            && self.variables == other.variables
+            && self.heightmaps == other.heightmaps
+    }
+}
+
+impl ApproxEq for ScadFile {
+    fn approx_eq(&self, other: &ScadFile, epsilon: f64) -> bool {
+        self.first_difference(other, epsilon).is_none()
     }
 }
 
@@ -98,16 +783,21 @@ impl Default for ScadFile {
 #[cfg(test)]
 mod file_tests {
     use crate::scad_element::*;
+    use crate::scad_heightmap::Heightmap;
+    use crate::scad_import::ImportParams;
+    use crate::scad_library::ScadCall;
+    use nalgebra as na;
 
     use super::*;
     use std::fs;
     use std::fs::File;
+    use std::io::prelude::*;
 
     #[test]
     fn detail_test() {
         let mut sfile = ScadFile::new();
 
-        sfile.detail = 30;
+        sfile.set_detail(30);
 
         assert_eq!(sfile.get_code(), "$fn=30;\n");
 
@@ -120,11 +810,119 @@ mod file_tests {
         assert_eq!(sfile.get_code(), "$fn=30;\nunion();\ndifference();\n")
     }
 
+    #[test]
+    fn variables_test() {
+        let mut sfile = ScadFile::new();
+        sfile.set_detail(-3);
+        assert_eq!(sfile.get_detail(), 0);
+        assert_eq!(sfile.get_code(), "");
+
+        sfile.set_detail(48);
+        sfile.variables_mut().min_size = Some(0.5);
+        sfile.variables_mut().time = Some(0.);
+        sfile.variables_mut().viewport_translation = Some(na::Vector3::new(0., 0., 5.));
+        sfile.variables_mut().preview = Some(Box::new(ObjectParams {
+            detail: Some(12),
+            viewport_fov: Some(30.),
+            ..Default::default()
+        }));
+        sfile.use_library("lib.scad");
+        sfile.add_object(ScadObject::new(ScadElement::Union));
+        assert_eq!(sfile.get_detail(), 48);
+        assert_eq!(
+            sfile.get_code(),
+            "use <lib.scad>\n$fn=$preview?12:48;\n$fs=0.5;\n$t=0;\n$vpt=[0,0,5];\n$vpf=$preview?30:$vpf;\nunion();\n"
+        );
+
+        let mut other = sfile.clone();
+        other.variables_mut().time = Some(0.5);
+        assert_eq!(
+            sfile.first_difference(&other, 1e-9).unwrap().to_string(),
+            "trees differ at the root\n  left:  $fn=$preview?12:48,$fs=0.5,$t=0,$vpt=[0,0,5],$vpf=$preview?30:$vpf\n  right: $fn=$preview?12:48,$fs=0.5,$t=0.5,$vpt=[0,0,5],$vpf=$preview?30:$vpf"
+        );
+
+        sfile.variables_mut().center = Some(true);
+        match sfile.try_get_code() {
+            Err(ScadError::UnsupportedArgument { module, argument }) => {
+                assert_eq!(module, "file");
+                assert_eq!(argument, "center");
+            }
+            _ => panic!("center was accepted for a file"),
+        }
+    }
+
+    #[test]
+    fn expressions_test() {
+        let mut sfile = ScadFile::new();
+        let wall = CustomizerVariable::new("wall", &2.);
+        let height = CustomizerVariable::new("height", &10.);
+        let inner = ScadExpr::from(height.reference()) - 2. * ScadExpr::from(wall.reference());
+        sfile.add_customizer_variables(vec![wall, height]);
+        sfile.variables_mut().expressions = vec![
+            (String::from("inner"), inner),
+            (String::from("$fn"), ScadExpr::var("wall") * 8),
+        ];
+        sfile.add_object(
+            ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 0., 0.)))
+                .with_expression("v", ScadExpr::vec3(0, 0, ScadExpr::var("inner") / 2)),
+        );
+        assert_eq!(
+            sfile.get_code(),
+            "wall=2;\nheight=10;\n$fn=wall*8;\ninner=height-2*wall;\ntranslate([0,0,inner/2]);\n"
+        );
+
+        sfile.variables_mut().expressions = vec![(String::from("center"), true.into())];
+        assert!(sfile.try_get_code().is_err());
+    }
+
+    #[test]
+    fn customizer_test() {
+        use crate::scad_customizer::CustomizerVariable;
+
+        let mut sfile = ScadFile::new();
+        sfile.add_customizer_variable(CustomizerVariable::new("seed", &7).tab("Hidden"));
+        sfile.add_customizer_variable(CustomizerVariable::new("height", &5.).tab("Size"));
+        sfile.add_customizer_variable(
+            CustomizerVariable::new("name", "part").widget(CustomizerWidget::MaxLength(12)),
+        );
+        sfile.add_customizer_variable(
+            CustomizerVariable::new("width", &10.)
+                .range(1., 20.)
+                .tab("Size"),
+        );
+        sfile.set_detail(20);
+        sfile.add_object(ScadObject::new(ScadElement::Union));
+
+        assert_eq!(
+            sfile.get_code(),
+            "name=\"part\"; // 12\n/* [Hidden] */\nseed=7;\n/* [Size] */\nheight=5;\nwidth=10; // [1:20]\n$fn=20;\nunion();\n"
+        );
+        sfile.set_format_options(FormatOptions::compact());
+        assert_eq!(
+            sfile.get_code(),
+            "name=\"part\"; // 12\n/* [Hidden] */\nseed=7;\n/* [Size] */\nheight=5;width=10; // [1:20]\n$fn=20;union();"
+        );
+
+        let mut other = sfile.clone();
+        other.add_customizer_variable(CustomizerVariable::new("height", &6.).tab("Size"));
+        assert_eq!(other.customizer_variables().len(), 4);
+        assert_eq!(
+            sfile.first_difference(&other, 0.).unwrap().to_string(),
+            "trees differ at the root\n  left:  height=5;\n  right: height=6;"
+        );
+
+        other.customizer_variables_mut()[1].tab = Some(String::from("*/"));
+        match other.try_get_code() {
+            Err(ScadError::InvalidCustomizerText(text)) => assert_eq!(text, "*/"),
+            _ => panic!("invalid tab name was accepted"),
+        }
+    }
+
     #[test]
     fn file_test() {
         let mut sfile = ScadFile::new();
 
-        sfile.detail = 30;
+        sfile.set_detail(30);
 
         let write_success = sfile.write_to_file(String::from("test.scad"));
 
@@ -152,12 +950,412 @@ mod file_tests {
         };
 
         //Remove the file we created
-        match fs::remove_file("test.scad") {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+        drop(fs::remove_file("test.scad"));
 
         assert!(write_success);
         assert!(correct_content);
     }
+
+    #[test]
+    fn module_test() {
+        let mut sfile = ScadFile::new();
+        sfile.set_detail(10);
+        sfile.define_module("empty", vec![], vec![]);
+        sfile.define_module(
+            "peg",
+            vec![],
+            vec![ScadObject::new(ScadElement::Cube(na::Vector3::new(
+                1., 1., 1.,
+            )))],
+        );
+        sfile.add_object(ScadObject::new(ScadElement::Call(ScadCall::new("peg"))));
+        assert_eq!(
+            sfile.get_code(),
+            "$fn=10;\nmodule empty(){}\nmodule peg()\n{\n\tcube([1,1,1]);\n}\npeg();\n"
+        );
+
+        sfile.define_module("empty", vec![], vec![ScadObject::new(ScadElement::Else)]);
+        assert_eq!(sfile.modules().len(), 2);
+        let error = sfile.try_get_code().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "in module empty: in child 0: else must directly follow an if and can not have a modifier"
+        );
+        match *error.root_cause() {
+            ScadError::MisplacedElse => {}
+            _ => panic!("wrong root cause"),
+        }
+    }
+
+    #[test]
+    fn library_test() {
+        let mut sfile = ScadFile::new();
+        sfile.include_library("constants.scad");
+        sfile.use_library("BOSL2/std.scad");
+        sfile.use_library("BOSL2/std.scad");
+        sfile.set_detail(8);
+        sfile.add_object(ScadObject::new(ScadElement::Call(
+            ScadCall::new("cyl").named("d", 3),
+        )));
+
+        assert_eq!(sfile.libraries().len(), 2);
+        assert_eq!(
+            sfile.get_code(),
+            "include <constants.scad>\nuse <BOSL2/std.scad>\n$fn=8;\ncyl(d=3);\n"
+        );
+
+        sfile.set_format_options(FormatOptions::compact());
+        assert_eq!(
+            sfile.get_code(),
+            "include <constants.scad>use <BOSL2/std.scad>$fn=8;cyl(d=3);"
+        );
+
+        let mut other = sfile.clone();
+        other.use_library("MCAD/nuts_and_bolts.scad");
+        assert!(sfile != other);
+        assert!(sfile.first_difference(&other, 0.).is_some());
+    }
+
+    #[test]
+    fn deduplicate_test() {
+        let peg = || {
+            let mut peg = ScadObject::new(ScadElement::Translate(na::Vector3::new(1., 0., 0.)));
+            peg.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+                1., 1., 1.,
+            ))));
+            peg
+        };
+        let mut pegs = ScadObject::new(ScadElement::Union);
+        pegs.add_child(peg());
+        pegs.add_child(peg());
+
+        let mut conditional = ScadObject::new(ScadElement::If(ScadExpr::var("x").gt(1)));
+        conditional.add_child(peg());
+
+        let mut sfile = ScadFile::new();
+        sfile.define_module("shared_0", vec![], vec![]);
+        sfile.add_object(pegs.clone());
+        sfile.add_object(pegs.highlight());
+        sfile.add_object(conditional.clone());
+        sfile.add_object(conditional);
+
+        assert_eq!(sfile.deduplicate(), 2);
+        let call = |name: &str| ScadObject::new(ScadElement::Call(ScadCall::new(name)));
+        let conditional = {
+            let mut conditional = ScadObject::new(ScadElement::If(ScadExpr::var("x").gt(1)));
+            conditional.add_child(call("shared_2"));
+            conditional
+        };
+        assert_eq!(
+            sfile.objects(),
+            &[
+                call("shared_1"),
+                call("shared_1").highlight(),
+                conditional.clone(),
+                conditional,
+            ][..]
+        );
+
+        let mut pegs = ScadObject::new(ScadElement::Union);
+        pegs.add_child(call("shared_2"));
+        pegs.add_child(call("shared_2"));
+        assert_eq!(sfile.modules()[1].name, "shared_1");
+        assert_eq!(sfile.modules()[1].body, vec![pegs]);
+        assert_eq!(sfile.modules()[2].name, "shared_2");
+        assert_eq!(sfile.modules()[2].body, vec![peg()]);
+
+        assert_eq!(sfile.deduplicate(), 0);
+
+        //The argument could be the loop variable, so the calls stay in the loop
+        let spoke = || {
+            let mut spoke = ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 2., 0.)));
+            spoke.add_child(ScadObject::new(ScadElement::Call(ScadCall {
+                module: String::from("spoke"),
+                args: vec![(None, ScadExpr::var("i"))],
+            })));
+            spoke
+        };
+        let mut wheel = ScadObject::new(ScadElement::For(vec![(
+            String::from("i"),
+            LoopValues::Numbers(vec![1., 2.]),
+        )]));
+        wheel.add_child(spoke());
+        wheel.add_child(spoke());
+
+        let mut sfile = ScadFile::new();
+        sfile.add_object(wheel.clone());
+        assert_eq!(sfile.deduplicate(), 0);
+        sfile.add_object(wheel);
+        assert_eq!(sfile.deduplicate(), 1);
+        assert_eq!(
+            sfile.modules()[0].body[0].children(),
+            &[spoke(), spoke()][..]
+        );
+
+        //Expressions can use the loop variable as well
+        let rung = || {
+            let mut rung = ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 0., 0.)))
+                .with_expression("v", ScadExpr::vec3(ScadExpr::var("i"), 0, 0));
+            rung.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+                1., 1., 1.,
+            ))));
+            rung
+        };
+        let mut ladder = ScadObject::new(ScadElement::For(vec![(
+            String::from("i"),
+            LoopValues::Numbers(vec![1., 2.]),
+        )]));
+        ladder.add_child(rung());
+        ladder.add_child(rung());
+
+        let mut sfile = ScadFile::new();
+        sfile.add_object(ladder.clone());
+        assert_eq!(sfile.deduplicate(), 0);
+        assert_eq!(sfile.objects(), &[ladder.clone()][..]);
+
+        //So can the values of loops inside it
+        let mut steps = ScadObject::new(ScadElement::For(vec![(
+            String::from("j"),
+            LoopValues::Expression(ScadExpr::range(0, ScadExpr::var("i"))),
+        )]));
+        steps.add_child(ScadObject::new(ScadElement::Sphere(CircleType::Radius(1.))));
+        let mut step = ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 1., 0.)));
+        step.add_child(steps);
+        let mut stairs = ladder;
+        stairs.add_child(step.clone());
+        stairs.add_child(step);
+
+        let mut sfile = ScadFile::new();
+        sfile.add_object(stairs.clone());
+        assert_eq!(sfile.deduplicate(), 0);
+        assert_eq!(sfile.objects(), &[stairs][..]);
+
+        //And the variables of let statements
+        let rung = || {
+            let mut rung = ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 0., 0.)))
+                .with_expression("v", ScadExpr::vec3(ScadExpr::var("w"), 0, 0));
+            rung.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+                1., 1., 1.,
+            ))));
+            rung
+        };
+        let mut rungs = ScadObject::new(ScadElement::Let(vec![(String::from("w"), 3.into())]));
+        rungs.add_child(rung());
+        rungs.add_child(rung());
+
+        let mut sfile = ScadFile::new();
+        sfile.add_object(rungs.clone());
+        sfile.add_object(rungs.clone());
+        assert_eq!(sfile.deduplicate(), 0);
+        assert_eq!(sfile.objects(), &[rungs.clone(), rungs][..]);
+    }
+
+    #[test]
+    fn write_to_test() {
+        let mut sfile = ScadFile::new();
+        sfile.set_detail(12);
+
+        let mut obj = ScadObject::new(ScadElement::Union);
+        obj.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 2., 3.))));
+        sfile.add_object(obj);
+
+        let mut buffer = Vec::new();
+        sfile.write_to(&mut buffer).unwrap();
+
+        assert_eq!(String::from_utf8(buffer).unwrap(), sfile.get_code());
+    }
+
+    #[test]
+    fn format_options_test() {
+        let mut sfile = ScadFile::new();
+        assert_eq!(sfile.get_code(), "");
+
+        sfile.set_detail(30);
+        let mut obj = ScadObject::new(ScadElement::Union);
+        obj.add_child(ScadObject::new(ScadElement::Hull));
+        sfile.add_object(obj);
+        sfile.add_object(ScadObject::new(ScadElement::Difference));
+
+        sfile.set_format_options(FormatOptions {
+            final_newline: false,
+            ..Default::default()
+        });
+        assert_eq!(
+            sfile.get_code(),
+            "$fn=30;\nunion()\n{\n\thull();\n}\ndifference();"
+        );
+
+        sfile.set_format_options(FormatOptions::compact());
+        assert_eq!(sfile.get_code(), "$fn=30;union()hull();difference();");
+
+        sfile.set_format_options(FormatOptions {
+            final_newline: true,
+            ..FormatOptions::compact()
+        });
+        assert_eq!(sfile.get_code(), "$fn=30;union()hull();difference();\n");
+    }
+
+    #[test]
+    fn compare_test() {
+        let mut a = ScadFile::new();
+        a.add_object(ScadObject::new(ScadElement::Union));
+        a.add_object(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+            1., 1., 1.,
+        ))));
+
+        let mut b = a.clone();
+        b.set_format_options(FormatOptions::compact());
+        assert_eq!(a, b);
+
+        b.set_detail(10);
+        assert_eq!(a.first_difference(&b, 0.).unwrap().right, "$fn=10");
+
+        let mut c = ScadFile::new();
+        c.add_object(ScadObject::new(ScadElement::Union));
+        c.add_object(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+            1., 1., 1.01,
+        ))));
+        assert!(a != c);
+        assert!(a.approx_eq(&c, 0.1));
+        assert_eq!(a.first_difference(&c, 0.001).unwrap().path, vec![1]);
+
+        c.add_object(ScadObject::new(ScadElement::Union));
+        assert_eq!(a.first_difference(&c, 0.1).unwrap().left, "2 objects");
+    }
+
+    struct FailingWriter;
+    impl io::Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "nope"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_to_error_test() {
+        let mut sfile = ScadFile::new();
+        sfile.add_object(ScadObject::new(ScadElement::Union));
+
+        match sfile.write_to(FailingWriter) {
+            Err(ScadError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+            _ => panic!("io error was not reported"),
+        }
+    }
+
+    #[test]
+    fn try_write_to_file_test() {
+        let mut sfile = ScadFile::new();
+        sfile.add_object(ScadObject::new(ScadElement::Union));
+        sfile.add_object(ScadObject::new(ScadElement::Color(na::Vector3::new(
+            0., 0., 3.,
+        ))));
+
+        match sfile.try_write_to_file("invalid_test.scad") {
+            Err(ScadError::At { path, .. }) => assert_eq!(path, vec![1]),
+            _ => panic!("invalid colour was not reported"),
+        }
+        assert!(!Path::new("invalid_test.scad").exists());
+
+        match ScadFile::new().try_write_to_file("no_such_directory/test.scad") {
+            Err(ScadError::Io(_)) => {}
+            _ => panic!("missing directory was not reported"),
+        }
+    }
+
+    #[test]
+    fn heightmap_file_test() {
+        let mut sfile = ScadFile::new();
+        sfile.add_object(ScadObject::new(ScadElement::Surface(SurfaceParams::new(
+            "bumps.dat",
+        ))));
+        sfile.add_heightmap("bumps.dat", Heightmap::from([[0., 1.], [1., 0.]]));
+        sfile.add_heightmap("flat.dat", Heightmap::from([[0.]]));
+        sfile.add_heightmap("bumps.dat", Heightmap::from([[1., 2.], [2., 1.]]));
+        assert_eq!(sfile.heightmaps().len(), 2);
+
+        fs::create_dir_all("heightmap_test").unwrap();
+        let result = sfile.try_write_to_file("heightmap_test/terrain.scad");
+        let bumps = fs::read_to_string("heightmap_test/bumps.dat");
+        let flat = fs::read_to_string("heightmap_test/flat.dat");
+        drop(fs::remove_dir_all("heightmap_test"));
+
+        result.unwrap();
+        assert_eq!(bumps.unwrap(), "1 2\n2 1\n");
+        assert_eq!(flat.unwrap(), "0\n");
+
+        let mut other = sfile.clone();
+        assert_eq!(sfile, other);
+        other.add_heightmap("flat.dat", Heightmap::from([[0.5]]));
+        assert!(sfile != other);
+        assert!(sfile.approx_eq(&other, 1.));
+        assert_eq!(
+            sfile.first_difference(&other, 0.1).unwrap().left,
+            "heightmaps [\"bumps.dat\", \"flat.dat\"]"
+        );
+
+        sfile.add_heightmap("broken.dat", Heightmap::new(vec![vec![1.], vec![]]));
+        assert!(sfile
+            .try_write_to_file("broken_heightmap_test.scad")
+            .is_err());
+        assert!(!Path::new("broken_heightmap_test.scad").exists());
+
+        for name in &["../escape.dat", "/tmp/escape.dat", "nested/map.dat", "..", ""] {
+            let mut escaping = ScadFile::new();
+            escaping.add_heightmap(name, Heightmap::from([[0.]]));
+            match escaping.try_write_to_file("escaping_heightmap_test.scad") {
+                Err(ScadError::InvalidHeightmapName(ref invalid)) => assert_eq!(invalid, name),
+                result => panic!("{:?} was accepted: {:?}", name, result),
+            }
+            assert!(!Path::new("escaping_heightmap_test.scad").exists());
+        }
+    }
+
+    #[test]
+    fn import_path_test() {
+        let mut sfile = ScadFile::new();
+        sfile.add_object(ScadObject::new(ScadElement::ImportFile(ImportParams {
+            check_exists: true,
+            ..ImportParams::new("Cargo.toml")
+        })));
+        sfile.add_object(ScadObject::new(ScadElement::Import(String::from(
+            "as_written.stl",
+        ))));
+        //Objects with params are written the same way
+        sfile.add_object(
+            ScadObject::new(ScadElement::ImportFile(ImportParams::new("parts/a.stl")))
+                .with_detail(8),
+        );
+        assert_eq!(
+            sfile.get_code(),
+            "import(file=\"Cargo.toml\");\nimport(\"as_written.stl\");\n\
+             import(file=\"parts/a.stl\",$fn=8);\n"
+        );
+
+        fs::create_dir_all("import_test/models").unwrap();
+        let result = sfile.try_write_to_file("import_test/models/part.scad");
+        let code = fs::read_to_string("import_test/models/part.scad");
+        drop(fs::remove_dir_all("import_test"));
+
+        result.unwrap();
+        assert_eq!(
+            code.unwrap(),
+            "import(file=\"../../Cargo.toml\");\nimport(\"as_written.stl\");\n\
+             import(file=\"../../parts/a.stl\",$fn=8);\n"
+        );
+
+        sfile.add_object(ScadObject::new(ScadElement::ImportFile(ImportParams {
+            check_exists: true,
+            ..ImportParams::new("missing_import_test.stl")
+        })));
+        match sfile.try_write_to_file("missing_import_test.scad") {
+            Err(ScadError::At { path, error }) => {
+                assert_eq!(path, vec![3]);
+                assert!(matches!(*error, ScadError::MissingImport(_)));
+            }
+            _ => panic!("missing import was not reported"),
+        }
+        assert!(!Path::new("missing_import_test.scad").exists());
+    }
 }
@@ -0,0 +1,114 @@
+use std::fmt;
+
+/**
+  OpenSCAD release that the generated code is meant for.
+
+  Elements and arguments that the target release doesn't understand are
+  reported as `ScadError::UnsupportedVersion` instead of being written, since
+  OpenSCAD would otherwise ignore them with a warning and produce a different
+  model. Versions are ordered from oldest to newest.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OpenScadVersion {
+    V2015_03,
+    V2019_05,
+    V2021_01,
+    /// The development snapshots, which have features that are not in any release yet
+    Snapshot,
+}
+
+impl fmt::Display for OpenScadVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpenScadVersion::V2015_03 => write!(f, "OpenSCAD 2015.03"),
+            OpenScadVersion::V2019_05 => write!(f, "OpenSCAD 2019.05"),
+            OpenScadVersion::V2021_01 => write!(f, "OpenSCAD 2021.01"),
+            OpenScadVersion::Snapshot => write!(f, "an OpenSCAD development snapshot"),
+        }
+    }
+}
+
+/// Character used for indenting the children of an object
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentStyle {
+    Tabs,
+    Spaces,
+}
+
+/**
+  Options for the layout of the generated code.
+
+  The default options produce the same code the crate has always generated:
+  children indented by one tab, braces around children on separate lines,
+  one object per line and a newline at the end of the file. Numbers are
+  rounded to 6 decimals. The code targets OpenSCAD 2021.01, the latest
+  release.
+
+  ```
+  # use scad::*;
+  let obj = scad!(Translate(vec3(1., 2., 3.)); scad!(Cube(vec3(1., 1., 1.))));
+
+  let options = FormatOptions {
+      elide_single_child_braces: true,
+      ..Default::default()
+  };
+
+  assert_eq!(obj.get_code_with(&options), "translate([1,2,3]) cube([1,1,1]);");
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatOptions {
+    pub indent_style: IndentStyle,
+    /// Number of tabs or spaces per indentation level
+    pub indent_width: usize,
+    /// Write objects with a single child as `parent() child();` without braces
+    pub elide_single_child_braces: bool,
+    /// Leave out all optional whitespace, useful for very large files
+    pub compact: bool,
+    /// End non-empty files with a newline
+    pub final_newline: bool,
+    /// Write all non-ASCII characters in strings as `\u` or `\U` escapes
+    /// instead of as UTF-8
+    pub escape_non_ascii: bool,
+    /// Maximum number of digits after the decimal point of floating point numbers.
+    /// Trailing zeros are always left out
+    pub precision: usize,
+    /// Oldest OpenSCAD version that has to understand the code
+    pub target: OpenScadVersion,
+}
+
+impl FormatOptions {
+    /// Options for the smallest possible output
+    pub fn compact() -> FormatOptions {
+        FormatOptions {
+            elide_single_child_braces: true,
+            compact: true,
+            final_newline: false,
+            ..Default::default()
+        }
+    }
+
+    /// Options for indenting with `width` spaces instead of tabs
+    pub fn spaces(width: usize) -> FormatOptions {
+        FormatOptions {
+            indent_style: IndentStyle::Spaces,
+            indent_width: width,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            indent_style: IndentStyle::Tabs,
+            indent_width: 1,
+            elide_single_child_braces: false,
+            compact: false,
+            final_newline: true,
+            escape_non_ascii: false,
+            precision: 6,
+            target: OpenScadVersion::V2021_01,
+        }
+    }
+}
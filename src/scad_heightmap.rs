@@ -0,0 +1,164 @@
+use crate::scad_compare::ApproxEq;
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_type::{ScadType, ScadWriter};
+
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/**
+  A grid of heights that can be saved as a `.dat` file for the `surface` element.
+
+  Each row is written as one line of the file. The first value of a row
+  ends up at x=0 and the first row at y=0, with one unit between values.
+
+  ```
+  # use scad::*;
+  //A ramp that is 4 units wide and 3 units deep
+  let ramp = Heightmap::from_fn(4, 3, |x, _y| x as f64 * 0.5);
+  assert_eq!(ramp.get_code(), "0 0.5 1 1.5\n0 0.5 1 1.5\n0 0.5 1 1.5\n");
+
+  let mut file = ScadFile::new();
+  file.add_heightmap("ramp.dat", ramp);
+  file.add_object(scad!(Surface(SurfaceParams::new("ramp.dat"))));
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Heightmap {
+    rows: Vec<Vec<f64>>,
+}
+
+impl Heightmap {
+    pub fn new(rows: Vec<Vec<f64>>) -> Heightmap {
+        Heightmap { rows }
+    }
+
+    /// Creates a heightmap by calling `height(x, y)` for every point of the grid
+    pub fn from_fn<F: FnMut(usize, usize) -> f64>(
+        columns: usize,
+        rows: usize,
+        mut height: F,
+    ) -> Heightmap {
+        Heightmap {
+            rows: (0..rows)
+                .map(|y| (0..columns).map(|x| height(x, y)).collect())
+                .collect(),
+        }
+    }
+
+    pub fn rows(&self) -> &[Vec<f64>] {
+        &self.rows
+    }
+
+    /**
+      Writes the heightmap to a `.dat` file. Like `ScadFile::try_write_to_file`,
+      nothing is written if the heightmap is invalid.
+    */
+    pub fn try_write_to_file<P: AsRef<Path>>(&self, path: P) -> ScadResult {
+        let data = self.try_get_code()?;
+
+        Ok(fs::write(path, data)?)
+    }
+}
+
+impl<const C: usize, const R: usize> From<[[f64; C]; R]> for Heightmap {
+    fn from(rows: [[f64; C]; R]) -> Heightmap {
+        Heightmap::new(rows.iter().map(|row| row.to_vec()).collect())
+    }
+}
+
+impl From<Vec<Vec<f64>>> for Heightmap {
+    fn from(rows: Vec<Vec<f64>>) -> Heightmap {
+        Heightmap::new(rows)
+    }
+}
+
+impl ApproxEq for Heightmap {
+    fn approx_eq(&self, other: &Heightmap, epsilon: f64) -> bool {
+        self.rows.approx_eq(&other.rows, epsilon)
+    }
+}
+
+impl ScadType for Heightmap {
+    /**
+      Writes the content of the `.dat` file. The values are formatted with the
+      precision of the writer, but the layout is always one row per line since
+      that is what OpenSCAD expects.
+    */
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        let expected = self.rows.first().map_or(0, Vec::len);
+
+        for (i, row) in self.rows.iter().enumerate() {
+            if row.len() != expected {
+                return Err(ScadError::RaggedHeightmap {
+                    row: i,
+                    length: row.len(),
+                    expected,
+                });
+            }
+
+            for (j, value) in row.iter().enumerate() {
+                if j != 0 {
+                    w.write_char(' ')?;
+                }
+                value.write_code(w)?;
+            }
+            w.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod heightmap_tests {
+    use super::*;
+    use crate::scad_format::FormatOptions;
+
+    #[test]
+    fn dat_test() {
+        let map = Heightmap::from([[0., 1., 2.], [-0.25, 1e-9, 10.]]);
+        assert_eq!(map.get_code(), "0 1 2\n-0.25 0 10\n");
+        assert_eq!(Heightmap::new(vec![]).get_code(), "");
+
+        let mut data = String::new();
+        map.write_code(&mut ScadWriter::with_options(
+            &mut data,
+            FormatOptions {
+                precision: 10,
+                ..FormatOptions::compact()
+            },
+        ))
+        .unwrap();
+        assert_eq!(data, "0 1 2\n-0.25 0.000000001 10\n");
+    }
+
+    #[test]
+    fn from_fn_test() {
+        let map = Heightmap::from_fn(3, 2, |x, y| (x * 10 + y) as f64);
+        assert_eq!(map.rows(), &[vec![0., 10., 20.], vec![1., 11., 21.]][..]);
+    }
+
+    #[test]
+    fn invalid_heightmap_test() {
+        match Heightmap::new(vec![vec![1., 2.], vec![3.]]).try_get_code() {
+            Err(ScadError::RaggedHeightmap {
+                row: 1,
+                length: 1,
+                expected: 2,
+            }) => {}
+            _ => panic!("ragged rows were not reported"),
+        }
+        match Heightmap::from([[f64::INFINITY]]).try_get_code() {
+            Err(ScadError::NonFiniteNumber(_)) => {}
+            _ => panic!("infinite height was not reported"),
+        }
+
+        assert!(Heightmap::from([[f64::NAN]])
+            .try_write_to_file("invalid_heightmap_test.dat")
+            .is_err());
+        assert!(!Path::new("invalid_heightmap_test.dat").exists());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::scad_compare::ApproxEq;
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_type::{ScadType, ScadWriter};
+use nalgebra as na;
+
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// File formats that OpenSCAD can import, detected from the file extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ImportFormat {
+    Stl,
+    Off,
+    ThreeMf,
+    Amf,
+    Svg,
+    Dxf,
+}
+
+impl ImportFormat {
+    /// Returns the format of `path` based on its extension, ignoring case
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImportFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "stl" => Some(ImportFormat::Stl),
+            "off" => Some(ImportFormat::Off),
+            "3mf" => Some(ImportFormat::ThreeMf),
+            "amf" => Some(ImportFormat::Amf),
+            "svg" => Some(ImportFormat::Svg),
+            "dxf" => Some(ImportFormat::Dxf),
+            _ => None,
+        }
+    }
+
+    /// True for formats that contain 2D shapes, which can be extruded
+    pub fn is_2d(self) -> bool {
+        matches!(self, ImportFormat::Svg | ImportFormat::Dxf)
+    }
+}
+
+/**
+  Parameters for importing a mesh or 2D drawing with `ScadElement::ImportFile`.
+
+  `file` is a path on the machine that generates the code, relative paths are
+  relative to the working directory. `ScadFile::try_write_to_file` rewrites
+  it to be relative to the written scad file, so the generated file still finds
+  it. `get_code` writes it as it is.
+
+  `layer`, `origin` and `scale` only apply to DXF files, `dpi`, `id` and
+  `center` only to SVG files. Setting them for other files is reported as
+  `ScadError::UnsupportedArgument`.
+
+  ```
+  # use scad::*;
+  let logo = ImportParams {
+      dpi: Some(96.),
+      center: Some(true),
+      ..ImportParams::new("logo.svg")
+  };
+  assert_eq!(logo.format(), Some(ImportFormat::Svg));
+  assert_eq!(
+      scad!(ImportFile(logo)).get_code(),
+      "import(file=\"logo.svg\",dpi=96,center=true);"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImportParams {
+    pub file: String,
+    pub convexity: Option<u64>,
+    pub layer: Option<String>,
+    pub origin: Option<na::Vector2<f64>>,
+    pub scale: Option<f64>,
+    pub dpi: Option<f64>,
+    pub id: Option<String>,
+    pub center: Option<bool>,
+    /// Report `ScadError::MissingImport` when generating code if the file doesn't exist
+    pub check_exists: bool,
+}
+
+impl ImportParams {
+    pub fn new(file: &str) -> ImportParams {
+        ImportParams {
+            file: file.to_string(),
+            convexity: None,
+            layer: None,
+            origin: None,
+            scale: None,
+            dpi: None,
+            id: None,
+            center: None,
+            check_exists: false,
+        }
+    }
+
+    pub fn format(&self) -> Option<ImportFormat> {
+        ImportFormat::from_path(&self.file)
+    }
+
+    /// Makes sure that every option that is set applies to the format of the file
+    fn check_options(&self) -> ScadResult {
+        let format = self.format();
+        let options = [
+            ("layer", self.layer.is_some(), ImportFormat::Dxf),
+            ("origin", self.origin.is_some(), ImportFormat::Dxf),
+            ("scale", self.scale.is_some(), ImportFormat::Dxf),
+            ("dpi", self.dpi.is_some(), ImportFormat::Svg),
+            ("id", self.id.is_some(), ImportFormat::Svg),
+            ("center", self.center.is_some(), ImportFormat::Svg),
+        ];
+
+        for &(argument, set, required) in options.iter() {
+            if set && format != Some(required) {
+                return Err(ScadError::UnsupportedArgument {
+                    module: format!("import of {}", self.file),
+                    argument: argument.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ScadType for ImportParams {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        self.check_options()?;
+        if self.check_exists && !Path::new(&self.file).exists() {
+            return Err(ScadError::MissingImport(self.file.clone()));
+        }
+
+        w.write_str("file=")?;
+        match w
+            .output_dir()
+            .and_then(|dir| relative_path(&self.file, dir))
+        {
+            Some(path) => w.argument("file", &path)?,
+            None => w.argument("file", &self.file)?,
+        }
+        if self.convexity.is_some() || w.has_expression("convexity") {
+            w.write_str(",convexity=")?;
+            w.argument("convexity", &self.convexity)?;
+        }
+        if self.layer.is_some() || w.has_expression("layer") {
+            w.write_str(",layer=")?;
+            w.argument("layer", &self.layer)?;
+        }
+        if self.origin.is_some() || w.has_expression("origin") {
+            w.write_str(",origin=")?;
+            w.argument("origin", &self.origin)?;
+        }
+        if self.scale.is_some() || w.has_expression("scale") {
+            w.write_str(",scale=")?;
+            w.argument("scale", &self.scale)?;
+        }
+        if self.dpi.is_some() || w.has_expression("dpi") {
+            w.write_str(",dpi=")?;
+            w.argument("dpi", &self.dpi)?;
+        }
+        if self.id.is_some() || w.has_expression("id") {
+            w.write_str(",id=")?;
+            w.argument("id", &self.id)?;
+        }
+        if self.center.is_some() || w.has_expression("center") {
+            w.write_str(",center=")?;
+            w.argument("center", &self.center)?;
+        }
+        Ok(())
+    }
+}
+
+impl ApproxEq for ImportParams {
+    fn approx_eq(&self, other: &ImportParams, epsilon: f64) -> bool {
+        self.file == other.file
+            && self.convexity == other.convexity
+            && self.layer == other.layer
+            && self.origin.approx_eq(&other.origin, epsilon)
+            && self.scale.approx_eq(&other.scale, epsilon)
+            && self.dpi.approx_eq(&other.dpi, epsilon)
+            && self.id == other.id
+            && self.center == other.center
+            && self.check_exists == other.check_exists
+    }
+}
+
+/// Removes `.` and `..` from an absolute path without touching the file system
+fn normalize(path: &Path) -> Option<Vec<Component<'_>>> {
+    let mut components: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if let Some(Component::Normal(_)) = components.last() {
+                    components.pop();
+                }
+            }
+            component => components.push(component),
+        }
+    }
+    match components.first() {
+        Some(Component::RootDir) | Some(Component::Prefix(_)) => Some(components),
+        _ => None,
+    }
+}
+
+/**
+  Returns `path` relative to `directory`, with `/` as separator. Relative
+  paths are resolved against the working directory first. Returns `None` if
+  there is no relative path, for example for files on different drives.
+*/
+fn relative_path(path: &str, directory: &Path) -> Option<String> {
+    let working_dir = env::current_dir().ok()?;
+    let path: PathBuf = working_dir.join(path);
+    let directory: PathBuf = working_dir.join(directory);
+    let path = normalize(&path)?;
+    let directory = normalize(&directory)?;
+
+    let common = path
+        .iter()
+        .zip(directory.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return None;
+    }
+
+    let parts: Vec<String> = directory[common..]
+        .iter()
+        .map(|_| String::from(".."))
+        .chain(
+            path[common..]
+                .iter()
+                .map(|part| part.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect();
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod import_tests {
+    use super::*;
+
+    #[test]
+    fn format_test() {
+        assert_eq!(
+            ImportFormat::from_path("parts/Bracket.STL"),
+            Some(ImportFormat::Stl)
+        );
+        assert_eq!(
+            ImportFormat::from_path("case.3mf"),
+            Some(ImportFormat::ThreeMf)
+        );
+        assert_eq!(ImportFormat::from_path("README"), None);
+        assert!(ImportFormat::Dxf.is_2d());
+        assert!(!ImportFormat::Off.is_2d());
+    }
+
+    #[test]
+    fn options_test() {
+        assert_eq!(ImportParams::new("a.stl").get_code(), "file=\"a.stl\"");
+
+        let dxf = ImportParams {
+            convexity: Some(3),
+            layer: Some(String::from("outline")),
+            origin: Some(na::Vector2::new(1., -2.)),
+            scale: Some(25.4),
+            ..ImportParams::new("plate.dxf")
+        };
+        assert_eq!(
+            dxf.get_code(),
+            "file=\"plate.dxf\",convexity=3,layer=\"outline\",origin=[1,-2],scale=25.4"
+        );
+
+        let svg = ImportParams {
+            id: Some(String::from("cutout")),
+            center: Some(false),
+            ..ImportParams::new("logo.SVG")
+        };
+        assert_eq!(
+            svg.get_code(),
+            "file=\"logo.SVG\",id=\"cutout\",center=false"
+        );
+
+        match (ImportParams {
+            dpi: Some(72.),
+            ..ImportParams::new("plate.dxf")
+        })
+        .try_get_code()
+        {
+            Err(ScadError::UnsupportedArgument { module, argument }) => {
+                assert_eq!(module, "import of plate.dxf");
+                assert_eq!(argument, "dpi");
+            }
+            _ => panic!("dpi was accepted for a dxf file"),
+        }
+        assert!((ImportParams {
+            layer: Some(String::from("0")),
+            ..ImportParams::new("part.stl")
+        })
+        .try_get_code()
+        .is_err());
+    }
+
+    #[test]
+    fn check_exists_test() {
+        let missing = ImportParams {
+            check_exists: true,
+            ..ImportParams::new("does/not/exist.stl")
+        };
+        match missing.try_get_code() {
+            Err(ScadError::MissingImport(file)) => assert_eq!(file, "does/not/exist.stl"),
+            _ => panic!("missing file was not reported"),
+        }
+
+        let existing = ImportParams {
+            check_exists: true,
+            ..ImportParams::new("Cargo.toml")
+        };
+        assert!(existing.try_get_code().is_ok());
+    }
+
+    #[test]
+    fn relative_path_test() {
+        let relative = |path: &str, directory: &str| relative_path(path, Path::new(directory));
+
+        assert_eq!(
+            relative("parts/a.stl", "out"),
+            Some(String::from("../parts/a.stl"))
+        );
+        assert_eq!(
+            relative("parts/a.stl", ""),
+            Some(String::from("parts/a.stl"))
+        );
+        assert_eq!(
+            relative("./out/../a.stl", "out/models/"),
+            Some(String::from("../../a.stl"))
+        );
+        assert_eq!(
+            relative("out/models/a.stl", "out"),
+            Some(String::from("models/a.stl"))
+        );
+
+        let working_dir = env::current_dir().unwrap();
+        let absolute = working_dir.join("parts").join("a.stl");
+        assert_eq!(
+            relative(absolute.to_str().unwrap(), "out"),
+            Some(String::from("../parts/a.stl"))
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::scad_compare::ApproxEq;
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_expr::ScadExpr;
+use crate::scad_type::{write_identifier, ScadType, ScadWriter};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/**
+  A library that a file loads, see `ScadFile::use_library` and
+  `ScadFile::include_library`.
+
+  The path is written as it is. OpenSCAD looks for it next to the scad file
+  and in its library directories, so paths like `BOSL2/std.scad` work for
+  installed libraries.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LibraryImport {
+    /// `use <path>`, makes the modules and functions of the library available
+    Use(String),
+    /// `include <path>`, works as if the library was pasted into the file, so
+    /// its variables and top level objects are included as well
+    Include(String),
+}
+
+impl LibraryImport {
+    pub fn path(&self) -> &str {
+        match *self {
+            LibraryImport::Use(ref path) | LibraryImport::Include(ref path) => path,
+        }
+    }
+}
+
+impl ScadType for LibraryImport {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        let path = self.path();
+        if path.is_empty() || path.contains(['>', '\n', '\r']) {
+            return Err(ScadError::InvalidLibraryPath(path.to_string()));
+        }
+
+        match *self {
+            LibraryImport::Use(_) => write!(w, "use <{}>", path),
+            LibraryImport::Include(_) => write!(w, "include <{}>", path),
+        }
+    }
+}
+
+/**
+  A call of a module that the crate doesn't know, usually from a library
+  loaded with `ScadFile::use_library` or defined with `ScadFile::define_module`.
+
+  Arguments are stored as `ScadExpr`s, so they can be numbers, strings,
+  vectors or expressions of variables. They are checked and written with the
+  `FormatOptions` of the file like the arguments of any other element.
+
+  ```
+  # use scad::*;
+  let call = ScadCall::new("cuboid")
+      .arg(vec3(20., 10., 5.))
+      .named("rounding", ScadExpr::var("wall") / 2)
+      .named("edges", "Z");
+  assert_eq!(
+      scad!(Call(call)).get_code(),
+      "cuboid([20,10,5],rounding=wall/2,edges=\"Z\");"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScadCall {
+    pub module: String,
+    /// The name of each argument, `None` for positional ones, and its value
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub args: Vec<(Option<String>, ScadExpr)>,
+}
+
+impl ScadCall {
+    pub fn new(module: &str) -> ScadCall {
+        ScadCall {
+            module: module.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds a positional argument
+    pub fn arg<T: Into<ScadExpr>>(mut self, value: T) -> ScadCall {
+        self.args.push((None, value.into()));
+        self
+    }
+
+    /// Adds a named argument
+    pub fn named<T: Into<ScadExpr>>(mut self, name: &str, value: T) -> ScadCall {
+        self.args.push((Some(name.to_string()), value.into()));
+        self
+    }
+}
+
+impl ScadType for ScadCall {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.begin_call(&self.module)?;
+        for (i, (name, value)) in self.args.iter().enumerate() {
+            if i != 0 {
+                w.write_char(',')?;
+            }
+            match *name {
+                Some(ref name) => {
+                    write_identifier(w, name)?;
+                    w.write_char('=')?;
+                    w.argument(name, value)?;
+                }
+                None => value.write_code(w)?,
+            }
+        }
+        w.end_call()
+    }
+}
+
+impl ApproxEq for ScadCall {
+    fn approx_eq(&self, other: &ScadCall, epsilon: f64) -> bool {
+        self.module == other.module
+            && self.args.len() == other.args.len()
+            && self
+                .args
+                .iter()
+                .zip(other.args.iter())
+                .all(|((a_name, a), (b_name, b))| a_name == b_name && a.approx_eq(b, epsilon))
+    }
+}
+
+#[cfg(test)]
+mod library_tests {
+    use super::*;
+    use crate::scad_format::FormatOptions;
+    use nalgebra as na;
+
+    #[test]
+    fn import_test() {
+        assert_eq!(
+            LibraryImport::Use(String::from("BOSL2/std.scad")).get_code(),
+            "use <BOSL2/std.scad>"
+        );
+        assert_eq!(
+            LibraryImport::Include(String::from("../lib/screws.scad")).get_code(),
+            "include <../lib/screws.scad>"
+        );
+
+        for path in ["", "a>b.scad", "a\nb.scad"].iter() {
+            match LibraryImport::Use(path.to_string()).try_get_code() {
+                Err(ScadError::InvalidLibraryPath(invalid)) => assert_eq!(invalid, *path),
+                _ => panic!("invalid path {:?} was accepted", path),
+            }
+        }
+    }
+
+    #[test]
+    fn call_test() {
+        assert_eq!(ScadCall::new("nut").get_code(), "nut()");
+        assert_eq!(
+            ScadCall::new("screw")
+                .arg("M3")
+                .arg(vec![1, 2])
+                .named("length", na::Vector2::new(0.5, 12.))
+                .named("$fn", 24)
+                .named("thread", true)
+                .get_code(),
+            "screw(\"M3\",[1,2],length=[0.5,12],$fn=24,thread=true)"
+        );
+
+        let expression = ScadCall {
+            module: String::from("rotate"),
+            args: vec![(Some(String::from("a")), ScadExpr::Code(String::from("$t * 360")))],
+        };
+        assert_eq!(expression.get_code(), "rotate(a=$t * 360)");
+
+        assert!(ScadCall::new("cube-2").try_get_code().is_err());
+        assert!(ScadCall::new("cube")
+            .named("not valid", 1.)
+            .try_get_code()
+            .is_err());
+
+        let options = FormatOptions {
+            precision: 2,
+            escape_non_ascii: true,
+            ..Default::default()
+        };
+        let mut code = String::new();
+        ScadCall::new("label")
+            .arg("µm")
+            .arg(1. / 3.)
+            .write_code(&mut ScadWriter::with_options(&mut code, options))
+            .unwrap();
+        assert_eq!(code, "label(\"\\u00b5m\",0.33)");
+
+        //Values are checked when they are written instead of when they are added
+        assert!(ScadCall::new("cube").arg(f64::NAN).try_get_code().is_err());
+        assert!(ScadCall::new("text").arg("a\0b").try_get_code().is_err());
+
+        let close = ScadCall::new("cube").arg(0.1 + 0.2);
+        assert!(close != ScadCall::new("cube").arg(0.3));
+        assert!(close.approx_eq(&ScadCall::new("cube").arg(0.3), 1e-9));
+        assert!(!close.approx_eq(&ScadCall::new("cube").named("size", 0.3), 1e-9));
+    }
+}
@@ -17,7 +17,6 @@ use nalgebra as na;
   });
   ```
 */
-
 #[macro_export]
 macro_rules! scad {
     ($parent:expr) => {$crate::ScadObject::new($parent)};
@@ -48,19 +47,64 @@ macro_rules! scad {
     };
 }
 
+/**
+  Asserts that two `ScadObject`s or `ScadFile`s are structurally equal, optionally
+  allowing floating point values to differ by an epsilon. On failure, the path to
+  the first difference and the code of both sides are printed instead of the
+  whole trees.
+
+  ```
+  # use scad::*;
+  let expected = scad!(Translate(vec3(0., 0., 0.3)); scad!(Sphere(Radius(1.))));
+
+  assert_scad_eq!(
+      scad!(Translate(vec3(0., 0., 0.1 + 0.2)); scad!(Sphere(Radius(1.)))),
+      expected,
+      1e-9
+  );
+  ```
+*/
+#[macro_export]
+macro_rules! assert_scad_eq {
+    ($left:expr, $right:expr) => {
+        $crate::assert_scad_eq!($left, $right, 0.)
+    };
+    ($left:expr, $right:expr, $epsilon:expr) => {
+        if let Some(diff) = $left.first_difference(&$right, $epsilon) {
+            panic!("assertion failed: `left == right`\n{}", diff);
+        }
+    };
+}
+
 /**
   Utility function for creating nalgebra vectors without having
   to write `na::Vector3::new(x,y,z)`
+
+  The `f32` components are widened to `f64` without loss. Use `dvec3` to
+  pass `f64` values.
 */
-pub const fn vec3(x: f32, y: f32, z: f32) -> na::Vector3<f32> {
-    na::Vector3::new(x, y, z)
+pub const fn vec3(x: f32, y: f32, z: f32) -> na::Vector3<f64> {
+    na::Vector3::new(x as f64, y as f64, z as f64)
 }
 
 /**
   Utility function for creating nalgebra vectors without having
   to write `na::Vector2::new(x,y)`
+
+  The `f32` components are widened to `f64` without loss. Use `dvec2` to
+  pass `f64` values.
 */
-pub const fn vec2(x: f32, y: f32) -> na::Vector2<f32> {
+pub const fn vec2(x: f32, y: f32) -> na::Vector2<f64> {
+    na::Vector2::new(x as f64, y as f64)
+}
+
+/// Same as `vec3` but with `f64` components
+pub const fn dvec3(x: f64, y: f64, z: f64) -> na::Vector3<f64> {
+    na::Vector3::new(x, y, z)
+}
+
+/// Same as `vec2` but with `f64` components
+pub const fn dvec2(x: f64, y: f64) -> na::Vector2<f64> {
     na::Vector2::new(x, y)
 }
 
@@ -101,6 +145,27 @@ pub const fn vec2(x: f32, y: f32) -> na::Vector2<f32> {
       }
   }
   ```
+
+  ### Customizer
+  The members can be exported as variables for the Customizer of OpenSCAD
+  with `customizer_variables`, if all of them have types that implement
+  `CustomizerType`. Doc comments of the members become the descriptions.
+
+  ```
+  # use scad::*;
+  qstruct!(Bracket() {
+      /// Thickness of the plate
+      thickness: f64 = 3.,
+      holes: u32 = 2,
+  });
+
+  let mut file = ScadFile::new();
+  file.add_customizer_variables(Bracket::new().customizer_variables());
+  assert_eq!(
+      file.get_code(),
+      "// Thickness of the plate\nthickness=3;\nholes=2;\n"
+  );
+  ```
 */
 #[macro_export]
 macro_rules! qstruct
@@ -108,7 +173,7 @@ macro_rules! qstruct
     ($name:ident
     ($($param_name:ident: $param_type:ty),*$(),+)
     {
-        $($mem_name:ident : $mem_type:ty = $mem_value:expr),*$(),+
+        $($(#[doc = $mem_doc:literal])* $mem_name:ident : $mem_type:ty = $mem_value:expr),*$(),+
     })
     =>
     {
@@ -116,6 +181,7 @@ macro_rules! qstruct
         pub struct $name
         {
             $(
+                $(#[doc = $mem_doc])*
                 pub $mem_name : $mem_type
             ),*
         }
@@ -136,6 +202,35 @@ macro_rules! qstruct
                     )*
                 }
             }
+
+            /// The members as variables for the Customizer of OpenSCAD, with
+            /// their doc comments as descriptions. Only available if all
+            /// members have types that implement `CustomizerType`
+            #[allow(dead_code)]
+            pub fn customizer_variables<'a>(&'a self) -> Vec<$crate::CustomizerVariable>
+            where
+                $(&'a $mem_type: $crate::CustomizerType),*
+            {
+                vec![
+                    $({
+                        let variable = $crate::CustomizerVariable::new(
+                            stringify!($mem_name),
+                            &&self.$mem_name,
+                        );
+                        let lines: &[&str] = &[$($mem_doc),*];
+                        let description = lines
+                            .iter()
+                            .map(|line| line.trim())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        if description.is_empty() {
+                            variable
+                        } else {
+                            variable.description(&description)
+                        }
+                    }),*
+                ]
+            }
         }
     }
 }
@@ -154,6 +249,16 @@ mod macro_test {
     #[test]
     fn vec3_test() {
         assert_eq!(vec3(0.0, 1.0, 2.0), na::Vector3::new(0.0, 1.0, 2.0));
+
+        //f32 components are converted without loss
+        assert_eq!(vec3(0.1, 1., 2.), na::Vector3::new(0.1f32, 1., 2.).cast::<f64>());
+        assert_eq!(vec2(0.3, 1.), na::Vector2::new(f64::from(0.3f32), 1.));
+        assert_eq!(dvec3(0.1, 1., 2.), na::Vector3::new(0.1, 1., 2.));
+        assert_eq!(dvec2(0.3, 1.), na::Vector2::new(0.3, 1.));
+
+        //Both can be used in constants
+        const SIZE: na::Vector3<f64> = vec3(1., 2., 3.);
+        assert_eq!(SIZE, dvec3(1., 2., 3.));
     }
 
     #[test]
@@ -190,6 +295,28 @@ mod macro_test {
         );
     }
 
+    #[test]
+    fn assert_scad_eq_test() {
+        assert_scad_eq!(
+            scad!(Cube(vec3(1., 1., 1.)); scad!(Union)),
+            scad!(Cube(vec3(1., 1., 1.)); scad!(Union))
+        );
+        assert_scad_eq!(
+            scad!(Cube(vec3(1., 1., 1.)); scad!(Union)),
+            scad!(Cube(vec3(1., 1., 1.001)); scad!(Union)),
+            0.01
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "trees differ in child 0\n  left:  union()\n  right: hull()")]
+    fn assert_scad_eq_fail_test() {
+        assert_scad_eq!(
+            scad!(Cube(vec3(1., 1., 1.)); scad!(Union)),
+            scad!(Cube(vec3(1., 1., 1.)); scad!(Hull))
+        );
+    }
+
     #[test]
     fn qstruct_test() {}
 }
@@ -246,4 +373,43 @@ mod qstruct_test {
     fn impl_test() {
         assert_eq!(Test3::new().get_sum(), 4.);
     }
+
+    qstruct! {
+        Test4(outer: f64)
+        {
+            /// Width of the part
+            width: f64 = outer,
+            /// Number of
+            /// holes
+            holes: u32 = 4,
+            label: String = String::from("A"),
+        }
+    }
+
+    //Members that can't be exported don't stop the struct from compiling
+    qstruct! {
+        Test5()
+        {
+            part: crate::ScadObject = scad!(crate::ScadElement::Union),
+        }
+    }
+
+    #[test]
+    fn customizer_test() {
+        use crate::scad_customizer::{CustomizerValue, CustomizerVariable};
+
+        assert_eq!(
+            Test4::new(2.5).customizer_variables(),
+            vec![
+                CustomizerVariable::new("width", &2.5).description("Width of the part"),
+                CustomizerVariable::new("holes", &4).description("Number of holes"),
+                CustomizerVariable::new("label", "A"),
+            ]
+        );
+        assert_eq!(
+            Test4::new(1.).customizer_variables()[0].value,
+            CustomizerValue::Number(1.)
+        );
+        assert_eq!(Test5::new().part.children().len(), 0);
+    }
 }
@@ -0,0 +1,350 @@
+use crate::scad_compare::ApproxEq;
+use crate::scad_element::{LoopValues, ScadElement};
+use crate::scad_error::ScadResult;
+use crate::scad_expr::ScadExpr;
+use crate::scad_format::FormatOptions;
+use crate::scad_library::ScadCall;
+use crate::scad_object::{check_else, ScadObject};
+use crate::scad_type::{write_identifier, ScadType, ScadWriter};
+
+use std::collections::BTreeMap;
+use std::mem;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/**
+  A module definition, added to a file with `ScadFile::define_module` and
+  called with a `ScadCall`.
+
+  Parameters are a name and an optional default value, which can refer to
+  the parameters before it. The body can use the parameters in expressions
+  and the children of the call with `ScadElement::Children`.
+
+  ```
+  # use scad::*;
+  let hole = ScadModule {
+      name: String::from("hole"),
+      params: vec![
+          (String::from("d"), Some(3.into())),
+          (String::from("depth"), Some(ScadExpr::var("d") * 3)),
+      ],
+      body: vec![scad!(Cylinder(10., Diameter(3.))).with_expression("h", ScadExpr::var("depth"))],
+  };
+  assert_eq!(
+      hole.get_code(),
+      "module hole(d=3,depth=d*3)\n{\n\tcylinder(h=depth,d=3);\n}"
+  );
+  ```
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScadModule {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub params: Vec<(String, Option<ScadExpr>)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub body: Vec<ScadObject>,
+}
+
+impl ScadType for ScadModule {
+    /**
+      Writes the definition. The body is laid out like the children of an
+      object, errors in it are wrapped in `ScadError::At` with the index of
+      the object in the body.
+    */
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_str("module ")?;
+        write_identifier(w, &self.name)?;
+        w.write_char('(')?;
+        for (i, (name, default)) in self.params.iter().enumerate() {
+            if i != 0 {
+                w.write_char(',')?;
+            }
+            write_identifier(w, name)?;
+            if let Some(ref default) = *default {
+                w.write_char('=')?;
+                default.write_code(w)?;
+            }
+        }
+        w.write_char(')')?;
+
+        if self.body.is_empty() {
+            return w.write_str("{}");
+        }
+        if self.body.len() == 1 && w.options().elide_single_child_braces {
+            w.space()?;
+            check_else(None, &self.body[0]).map_err(|e| e.in_child(0))?;
+            return self.body[0].write_code(w).map_err(|e| e.in_child(0));
+        }
+
+        w.newline()?;
+        w.write_char('{')?;
+        w.indent();
+        for (i, object) in self.body.iter().enumerate() {
+            w.newline()?;
+            check_else(i.checked_sub(1).map(|i| &self.body[i]), object)
+                .map_err(|e| e.in_child(i))?;
+            object.write_code(w).map_err(|e| e.in_child(i))?;
+        }
+        w.dedent();
+        w.newline()?;
+        w.write_char('}')
+    }
+}
+
+impl ApproxEq for ScadModule {
+    fn approx_eq(&self, other: &ScadModule, epsilon: f64) -> bool {
+        self.name == other.name
+            && self.params.len() == other.params.len()
+            && self
+                .params
+                .iter()
+                .zip(&other.params)
+                .all(|((name_a, a), (name_b, b))| name_a == name_b && a.approx_eq(b, epsilon))
+            && self.body.approx_eq(&other.body, epsilon)
+    }
+}
+
+/**
+  Elements that can be moved into a module without changing their meaning.
+  Expressions can refer to variables of the surrounding scope and `children`
+  to the children of the surrounding module, so those stay where they are.
+*/
+fn is_self_contained(element: &ScadElement) -> bool {
+    !matches!(
+        *element,
+        ScadElement::If(_)
+            | ScadElement::Else
+            | ScadElement::Let(_)
+            | ScadElement::Echo(_)
+            | ScadElement::Assert(..)
+            | ScadElement::Children(_)
+    )
+}
+
+/// Objects with arguments that may refer to loop variables or module
+/// parameters: module calls with arguments, objects with expressions and
+/// loops over expressions
+fn has_arguments(object: &ScadObject) -> bool {
+    let element_arguments = match *object.element() {
+        ScadElement::Call(ref call) => !call.args.is_empty(),
+        ScadElement::For(ref loops) => loops
+            .iter()
+            .any(|(_, values)| matches!(values, LoopValues::Expression(_))),
+        ScadElement::IntersectionFor(_, LoopValues::Expression(_)) => true,
+        _ => false,
+    };
+    element_arguments || !object.params().expressions.is_empty()
+}
+
+/// Elements whose children can use variables that only exist inside them
+fn introduces_variables(element: &ScadElement) -> bool {
+    matches!(
+        *element,
+        ScadElement::For(_) | ScadElement::IntersectionFor(..) | ScadElement::Let(_)
+    )
+}
+
+/// True if `test` is true for any object in the tree
+fn any_object(object: &ScadObject, test: fn(&ScadObject) -> bool) -> bool {
+    test(object)
+        || object
+            .children()
+            .iter()
+            .any(|child| any_object(child, test))
+}
+
+/// Subtrees that can be moved into a module. `in_scope` is true inside loops
+/// and modules with parameters, where arguments could refer to local variables.
+/// Variables of loops inside the subtree move along with it
+fn can_move(object: &ScadObject, in_scope: bool) -> bool {
+    if any_object(object, |object| !is_self_contained(object.element())) {
+        return false;
+    }
+    !in_scope || !any_object(object, has_arguments)
+}
+
+/// The code of an object without its modifier. Subtrees with the same key
+/// generate the same code, so they can share a module
+fn subtree_key(object: &ScadObject, options: &FormatOptions) -> Option<String> {
+    let code = object.try_get_code_with(options).ok()?;
+    match object.get_modifier() {
+        Some(modifier) => Some(code[modifier.symbol().len_utf8()..].to_string()),
+        None => Some(code),
+    }
+}
+
+/// Counts the subtrees that could be moved into a module
+fn count_subtrees(
+    object: &ScadObject,
+    options: &FormatOptions,
+    in_scope: bool,
+    counts: &mut BTreeMap<String, usize>,
+) {
+    //Single objects are as short as a module call, so only trees are counted
+    if !object.children().is_empty() && can_move(object, in_scope) {
+        if let Some(key) = subtree_key(object, options) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    let in_scope = in_scope || introduces_variables(object.element());
+    for child in object.children() {
+        count_subtrees(child, options, in_scope, counts);
+    }
+}
+
+/// Replaces every movable subtree with code `key` by a call of the module
+/// `name` and returns one of the replaced subtrees
+fn replace_subtrees(
+    objects: &mut [ScadObject],
+    key: &str,
+    name: &str,
+    options: &FormatOptions,
+    in_scope: bool,
+) -> Option<ScadObject> {
+    let mut replaced = None;
+    for object in objects.iter_mut() {
+        let matches = !object.children().is_empty()
+            && can_move(object, in_scope)
+            && subtree_key(object, options).is_some_and(|code| code == key);
+        if matches {
+            let mut call = ScadObject::new(ScadElement::Call(ScadCall::new(name)));
+            call.set_modifier(object.get_modifier());
+            let mut original = mem::replace(object, call);
+            original.set_modifier(None);
+            replaced = Some(original);
+        } else {
+            let in_scope = in_scope || introduces_variables(object.element());
+            if let Some(original) =
+                replace_subtrees(object.children_mut(), key, name, options, in_scope)
+            {
+                replaced = Some(original);
+            }
+        }
+    }
+    replaced
+}
+
+/// Moves repeated subtrees of `objects` and the module bodies into new
+/// modules, largest first. Returns the number of modules that were added
+pub(crate) fn deduplicate(
+    objects: &mut [ScadObject],
+    modules: &mut Vec<ScadModule>,
+    options: &FormatOptions,
+) -> usize {
+    //The layout doesn't matter for comparing, so the shortest one is used
+    let options = FormatOptions {
+        elide_single_child_braces: true,
+        compact: true,
+        ..options.clone()
+    };
+
+    let mut added = 0;
+    loop {
+        let mut counts = BTreeMap::new();
+        for object in objects.iter() {
+            count_subtrees(object, &options, false, &mut counts);
+        }
+        for module in modules.iter() {
+            for object in &module.body {
+                count_subtrees(object, &options, !module.params.is_empty(), &mut counts);
+            }
+        }
+        let key = match counts
+            .into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(key, _)| key)
+            .max_by_key(|key| key.len())
+        {
+            Some(key) => key,
+            None => return added,
+        };
+
+        let name = (0..)
+            .map(|i| format!("shared_{}", i))
+            .find(|name| modules.iter().all(|module| module.name != *name))
+            .unwrap_or_default();
+        let mut body = replace_subtrees(objects, &key, &name, &options, false);
+        for module in modules.iter_mut() {
+            let in_scope = !module.params.is_empty();
+            body = replace_subtrees(&mut module.body, &key, &name, &options, in_scope).or(body);
+        }
+
+        modules.push(ScadModule {
+            name,
+            params: Vec::new(),
+            body: body.into_iter().collect(),
+        });
+        added += 1;
+    }
+}
+
+#[cfg(test)]
+mod module_tests {
+    use super::*;
+    use crate::scad_element::*;
+    use crate::scad_error::ScadError;
+    use crate::scad_format::FormatOptions;
+    use nalgebra as na;
+
+    fn post() -> ScadModule {
+        let mut post = ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 0., 1.)));
+        post.add_child(ScadObject::new(ScadElement::Children(ChildSelection::All)));
+        ScadModule {
+            name: String::from("post"),
+            params: vec![
+                (String::from("h"), None),
+                (String::from("d"), Some(ScadExpr::var("h") / 2)),
+            ],
+            body: vec![
+                ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.))),
+                post,
+            ],
+        }
+    }
+
+    #[test]
+    fn definition_test() {
+        assert_eq!(
+            post().get_code(),
+            "module post(h,d=h/2)\n{\n\tcube([1,1,1]);\n\ttranslate([0,0,1])\n\t{\n\t\tchildren();\n\t}\n}"
+        );
+        let mut code = String::new();
+        post()
+            .write_code(&mut ScadWriter::with_options(
+                &mut code,
+                FormatOptions::compact(),
+            ))
+            .unwrap();
+        assert_eq!(
+            code,
+            "module post(h,d=h/2){cube([1,1,1]);translate([0,0,1])children();}"
+        );
+
+        let empty = ScadModule {
+            name: String::from("nothing"),
+            params: Vec::new(),
+            body: Vec::new(),
+        };
+        assert_eq!(empty.get_code(), "module nothing(){}");
+    }
+
+    #[test]
+    fn invalid_module_test() {
+        let mut module = post();
+        module.name = String::from("2d");
+        match module.try_get_code() {
+            Err(ScadError::InvalidIdentifier(name)) => assert_eq!(name, "2d"),
+            _ => panic!("invalid module name was accepted"),
+        }
+
+        let mut module = post();
+        module.body.insert(0, ScadObject::new(ScadElement::Else));
+        match module.try_get_code() {
+            Err(ScadError::At { path, .. }) => assert_eq!(path, vec![0]),
+            _ => panic!("misplaced else was accepted"),
+        }
+    }
+}
@@ -1,8 +1,268 @@
+use crate::scad_compare::{describe, ApproxEq, ScadDiff};
 use crate::scad_element::*;
-use crate::scad_type::ScadType;
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_expr::ScadExpr;
+use crate::scad_format::{FormatOptions, OpenScadVersion};
+use crate::scad_type::{write_identifier, PendingCall, ScadType, ScadWriter};
+use crate::scad_visit::{walk, ScadFold, ScadVisitor};
 
+use nalgebra as na;
 use std::vec::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/**
+  The modifier characters that OpenSCAD allows in front of an object.
+  They are mostly useful for debugging models.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScadModifier {
+    /// `!`, only the object and its children are rendered
+    Root,
+    /// `#`, the object is rendered as usual and highlighted in the preview
+    Debug,
+    /// `%`, the object is only shown transparently in the preview
+    Background,
+    /// `*`, the object is ignored
+    Disable,
+}
+
+impl ScadModifier {
+    pub fn symbol(self) -> char {
+        match self {
+            ScadModifier::Root => '!',
+            ScadModifier::Debug => '#',
+            ScadModifier::Background => '%',
+            ScadModifier::Disable => '*',
+        }
+    }
+}
+
+/**
+  Extra arguments for the module call of an object.
+
+  `center` is only accepted by elements where `ScadElement::accepts_center`
+  is true. The special variables, like the resolution variables `$fn`, `$fa`
+  and `$fs`, can be given to any object, they then apply to the object and all
+  its children. Setting them on single objects allows mixing fine and coarse
+  detail in one file. `ScadFile::variables_mut` sets them for the whole file.
+
+  ```
+  # use scad::*;
+  let hole = scad!(Cylinder(10., Diameter(3.))).centered().with_detail(48);
+  assert_eq!(hole.get_code(), "cylinder(h=10,d=3,center=true,$fn=48);");
+
+  let mut dome = scad!(Sphere(Radius(50.)));
+  dome.params_mut().min_angle = Some(12.);
+  dome.params_mut().min_size = Some(2.);
+  assert_eq!(dome.get_code(), "sphere(r=50,$fa=12,$fs=2);");
+  ```
+
+  Values in `preview` replace the others while `$preview` is true, which is
+  in the preview of the OpenSCAD GUI. Variables that only have a preview
+  value keep the value they have outside of the object in the render.
+
+  ```
+  # use scad::*;
+  let ball = scad!(Sphere(Radius(5.))).with_detail(96).with_preview_detail(24);
+  assert_eq!(ball.get_code(), "sphere(r=5,$fn=$preview?24:96);");
+  ```
+
+  `expressions` replace the values of the arguments of the element and of
+  the special variables by their OpenSCAD name, like `v` for `translate` or
+  `r` and `h` for `cylinder`. The value of the element is still checked, so
+  it has to be valid even though it isn't written. Names that the element
+  doesn't write are only accepted for special variables, see
+  `ScadObject::with_expression`.
+
+  ```
+  # use scad::*;
+  let wall = ScadExpr::var("wall");
+  let lid = scad!(Translate(vec3(0., 0., 7.5)))
+      .with_expression("v", ScadExpr::vec3(0, 0, wall.clone() + ScadExpr::var("height") / 2))
+      .with_expression("$fn", wall * 8);
+  assert_eq!(lid.get_code(), "translate([0,0,wall+height/2],$fn=wall*8);");
+  ```
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ObjectParams {
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub center: Option<bool>,
+    /// `$fn`, the number of fragments in a full circle
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub detail: Option<u32>,
+    /// `$fa`, the minimum angle of a fragment in degrees
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub min_angle: Option<f64>,
+    /// `$fs`, the minimum size of a fragment
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub min_size: Option<f64>,
+    /// `$t`, the time of the animation from 0 to 1
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub time: Option<f64>,
+    /// `$vpr`, the rotation of the viewport in degrees
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub viewport_rotation: Option<na::Vector3<f64>>,
+    /// `$vpt`, the point the viewport looks at
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub viewport_translation: Option<na::Vector3<f64>>,
+    /// `$vpd`, the distance of the camera from the point it looks at
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub viewport_distance: Option<f64>,
+    /// `$vpf`, the field of view of the camera in degrees. Needs OpenSCAD
+    /// 2021.01
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub viewport_fov: Option<f64>,
+    /// Values that are used instead of the ones above in the preview, which
+    /// needs OpenSCAD 2019.05. The `preview` of these values is ignored
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub preview: Option<Box<ObjectParams>>,
+    /// Expressions that are written instead of the values of the arguments
+    /// with the same name, see `ScadObject::with_expression`
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub expressions: Vec<(String, ScadExpr)>,
+}
+
+impl ObjectParams {
+    pub fn is_empty(&self) -> bool {
+        *self == ObjectParams::default()
+    }
+
+    /**
+      Writes each argument that is set. `start` is called with the name of
+      the argument before its value is written and is responsible for the
+      separators. Returns the number of arguments.
+
+      `expressions` replace the values of the arguments with the same name,
+      the others are written after the special variables.
+    */
+    pub(crate) fn write_each<F>(
+        &self,
+        w: &mut ScadWriter,
+        mut expressions: Vec<(String, ScadExpr)>,
+        mut start: F,
+    ) -> ScadResult<usize>
+    where
+        F: FnMut(&mut ScadWriter, &str) -> ScadResult,
+    {
+        let preview = self.preview.as_deref().cloned().unwrap_or_default();
+
+        let mut count = 0;
+        let mut write = |w: &mut ScadWriter,
+                         name: &str,
+                         fallback: &str,
+                         value: Option<&dyn ScadType>,
+                         preview: Option<&dyn ScadType>| {
+            let expression = expressions
+                .iter()
+                .position(|(arg, _)| arg == name)
+                .map(|index| expressions.remove(index).1);
+            if expression.is_none() && value.is_none() && preview.is_none() {
+                return Ok(());
+            }
+            count += 1;
+            start(w, name)?;
+            if name == "$vpf" {
+                w.require("$vpf", OpenScadVersion::V2021_01)?;
+            }
+            let (value, preview) = match (expression, value, preview) {
+                (Some(expression), _, _) => return expression.write_code(w),
+                (None, Some(value), None) => return value.write_code(w),
+                (None, value, Some(preview)) => (value, preview),
+                (None, None, None) => return Ok(()),
+            };
+            w.require("$preview", OpenScadVersion::V2019_05)?;
+            w.write_str("$preview?")?;
+            preview.write_code(w)?;
+            w.write_char(':')?;
+            match value {
+                Some(value) => value.write_code(w),
+                None => w.write_str(fallback),
+            }
+        };
+
+        macro_rules! variable {
+            ($field:ident, $name:expr, $fallback:expr) => {
+                write(
+                    w,
+                    $name,
+                    $fallback,
+                    self.$field.as_ref().map(|value| value as &dyn ScadType),
+                    preview.$field.as_ref().map(|value| value as &dyn ScadType),
+                )?
+            };
+        }
+        variable!(center, "center", "false");
+        variable!(detail, "$fn", "$fn");
+        variable!(min_angle, "$fa", "$fa");
+        variable!(min_size, "$fs", "$fs");
+        variable!(time, "$t", "$t");
+        variable!(viewport_rotation, "$vpr", "$vpr");
+        variable!(viewport_translation, "$vpt", "$vpt");
+        variable!(viewport_distance, "$vpd", "$vpd");
+        variable!(viewport_fov, "$vpf", "$vpf");
+
+        for (name, expression) in expressions {
+            count += 1;
+            start(w, &name)?;
+            expression.write_code(w)?;
+        }
+        Ok(count)
+    }
+}
+
+impl ApproxEq for ObjectParams {
+    fn approx_eq(&self, other: &ObjectParams, epsilon: f64) -> bool {
+        let previews_equal = match (&self.preview, &other.preview) {
+            (Some(a), Some(b)) => a.approx_eq(b, epsilon),
+            (None, None) => true,
+            _ => false,
+        };
+        self.center == other.center
+            && self.detail == other.detail
+            && self.min_angle.approx_eq(&other.min_angle, epsilon)
+            && self.min_size.approx_eq(&other.min_size, epsilon)
+            && self.time.approx_eq(&other.time, epsilon)
+            && self
+                .viewport_rotation
+                .approx_eq(&other.viewport_rotation, epsilon)
+            && self
+                .viewport_translation
+                .approx_eq(&other.viewport_translation, epsilon)
+            && self
+                .viewport_distance
+                .approx_eq(&other.viewport_distance, epsilon)
+            && self.viewport_fov.approx_eq(&other.viewport_fov, epsilon)
+            && previews_equal
+            && self.expressions.len() == other.expressions.len()
+            && self
+                .expressions
+                .iter()
+                .zip(other.expressions.iter())
+                .all(|((a_name, a), (b_name, b))| a_name == b_name && a.approx_eq(b, epsilon))
+    }
+}
+
+/// Writes the arguments that are set, separated by commas
+impl ScadType for ObjectParams {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        let mut first = true;
+        self.write_each(w, self.expressions.clone(), |w, name| {
+            if !first {
+                w.write_char(',')?;
+            }
+            first = false;
+            write_identifier(w, name)?;
+            w.write_char('=')
+        })?;
+        Ok(())
+    }
+}
+
 /**
   An scad object which is a single scad element and can have zero or more child objects
 
@@ -36,16 +296,61 @@ use std::vec::*;
   more children to an scad object created by the macro, you can simply use the
   `add_child` function on the result of the macro.
 */
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "ScadObjectData"))]
 pub struct ScadObject {
     element: ScadElement,
 
+    #[cfg_attr(feature = "serde", serde(default))]
     children: Vec<ScadObject>,
 
-    //Decides wether or not the object should be drawn alone (by adding ! before)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "ObjectParams::is_empty")
+    )]
+    params: ObjectParams,
+
+    //Written in front of the object, for example ! to draw it alone
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    modifier: Option<ScadModifier>,
+}
+
+/// The deserialized form of `ScadObject`. Also reads the `important` flag that
+/// was written before there were other modifiers
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct ScadObjectData {
+    element: ScadElement,
+    #[serde(default)]
+    children: Vec<ScadObject>,
+    #[serde(default)]
+    params: ObjectParams,
+    #[serde(default)]
+    modifier: Option<ScadModifier>,
+    #[serde(default)]
     important: bool,
 }
 
+#[cfg(feature = "serde")]
+impl From<ScadObjectData> for ScadObject {
+    fn from(data: ScadObjectData) -> ScadObject {
+        let modifier = match data.modifier {
+            None if data.important => Some(ScadModifier::Root),
+            modifier => modifier,
+        };
+        ScadObject {
+            element: data.element,
+            children: data.children,
+            params: data.params,
+            modifier,
+        }
+    }
+}
+
 impl ScadObject {
     pub fn new(element: ScadElement) -> ScadObject {
         ScadObject {
@@ -53,7 +358,9 @@ impl ScadObject {
 
             children: Vec::new(),
 
-            important: false,
+            params: ObjectParams::default(),
+
+            modifier: None,
         }
     }
 
@@ -61,12 +368,114 @@ impl ScadObject {
         self.children.push(statement);
     }
 
+    pub fn element(&self) -> &ScadElement {
+        &self.element
+    }
+
+    pub fn element_mut(&mut self) -> &mut ScadElement {
+        &mut self.element
+    }
+
+    pub fn set_element(&mut self, element: ScadElement) {
+        self.element = element;
+    }
+
+    pub fn children(&self) -> &[ScadObject] {
+        &self.children
+    }
+
+    /// Mutable access to the children, for inserting, removing or reordering them
+    pub fn children_mut(&mut self) -> &mut Vec<ScadObject> {
+        &mut self.children
+    }
+
+    pub fn params(&self) -> &ObjectParams {
+        &self.params
+    }
+
+    pub fn params_mut(&mut self) -> &mut ObjectParams {
+        &mut self.params
+    }
+
+    /// Takes ownership over the object, adds `center=true` and returns it
+    pub fn centered(mut self) -> ScadObject {
+        self.params.center = Some(true);
+        self
+    }
+
+    /// Takes ownership over the object, sets `$fn` for it and its children and returns it
+    pub fn with_detail(mut self, detail: u32) -> ScadObject {
+        self.params.detail = Some(detail);
+        self
+    }
+
+    /// Takes ownership over the object, sets the `$fn` that is used in the
+    /// preview instead of the one from `with_detail` and returns it
+    pub fn with_preview_detail(mut self, detail: u32) -> ScadObject {
+        self.params
+            .preview
+            .get_or_insert_with(Default::default)
+            .detail = Some(detail);
+        self
+    }
+
+    /**
+      Takes ownership over the object, sets an expression that is written
+      instead of the value of the argument `argument` and returns it.
+
+      Special variables like `$fn` that the element doesn't write are added
+      to the call, and so are any arguments of `Call`. Other arguments have
+      to be arguments of the element, optional ones are written when they
+      have an expression even if they have no value. Writing the object
+      fails with `ScadError::UnsupportedArgument` otherwise, which also
+      catches misspelled names.
+    */
+    pub fn with_expression<T: Into<ScadExpr>>(
+        mut self,
+        argument: &str,
+        expression: T,
+    ) -> ScadObject {
+        let expressions = &mut self.params.expressions;
+        expressions.retain(|(name, _)| name != argument);
+        expressions.push((argument.to_string(), expression.into()));
+        self
+    }
+
+    pub fn get_modifier(&self) -> Option<ScadModifier> {
+        self.modifier
+    }
+
+    pub fn set_modifier(&mut self, modifier: Option<ScadModifier>) {
+        self.modifier = modifier;
+    }
+
+    /// Takes ownership over the object, sets its modifier and returns it
+    pub fn with_modifier(mut self, modifier: ScadModifier) -> ScadObject {
+        self.modifier = Some(modifier);
+        self
+    }
+
+    /// Returns true if the object is drawn alone, see `important`
+    pub fn is_marked_important(&self) -> bool {
+        self.modifier == Some(ScadModifier::Root)
+    }
+
+    /// Adds or removes the `!` modifier. Removing it leaves other modifiers alone.
+    pub fn set_important(&mut self, important: bool) {
+        if important {
+            self.modifier = Some(ScadModifier::Root);
+        } else if self.is_marked_important() {
+            self.modifier = None;
+        }
+    }
+
     /**
       Marks the object as important. This will prepend the object code
       with an ! which tells scad to only render that object and its children.
+      Same as `set_modifier(Some(ScadModifier::Root))`.
     */
     pub fn is_important(&mut self) {
-        self.important = true;
+        self.modifier = Some(ScadModifier::Root);
     }
 
     /**
@@ -74,55 +483,200 @@ impl ScadObject {
       Usefull if you want to mark something as important without having to
       change the binding to mut
     */
-    pub fn important(mut self) -> ScadObject {
-        self.important = true;
-        self
+    pub fn important(self) -> ScadObject {
+        self.with_modifier(ScadModifier::Root)
+    }
+
+    /// Takes ownership over the object and highlights it with `#`
+    pub fn highlight(self) -> ScadObject {
+        self.with_modifier(ScadModifier::Debug)
+    }
+
+    /// Takes ownership over the object and makes it a transparent background object with `%`
+    pub fn background(self) -> ScadObject {
+        self.with_modifier(ScadModifier::Background)
+    }
+
+    /// Takes ownership over the object and disables it with `*`
+    pub fn disable(self) -> ScadObject {
+        self.with_modifier(ScadModifier::Disable)
+    }
+
+    /// Walks over the object and all its children, see `ScadVisitor`
+    pub fn visit<V: ScadVisitor + ?Sized>(&self, visitor: &mut V) {
+        walk(self, visitor, 0, &mut Vec::new());
+    }
+
+    /// Rebuilds the object and its children with `folder`, see `ScadFold`
+    pub fn fold<F: ScadFold + ?Sized>(self, folder: &mut F) -> ScadObject {
+        folder.fold_object(self)
+    }
+
+    /**
+      Returns the scad code for the object laid out according to `options`
+
+      ## Panics
+      Panics if the object can't be represented in OpenSCAD, use
+      `try_get_code_with` to handle that case.
+    */
+    pub fn get_code_with(&self, options: &FormatOptions) -> String {
+        self.try_get_code_with(options)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the scad code for the object laid out according to `options`,
+    /// or the reason why it can't be represented in OpenSCAD
+    pub fn try_get_code_with(&self, options: &FormatOptions) -> ScadResult<String> {
+        let mut result = String::new();
+        self.write_code(&mut ScadWriter::with_options(&mut result, options.clone()))?;
+        Ok(result)
+    }
+
+    /**
+      Compares the trees like `approx_eq` and returns where they differ first.
+      Used by `assert_scad_eq!` to print readable assertion failures.
+    */
+    pub fn first_difference(&self, other: &ScadObject, epsilon: f64) -> Option<ScadDiff> {
+        if !self.element.approx_eq(&other.element, epsilon) {
+            return Some(ScadDiff::new(
+                describe(&self.element),
+                describe(&other.element),
+            ));
+        }
+        if !self.params.approx_eq(&other.params, epsilon) {
+            return Some(ScadDiff::new(
+                format!("arguments ({})", describe(&self.params)),
+                format!("arguments ({})", describe(&other.params)),
+            ));
+        }
+        if self.modifier != other.modifier {
+            let describe_modifier = |modifier: Option<ScadModifier>| match modifier {
+                Some(modifier) => format!("modifier {}", modifier.symbol()),
+                None => String::from("no modifier"),
+            };
+            return Some(ScadDiff::new(
+                describe_modifier(self.modifier),
+                describe_modifier(other.modifier),
+            ));
+        }
+
+        for (i, (a, b)) in self.children.iter().zip(other.children.iter()).enumerate() {
+            if let Some(diff) = a.first_difference(b, epsilon) {
+                return Some(diff.in_child(i));
+            }
+        }
+        if self.children.len() != other.children.len() {
+            return Some(ScadDiff::new(
+                format!("{} children", self.children.len()),
+                format!("{} children", other.children.len()),
+            ));
+        }
+
+        None
+    }
+}
+
+/// Makes sure that an `Else` object directly follows an `If` object
+pub(crate) fn check_else(previous: Option<&ScadObject>, object: &ScadObject) -> ScadResult {
+    if object.element != ScadElement::Else {
+        return Ok(());
+    }
+    match previous {
+        Some(previous) if matches!(previous.element, ScadElement::If(_)) => {}
+        _ => return Err(ScadError::MisplacedElse),
+    }
+    if object.modifier.is_some() {
+        return Err(ScadError::MisplacedElse);
+    }
+    Ok(())
+}
+
+impl ApproxEq for ScadObject {
+    fn approx_eq(&self, other: &ScadObject, epsilon: f64) -> bool {
+        self.first_difference(other, epsilon).is_none()
     }
 }
 
 impl ScadType for ScadObject {
     /**
-      Returns the scad code for the object.
+      Writes the scad code for the object.
 
       If there are no children, only the code for the ScadElement of the
-      object followed by a `;` is returned. If children exist, the code for
-      the element is returned first, followed by the code for each child surrounded
-      by `{}` and indented 1 tab character.
+      object followed by a `;` is written. If children exist, the code for
+      the element is written first, followed by the code for each child surrounded
+      by `{}` and indented one level. The `FormatOptions` of the writer decide
+      how the indentation looks and if the braces can be left out.
+
+      Errors in children are wrapped in `ScadError::At` with the index of the child.
     */
-    fn get_code(&self) -> String {
-        let mut result: String;
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        if let Some(modifier) = self.modifier {
+            w.write_char(modifier.symbol())?;
+        }
 
-        //Get the code for the current element
-        result = self.element.clone().get_code();
+        //Write the code for the current element, which adds the params to
+        //its call
+        let call = match self.element.keyword() {
+            Some(keyword) => {
+                //Fails before the value of the first argument is written
+                self.params
+                    .write_each(w, self.params.expressions.clone(), |_, name| {
+                        Err(ScadError::UnsupportedArgument {
+                            module: keyword.to_string(),
+                            argument: name.to_string(),
+                        })
+                    })?;
+                None
+            }
+            None => Some(PendingCall::new(
+                self.params.clone(),
+                self.element.accepts_center(),
+                matches!(self.element, ScadElement::Call(_)),
+            )),
+        };
+        w.start_call(call);
+        self.element.write_code(w)?;
 
-        if self.important {
-            result = String::from("!") + &result;
+        //Adding the code for all children, or ; if none exist
+        if self.children.is_empty() {
+            return w.write_char(';');
         }
 
-        //Adding the code for all children, or ; if none exist
-        result = result
-            + &(match self.children.len() {
-                0 => String::from(";"),
-                _ => {
-                    let mut child_code = String::from("\n{\n");
-                    for stmt in &self.children {
-                        //Add the children indented one line
-                        child_code = child_code + "\t" + &(stmt.get_code().replace("\n", "\n\t"));
-                        child_code += "\n";
-                    }
-
-                    //Add the final bracket and 'return' the result
-                    child_code + "}"
-                }
-            });
+        //The braces of an if are kept so that an else after it can't be
+        //mistaken for the else of an if inside it
+        let is_if = matches!(self.element, ScadElement::If(_));
+        if self.children.len() == 1 && w.options().elide_single_child_braces && !is_if {
+            match self.element {
+                ScadElement::Else => w.write_char(' ')?,
+                _ => w.space()?,
+            }
+            check_else(None, &self.children[0]).map_err(|e| e.in_child(0))?;
+            return self.children[0].write_code(w).map_err(|e| e.in_child(0));
+        }
+
+        w.newline()?;
+        w.write_char('{')?;
+        w.indent();
+        for (i, stmt) in self.children.iter().enumerate() {
+            //Add the children indented one level
+            w.newline()?;
+            check_else(i.checked_sub(1).map(|i| &self.children[i]), stmt)
+                .map_err(|e| e.in_child(i))?;
+            stmt.write_code(w).map_err(|e| e.in_child(i))?;
+        }
+        w.dedent();
 
-        result
+        //Add the final bracket
+        w.newline()?;
+        w.write_char('}')
     }
 }
 
 #[cfg(test)]
 mod statement_tests {
     use super::*;
+    use crate::scad_import::ImportParams;
+    use crate::scad_library::ScadCall;
     use nalgebra as na;
 
     #[test]
@@ -149,4 +703,464 @@ mod statement_tests {
         let test_2 = ScadObject::new(ScadElement::Union).important();
         assert_eq!(test_2.get_code(), "!union();");
     }
+
+    #[test]
+    fn modifier_test() {
+        let mut object = ScadObject::new(ScadElement::Union);
+        object.add_child(ScadObject::new(ScadElement::Hull).highlight());
+        object.add_child(ScadObject::new(ScadElement::Minkowski).background());
+        object.add_child(ScadObject::new(ScadElement::Difference).disable());
+        assert_eq!(
+            object.get_code(),
+            "union()\n{\n\t#hull();\n\t%minkowski();\n\t*difference();\n}"
+        );
+
+        object.set_important(false);
+        assert_eq!(object.get_modifier(), None);
+        object.set_modifier(Some(ScadModifier::Debug));
+        object.set_important(false);
+        assert_eq!(object.get_modifier(), Some(ScadModifier::Debug));
+        object.is_important();
+        assert!(object.is_marked_important());
+        object.set_important(false);
+        assert_eq!(object.get_modifier(), None);
+
+        let options = FormatOptions {
+            elide_single_child_braces: true,
+            ..Default::default()
+        };
+        let mut parent = ScadObject::new(ScadElement::Hull).background();
+        parent.add_child(ScadObject::new(ScadElement::Union).highlight());
+        assert_eq!(parent.get_code_with(&options), "%hull() #union();");
+    }
+
+    #[test]
+    fn params_test() {
+        let cube = ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 2., 3.))).centered();
+        assert_eq!(cube.get_code(), "cube([1,2,3],center=true);");
+
+        let mut cone = ScadObject::new(ScadElement::Cone(
+            2.,
+            CircleType::Radius(1.),
+            CircleType::Radius(0.),
+        ));
+        cone.params_mut().center = Some(false);
+        cone.params_mut().min_size = Some(0.5);
+        assert_eq!(
+            cone.get_code(),
+            "cylinder(h=2,r1=1,r2=0,center=false,$fs=0.5);"
+        );
+
+        let mut union = ScadObject::new(ScadElement::Union).with_detail(6);
+        union.add_child(
+            ScadObject::new(ScadElement::Circle(CircleType::Radius(1.))).with_detail(100),
+        );
+        assert_eq!(
+            union.get_code_with(&FormatOptions::compact()),
+            "union($fn=6)circle(r=1,$fn=100);"
+        );
+
+        let extrude =
+            ScadObject::new(ScadElement::LinearExtrude(Default::default())).with_detail(8);
+        assert_eq!(
+            extrude.get_code(),
+            "linear_extrude(height=1,center=false,convexity=10,twist=0,slices=1,$fn=8);"
+        );
+
+        match ScadObject::new(ScadElement::Sphere(CircleType::Radius(1.)))
+            .centered()
+            .try_get_code()
+        {
+            Err(ScadError::UnsupportedArgument { module, argument }) => {
+                assert_eq!(module, "sphere");
+                assert_eq!(argument, "center");
+            }
+            _ => panic!("center on a sphere was accepted"),
+        }
+        match ScadObject::new(ScadElement::Circle(CircleType::Radius(f64::NAN)))
+            .with_detail(3)
+            .try_get_code()
+        {
+            Err(ScadError::NonFiniteNumber(_)) => {}
+            _ => panic!("errors of the element were not reported"),
+        }
+        let mut conditional = ScadObject::new(ScadElement::If(ScadExpr::var("a")));
+        conditional.params_mut().min_size = Some(f64::NAN);
+        match conditional.try_get_code() {
+            Err(ScadError::UnsupportedArgument { module, argument }) => {
+                assert_eq!(module, "if");
+                assert_eq!(argument, "$fs");
+            }
+            _ => panic!("$fs on an if was accepted"),
+        }
+
+        let call = ScadObject::new(ScadElement::Call(ScadCall::new("label").named("$fn", 5)));
+        match call.clone().with_detail(8).try_get_code() {
+            Err(ScadError::DuplicateArgument { module, argument }) => {
+                assert_eq!(module, "label");
+                assert_eq!(argument, "$fn");
+            }
+            _ => panic!("$fn was written twice"),
+        }
+        assert_eq!(call.with_expression("$fn", 8).get_code(), "label($fn=8);");
+        assert_eq!(
+            ScadObject::new(ScadElement::Text(TextParams::new("a")))
+                .with_detail(8)
+                .get_code(),
+            "text(\"a\",size=10,halign=\"left\",valign=\"baseline\",spacing=1,$fn=8);"
+        );
+
+        let a = ScadObject::new(ScadElement::Union).with_detail(6);
+        assert_eq!(
+            a.first_difference(&a.clone().centered(), 0.).unwrap().to_string(),
+            "trees differ at the root\n  left:  arguments ($fn=6)\n  right: arguments (center=true,$fn=6)"
+        );
+    }
+
+    #[test]
+    fn special_variables_test() {
+        let mut camera = ScadObject::new(ScadElement::Union);
+        camera.params_mut().time = Some(0.25);
+        camera.params_mut().viewport_rotation = Some(na::Vector3::new(55., 0., 25.));
+        camera.params_mut().viewport_translation = Some(na::Vector3::new(0., 0., 10.));
+        camera.params_mut().viewport_distance = Some(200.);
+        camera.params_mut().viewport_fov = Some(22.5);
+        assert_eq!(
+            camera.get_code(),
+            "union($t=0.25,$vpr=[55,0,25],$vpt=[0,0,10],$vpd=200,$vpf=22.5);"
+        );
+
+        let mut cube = ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.)))
+            .centered()
+            .with_preview_detail(12);
+        cube.params_mut().preview.as_mut().unwrap().center = Some(false);
+        assert_eq!(
+            cube.get_code(),
+            "cube([1,1,1],center=$preview?false:true,$fn=$preview?12:$fn);"
+        );
+
+        let mut sphere = ScadObject::new(ScadElement::Sphere(CircleType::Radius(1.)));
+        sphere.params_mut().preview = Some(Box::new(ObjectParams {
+            min_size: Some(f64::INFINITY),
+            ..Default::default()
+        }));
+        match sphere.try_get_code() {
+            Err(ScadError::NonFiniteNumber(_)) => {}
+            _ => panic!("invalid preview value was accepted"),
+        }
+        assert!(!sphere.params().is_empty());
+
+        let target = |target| FormatOptions {
+            target,
+            ..Default::default()
+        };
+        match cube.try_get_code_with(&target(OpenScadVersion::V2015_03)) {
+            Err(ScadError::UnsupportedVersion { feature, .. }) => assert_eq!(feature, "$preview"),
+            _ => panic!("$preview was accepted for OpenSCAD 2015.03"),
+        }
+        assert!(cube
+            .try_get_code_with(&target(OpenScadVersion::V2019_05))
+            .is_ok());
+        match camera.try_get_code_with(&target(OpenScadVersion::V2019_05)) {
+            Err(ScadError::UnsupportedVersion { feature, .. }) => assert_eq!(feature, "$vpf"),
+            _ => panic!("$vpf was accepted for OpenSCAD 2019.05"),
+        }
+        let fov = ScadObject::new(ScadElement::Union).with_expression("$vpf", 30);
+        assert!(fov
+            .try_get_code_with(&target(OpenScadVersion::V2019_05))
+            .is_err());
+        assert!(fov
+            .try_get_code_with(&target(OpenScadVersion::V2021_01))
+            .is_ok());
+    }
+
+    #[test]
+    fn expressions_test() {
+        let r = ScadExpr::var("r");
+        let cone = ScadObject::new(ScadElement::Cone(
+            10.,
+            CircleType::Radius(1.),
+            CircleType::Diameter(1.),
+        ))
+        .with_expression("h", r.clone() * 2)
+        .with_expression("d2", 0)
+        .with_expression("center", r.clone().gt(5))
+        .with_detail(12)
+        .with_expression("$fn", 24);
+        assert_eq!(
+            cone.get_code(),
+            "cylinder(h=r*2,r1=1,d2=0,center=r>5,$fn=24);"
+        );
+
+        //Optional arguments are written when they have an expression
+        let extrude = ScadObject::new(ScadElement::LinearExtrude(LinExtrudeParams {
+            v: Some(na::Vector3::new(0., 0., 1.)),
+            ..Default::default()
+        }))
+        .with_expression("twist", ScadExpr::var("$t") * 360)
+        .with_expression("v", ScadExpr::vec3(0, ScadExpr::var("tilt"), 1))
+        .with_expression("segments", 4)
+        .with_expression("scale", r.clone());
+        let snapshot = FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..Default::default()
+        };
+        assert_eq!(
+            extrude.get_code_with(&snapshot),
+            "linear_extrude(height=1,v=[0,tilt,1],center=false,convexity=10,twist=$t*360,slices=1,segments=4,scale=r);"
+        );
+        let import = ScadObject::new(ScadElement::ImportFile(ImportParams::new("a.stl")))
+            .with_expression("file", ScadExpr::var("path"))
+            .with_expression("convexity", r.clone());
+        assert_eq!(import.get_code(), "import(file=path,convexity=r);");
+        match ScadObject::new(ScadElement::LinearExtrude(Default::default()))
+            .with_expression("segments", 4)
+            .try_get_code()
+        {
+            Err(ScadError::UnsupportedVersion { feature, .. }) => {
+                assert_eq!(feature, "linear_extrude with segments")
+            }
+            _ => panic!("segments were accepted for OpenSCAD 2021.01"),
+        }
+
+        let call = ScadObject::new(ScadElement::Call(ScadCall::new("cuboid").named("size", 1)))
+            .with_expression("rounding", r.clone() / 2);
+        assert_eq!(call.get_code(), "cuboid(size=1,rounding=r/2);");
+
+        let union = ScadObject::new(ScadElement::Union).with_expression("$fa", r.clone());
+        assert_eq!(union.get_code(), "union($fa=r);");
+
+        let rotate = ScadObject::new(ScadElement::Rotate2d(0.))
+            .with_expression("a", 1)
+            .with_expression("a", r.clone());
+        assert_eq!(rotate.get_code(), "rotate(r);");
+
+        let invalid = vec![
+            ScadObject::new(ScadElement::Sphere(CircleType::Radius(1.)))
+                .with_expression("center", true),
+            ScadObject::new(ScadElement::If(ScadExpr::var("a"))).with_expression("a", r.clone()),
+            ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 0., 0.)))
+                .with_expression("x", r.clone()),
+            ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.)))
+                .with_expression("sise", r.clone()),
+        ];
+        for object in invalid {
+            match object.try_get_code() {
+                Err(ScadError::UnsupportedArgument { .. }) => {}
+                _ => panic!("{:?} was accepted", object),
+            }
+        }
+        match ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.)))
+            .with_expression("sise", r.clone())
+            .try_get_code()
+        {
+            Err(ScadError::UnsupportedArgument { module, argument }) => {
+                assert_eq!(module, "cube");
+                assert_eq!(argument, "sise");
+            }
+            _ => panic!("a misspelled argument was accepted"),
+        }
+        assert!(ScadObject::new(ScadElement::Union)
+            .with_expression("a b", r)
+            .try_get_code()
+            .is_err());
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let cube = || ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.)));
+        let mut branch = ScadObject::new(ScadElement::If(ScadExpr::var("a")));
+        branch.add_child(cube());
+        let mut otherwise = ScadObject::new(ScadElement::Else);
+        otherwise.add_child(cube());
+
+        let mut union = ScadObject::new(ScadElement::Union);
+        union.add_child(branch.clone());
+        union.add_child(otherwise.clone());
+        assert_eq!(
+            union.get_code(),
+            "union()\n{\n\tif(a)\n\t{\n\t\tcube([1,1,1]);\n\t}\n\telse\n\t{\n\t\tcube([1,1,1]);\n\t}\n}"
+        );
+        assert_eq!(
+            union.get_code_with(&FormatOptions::compact()),
+            "union(){if(a){cube([1,1,1]);}else cube([1,1,1]);}"
+        );
+
+        //An if as the only child of an else is written as else if
+        let mut chain = ScadObject::new(ScadElement::Else);
+        chain.add_child(branch.clone());
+        union.children_mut()[1] = chain;
+        assert_eq!(
+            union.get_code_with(&FormatOptions::compact()),
+            "union(){if(a){cube([1,1,1]);}else if(a){cube([1,1,1]);}}"
+        );
+
+        union.children_mut().reverse();
+        match union.try_get_code() {
+            Err(ScadError::At { path, error }) => {
+                assert_eq!(path, vec![0]);
+                assert!(matches!(*error, ScadError::MisplacedElse));
+            }
+            _ => panic!("else before an if was accepted"),
+        }
+
+        let mut single = ScadObject::new(ScadElement::Union);
+        single.add_child(otherwise.clone());
+        assert!(single.try_get_code().is_err());
+
+        let mut highlighted = ScadObject::new(ScadElement::Union);
+        highlighted.add_child(branch.clone());
+        highlighted.add_child(otherwise.highlight());
+        assert!(highlighted.try_get_code().is_err());
+
+        match branch.with_detail(5).try_get_code() {
+            Err(ScadError::UnsupportedArgument { module, argument }) => {
+                assert_eq!(module, "if");
+                assert_eq!(argument, "$fn");
+            }
+            _ => panic!("$fn on an if was accepted"),
+        }
+    }
+
+    #[test]
+    fn nested_indent_test() {
+        let mut inner = ScadObject::new(ScadElement::Union);
+        inner.add_child(ScadObject::new(ScadElement::Hull));
+        inner.add_child(ScadObject::new(ScadElement::Minkowski));
+
+        let mut outer = ScadObject::new(ScadElement::Difference);
+        outer.add_child(inner);
+        outer.add_child(ScadObject::new(ScadElement::Intersection));
+
+        assert_eq!(
+            outer.get_code(),
+            "difference()\n{\n\tunion()\n\t{\n\t\thull();\n\t\tminkowski();\n\t}\n\tintersection();\n}"
+        );
+    }
+
+    #[test]
+    fn error_path_test() {
+        use crate::scad_error::ScadError;
+
+        let mut inner = ScadObject::new(ScadElement::Union);
+        inner.add_child(ScadObject::new(ScadElement::Hull));
+        inner.add_child(ScadObject::new(ScadElement::Color(na::Vector3::new(
+            1., 2., 0.,
+        ))));
+
+        let mut outer = ScadObject::new(ScadElement::Difference);
+        outer.add_child(ScadObject::new(ScadElement::Hull));
+        outer.add_child(inner);
+
+        match outer.try_get_code() {
+            Err(ScadError::At { path, error }) => {
+                assert_eq!(path, vec![1, 1]);
+                match *error {
+                    ScadError::InvalidColor(value) => assert_eq!(value, 2.),
+                    _ => panic!("wrong error"),
+                }
+            }
+            _ => panic!("invalid colour was not reported"),
+        }
+
+        let options = FormatOptions {
+            elide_single_child_braces: true,
+            ..Default::default()
+        };
+        let lone = ScadObject::new(ScadElement::Union).important();
+        let mut parent = ScadObject::new(ScadElement::Hull);
+        parent.add_child(ScadObject::new(ScadElement::Rotate2d(f64::NAN)));
+        assert!(lone.try_get_code_with(&options).is_ok());
+        match parent.try_get_code_with(&options) {
+            Err(ScadError::At { path, .. }) => assert_eq!(path, vec![0]),
+            _ => panic!("NaN was not reported"),
+        }
+    }
+
+    #[test]
+    fn first_difference_test() {
+        let a = nested_object();
+        assert_eq!(a.first_difference(&a.clone(), 0.), None);
+
+        let mut b = nested_object();
+        b.children[0].children[1] = ScadObject::new(ScadElement::Circle(CircleType::Radius(1.)));
+        let diff = a.first_difference(&b, 0.).unwrap();
+        assert_eq!(diff.path, vec![0, 1]);
+        assert_eq!(diff.left, "hull()");
+        assert_eq!(diff.right, "circle(r=1)");
+
+        let mut c = nested_object();
+        c.element = ScadElement::Translate(na::Vector3::new(1., 2., 3.000_000_01));
+        assert_eq!(
+            a.first_difference(&c, 0.).unwrap().to_string(),
+            "trees differ at the root\n  left:  translate([1,2,3])\n  right: translate([1,2,3.00000001])"
+        );
+        assert!(a != c);
+        assert!(a.approx_eq(&c, 1e-6));
+
+        let mut d = nested_object();
+        d.children[0].add_child(ScadObject::new(ScadElement::Union));
+        assert_eq!(
+            a.first_difference(&d, 1.),
+            Some(ScadDiff {
+                path: vec![0],
+                left: String::from("2 children"),
+                right: String::from("3 children")
+            })
+        );
+
+        let e = nested_object().important();
+        assert_eq!(a.first_difference(&e, 1.).unwrap().left, "no modifier");
+        assert_eq!(
+            e.first_difference(&e.clone().disable(), 1.)
+                .unwrap()
+                .to_string(),
+            "trees differ at the root\n  left:  modifier !\n  right: modifier *"
+        );
+    }
+
+    fn nested_object() -> ScadObject {
+        let mut rotate = ScadObject::new(ScadElement::Rotate2d(90.));
+        rotate.add_child(ScadObject::new(ScadElement::Union));
+        rotate.add_child(ScadObject::new(ScadElement::Hull));
+
+        let mut translate = ScadObject::new(ScadElement::Translate(na::Vector3::new(1., 2., 3.)));
+        translate.add_child(rotate);
+        translate
+    }
+
+    #[test]
+    fn format_spaces_test() {
+        assert_eq!(
+            nested_object().get_code_with(&FormatOptions::spaces(4)),
+            "translate([1,2,3])\n{\n    rotate(90)\n    {\n        union();\n        hull();\n    }\n}"
+        );
+    }
+
+    #[test]
+    fn format_elide_test() {
+        let options = FormatOptions {
+            elide_single_child_braces: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            nested_object().get_code_with(&options),
+            "translate([1,2,3]) rotate(90)\n{\n\tunion();\n\thull();\n}"
+        );
+    }
+
+    #[test]
+    fn format_compact_test() {
+        assert_eq!(
+            nested_object().get_code_with(&FormatOptions::compact()),
+            "translate([1,2,3])rotate(90){union();hull();}"
+        );
+        assert_eq!(
+            nested_object().get_code_with(&FormatOptions {
+                compact: true,
+                ..Default::default()
+            }),
+            "translate([1,2,3]){rotate(90){union();hull();}}"
+        );
+    }
 }
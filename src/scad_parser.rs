@@ -0,0 +1,3564 @@
+use crate::scad_color::check_color_string;
+use crate::scad_customizer::{CustomizerValue, CustomizerVariable, CustomizerWidget};
+use crate::scad_element::*;
+use crate::scad_expr::{BinaryOp, ScadExpr, UnaryOp};
+use crate::scad_file::ScadFile;
+use crate::scad_import::ImportParams;
+use crate::scad_library::{LibraryImport, ScadCall};
+use crate::scad_module::ScadModule;
+use crate::scad_object::{check_else, ObjectParams, ScadModifier, ScadObject};
+use nalgebra as na;
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+/**
+  Parses OpenSCAD source into a `ScadFile`.
+
+  Everything that can be generated by the crate can be parsed back, other
+  constructs like function definitions or assignments inside blocks are
+  reported as `ParseErrorKind::Unsupported` along with the location of the
+  construct.
+
+  Variables assigned at the top of the file become `CustomizerVariable`s if
+  their value is a number, a boolean, a string or a vector of numbers. Their
+  widget, description and tab are read from the comments around them the way
+  the Customizer of OpenSCAD reads them. Other values are kept as the
+  `expressions` of the file variables. Special variables like `$fn` set the
+  variables of the file, their values can depend on `$preview`.
+
+  Arguments of built in modules that aren't plain values are read as the
+  `ScadExpr`s of the object, see `ScadObject::with_expression`, and the
+  element gets a placeholder value for them. Conditions and values of control
+  flow statements, default values of module parameters and the arguments of
+  calls to modules from libraries or defined in the source are read as
+  `ScadExpr`s as well. Expressions that can't be represented otherwise, like
+  list comprehensions, are kept as `ScadExpr::Code`.
+
+  ```
+  # use scad::*;
+  let file = parse_file("$fn=20;\nsize=5; // [1:10]\ntranslate([1,2,3]) cube(5);").unwrap();
+
+  assert_eq!(file.customizer_variables(), &[CustomizerVariable::new("size", &5).range(1., 10.)]);
+  assert_eq!(
+      file.get_code(),
+      "size=5; // [1:10]\n$fn=20;\ntranslate([1,2,3])\n{\n\tcube([5,5,5]);\n}\n"
+  );
+  ```
+*/
+pub fn parse_file(source: &str) -> Result<ScadFile, ParseError> {
+    let mut parser = Parser::new(source)?;
+    let mut file = ScadFile::new();
+
+    while !parser.at_end() {
+        let span = parser.peek_span();
+        match parser.parse_statement()? {
+            Some(Statement::Object(object)) => {
+                parser.check_else(file.objects().last(), &object, span)?;
+                file.add_object(*object)
+            }
+            Some(Statement::Variables(variables)) => assign(file.variables_mut(), variables),
+            Some(Statement::Assignment(name, value, span)) => match customizer_value(&value) {
+                Some(value) => {
+                    file.add_customizer_variable(parser.customizer_variable(name, value, span))
+                }
+                None => assign_expression(file.variables_mut(), name, value),
+            },
+            Some(Statement::Module(module)) => {
+                file.define_module(&module.name, module.params, module.body)
+            }
+            Some(Statement::Library(library)) => file.add_library(library),
+            None => {}
+        }
+    }
+
+    Ok(file)
+}
+
+/**
+  Parses the source of a single OpenSCAD object, for example the output
+  of `ScadObject::get_code`.
+*/
+pub fn parse_object(source: &str) -> Result<ScadObject, ParseError> {
+    let mut parser = Parser::new(source)?;
+
+    let object = loop {
+        let span = parser.peek_span();
+        match parser.parse_statement()? {
+            Some(Statement::Object(object)) => {
+                parser.check_else(None, &object, span)?;
+                break *object;
+            }
+            Some(Statement::Variables(_)) | Some(Statement::Assignment(..)) => {
+                return Err(parser.error(
+                    ParseErrorKind::Unsupported(String::from("variable assignments")),
+                    span,
+                ))
+            }
+            Some(Statement::Module(_)) => {
+                return Err(parser.error(
+                    ParseErrorKind::Unsupported(String::from("module definitions")),
+                    span,
+                ))
+            }
+            Some(Statement::Library(_)) => {
+                return Err(parser.error(
+                    ParseErrorKind::Unsupported(String::from("use and include statements")),
+                    span,
+                ))
+            }
+            None => {}
+        }
+    };
+
+    if !parser.at_end() {
+        let span = parser.peek_span();
+        return Err(parser.error(
+            ParseErrorKind::UnexpectedToken {
+                found: parser.describe_next(),
+                expected: "end of input after the object",
+            },
+            span,
+        ));
+    }
+
+    Ok(object)
+}
+
+impl FromStr for ScadFile {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<ScadFile, ParseError> {
+        parse_file(source)
+    }
+}
+
+impl FromStr for ScadObject {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<ScadObject, ParseError> {
+        parse_object(source)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Byte range in the parsed source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+/// The different reasons why parsing can fail
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape(String),
+    InvalidNumber(String),
+    UnexpectedToken {
+        found: String,
+        expected: &'static str,
+    },
+    /// Valid OpenSCAD which can't be represented by the crate
+    Unsupported(String),
+    UnknownModule(String),
+    UnknownArgument {
+        module: String,
+        argument: String,
+    },
+    InvalidArgument {
+        module: String,
+        argument: String,
+        expected: &'static str,
+    },
+}
+
+/// Error returned by the parser. `line` and `column` are 1-based and refer to
+/// the start of `span`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ParseErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            ParseErrorKind::InvalidEscape(ref escape) => {
+                write!(f, "invalid escape sequence {}", escape)
+            }
+            ParseErrorKind::InvalidNumber(ref number) => write!(f, "invalid number {}", number),
+            ParseErrorKind::UnexpectedToken {
+                ref found,
+                expected,
+            } => write!(f, "expected {}, found {}", expected, found),
+            ParseErrorKind::Unsupported(ref what) => write!(f, "{} are not supported", what),
+            ParseErrorKind::UnknownModule(ref name) => write!(f, "unknown module {}", name),
+            ParseErrorKind::UnknownArgument {
+                ref module,
+                ref argument,
+            } => write!(f, "{} has no argument {}", module, argument),
+            ParseErrorKind::InvalidArgument {
+                ref module,
+                ref argument,
+                expected,
+            } => write!(
+                f,
+                "argument {} of {} must be {}",
+                argument, module, expected
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl Error for ParseError {}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Symbol(char),
+    /// The `<path>` of a use or include statement
+    Path(String),
+}
+
+fn error_at(source: &str, kind: ParseErrorKind, span: Span) -> ParseError {
+    let before = &source[..span.start];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    ParseError {
+        kind,
+        span,
+        line,
+        column,
+    }
+}
+
+/// The tokens of a source and the spans of its comments
+type Tokens = (Vec<(Token, Span)>, Vec<Span>);
+
+/// Splits the source into tokens. The spans of the comments are returned
+/// separately, they are only read for the annotations of customizer variables
+fn tokenize(source: &str) -> Result<Tokens, ParseError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = source[pos..].chars().next().unwrap();
+
+        match c {
+            c if c.is_whitespace() => pos += c.len_utf8(),
+            '/' if bytes.get(pos + 1) == Some(&b'/') => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                comments.push(Span { start, end: pos });
+            }
+            '/' if bytes.get(pos + 1) == Some(&b'*') => match source[pos + 2..].find("*/") {
+                Some(end) => {
+                    pos += end + 4;
+                    comments.push(Span { start, end: pos });
+                }
+                None => {
+                    return Err(error_at(
+                        source,
+                        ParseErrorKind::UnterminatedComment,
+                        Span {
+                            start,
+                            end: start + 2,
+                        },
+                    ))
+                }
+            },
+            '"' => {
+                let (value, end) = lex_string(source, pos)?;
+                pos = end;
+                tokens.push((Token::Str(value), Span { start, end }));
+            }
+            c if c.is_ascii_digit()
+                || (c == '.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit)) =>
+            {
+                pos = lex_number_end(bytes, pos);
+                let text = &source[start..pos];
+                let span = Span { start, end: pos };
+                match text.parse() {
+                    Ok(value) => tokens.push((Token::Number(value), span)),
+                    Err(_) => {
+                        return Err(error_at(
+                            source,
+                            ParseErrorKind::InvalidNumber(text.to_string()),
+                            span,
+                        ))
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+                pos += 1;
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_')
+                {
+                    pos += 1;
+                }
+                tokens.push((
+                    Token::Ident(source[start..pos].to_string()),
+                    Span { start, end: pos },
+                ));
+            }
+            '<' if matches!(tokens.last(), Some((Token::Ident(keyword), _)) if keyword == "use" || keyword == "include") =>
+            {
+                let end = match source[pos..].find(['>', '\n']) {
+                    Some(end) if source[pos + end..].starts_with('>') => pos + end + 1,
+                    _ => {
+                        return Err(error_at(
+                            source,
+                            ParseErrorKind::UnexpectedToken {
+                                found: String::from("end of line"),
+                                expected: "'>' at the end of the library path",
+                            },
+                            Span {
+                                start,
+                                end: start + 1,
+                            },
+                        ))
+                    }
+                };
+                pos = end;
+                tokens.push((
+                    Token::Path(source[start + 1..end - 1].to_string()),
+                    Span { start, end },
+                ));
+            }
+            c if "()[]{},;=!#%*:+-/?<>&|^.".contains(c) => {
+                pos += 1;
+                tokens.push((Token::Symbol(c), Span { start, end: pos }));
+            }
+            c => {
+                return Err(error_at(
+                    source,
+                    ParseErrorKind::UnexpectedCharacter(c),
+                    Span {
+                        start,
+                        end: start + c.len_utf8(),
+                    },
+                ))
+            }
+        }
+    }
+
+    Ok((tokens, comments))
+}
+
+fn lex_number_end(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() && (bytes[pos].is_ascii_digit() || bytes[pos] == b'.') {
+        pos += 1;
+    }
+    if pos < bytes.len() && (bytes[pos] == b'e' || bytes[pos] == b'E') {
+        let mut exponent = pos + 1;
+        if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+            exponent += 1;
+        }
+        if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+            pos = exponent;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+    pos
+}
+
+/// Reads a string literal starting at the `"` at `start` the way the OpenSCAD
+/// lexer does. Returns the value and the position after the closing quote.
+fn lex_string(source: &str, start: usize) -> Result<(String, usize), ParseError> {
+    let mut value = String::new();
+    let mut chars = source[start + 1..].char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let pos = start + 1 + offset;
+        match c {
+            '"' => return Ok((value, pos + 1)),
+            '\\' => {
+                let (_, escape) = match chars.next() {
+                    Some(escape) => escape,
+                    None => break,
+                };
+                let digits = match escape {
+                    'n' => {
+                        value.push('\n');
+                        continue;
+                    }
+                    't' => {
+                        value.push('\t');
+                        continue;
+                    }
+                    'r' => {
+                        value.push('\r');
+                        continue;
+                    }
+                    '\\' | '"' => {
+                        value.push(escape);
+                        continue;
+                    }
+                    'x' => 2,
+                    'u' => 4,
+                    'U' => 6,
+                    _ => 0,
+                };
+
+                let mut code = String::new();
+                while code.len() < digits {
+                    match chars.peek() {
+                        Some(&(_, d)) if d.is_ascii_hexdigit() => {
+                            code.push(d);
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+
+                let decoded = u32::from_str_radix(&code, 16)
+                    .ok()
+                    .filter(|&v| v != 0 && (escape != 'x' || v <= 0x7f))
+                    .and_then(std::char::from_u32);
+                match decoded {
+                    Some(decoded) if code.len() == digits => value.push(decoded),
+                    _ => {
+                        let end = pos + 1 + escape.len_utf8() + code.len();
+                        return Err(error_at(
+                            source,
+                            ParseErrorKind::InvalidEscape(source[pos..end].to_string()),
+                            Span { start: pos, end },
+                        ));
+                    }
+                }
+            }
+            c => value.push(c),
+        }
+    }
+
+    Err(error_at(
+        source,
+        ParseErrorKind::UnterminatedString,
+        Span {
+            start,
+            end: source.len(),
+        },
+    ))
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Copies the variables that are set in `values` to `variables`
+fn assign(variables: &mut ObjectParams, values: ObjectParams) {
+    macro_rules! assign {
+        ($($field:ident),*) => {
+            $(if values.$field.is_some() {
+                variables.$field = values.$field;
+            })*
+        };
+    }
+    assign!(
+        center,
+        detail,
+        min_angle,
+        min_size,
+        time,
+        viewport_rotation,
+        viewport_translation,
+        viewport_distance,
+        viewport_fov
+    );
+    if let Some(preview) = values.preview {
+        assign(
+            variables.preview.get_or_insert_with(Default::default),
+            *preview,
+        );
+    }
+    for (name, expression) in values.expressions {
+        assign_expression(variables, name, expression);
+    }
+}
+
+/// Sets the expression of a variable, replacing an earlier assignment
+fn assign_expression(variables: &mut ObjectParams, name: String, expression: ScadExpr) {
+    variables
+        .expressions
+        .retain(|(assigned, _)| *assigned != name);
+    variables.expressions.push((name, expression));
+}
+
+/// The value of an assignment that the Customizer can show, `None` for
+/// expressions
+fn customizer_value(value: &ScadExpr) -> Option<CustomizerValue> {
+    match *value {
+        ScadExpr::Number(value) => Some(CustomizerValue::Number(value)),
+        ScadExpr::Bool(value) => Some(CustomizerValue::Bool(value)),
+        ScadExpr::Str(ref value) => Some(CustomizerValue::Str(value.clone())),
+        ScadExpr::Vector(ref values) => values
+            .iter()
+            .map(|value| match *value {
+                ScadExpr::Number(value) => Some(value),
+                _ => None,
+            })
+            .collect::<Option<_>>()
+            .map(CustomizerValue::Vector),
+        _ => None,
+    }
+}
+
+/**
+  Reads the widget annotation behind a customizer variable. Comments that
+  aren't a widget for the type of the value are ordinary comments and give
+  a plain widget.
+*/
+fn parse_widget(text: &str, value: &CustomizerValue) -> CustomizerWidget {
+    let numeric = matches!(
+        *value,
+        CustomizerValue::Number(_) | CustomizerValue::Vector(_)
+    );
+    let options = match text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+    {
+        Some(options) => options,
+        None => {
+            return match *value {
+                _ if numeric => text.parse().map(CustomizerWidget::Step).unwrap_or_default(),
+                CustomizerValue::Str(_) => text
+                    .parse()
+                    .map(CustomizerWidget::MaxLength)
+                    .unwrap_or_default(),
+                _ => CustomizerWidget::Plain,
+            }
+        }
+    };
+
+    if numeric && !options.contains(',') {
+        let numbers = options
+            .split(':')
+            .map(|number| number.trim().parse())
+            .collect::<Result<Vec<f64>, _>>();
+        match numbers.as_deref() {
+            Ok(&[min, max]) => {
+                return CustomizerWidget::Slider {
+                    min,
+                    step: None,
+                    max,
+                }
+            }
+            Ok(&[min, step, max]) => {
+                return CustomizerWidget::Slider {
+                    min,
+                    step: Some(step),
+                    max,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let options = options
+        .split(',')
+        .map(|option| match option.split_once(':') {
+            Some((value, label)) => (value.trim(), Some(label.trim().to_string())),
+            None => (option.trim(), None),
+        });
+    match *value {
+        CustomizerValue::Number(_) => options
+            .map(|(value, label)| value.parse().map(|value| (value, label)))
+            .collect::<Result<_, _>>()
+            .map(CustomizerWidget::Numbers)
+            .unwrap_or_default(),
+        CustomizerValue::Str(_) => CustomizerWidget::Strings(
+            options
+                .map(|(value, label)| (value.to_string(), label))
+                .collect(),
+        ),
+        _ => CustomizerWidget::Plain,
+    }
+}
+
+enum Statement {
+    Object(Box<ScadObject>),
+    /// An assignment of a special variable, with only that variable set
+    Variables(ObjectParams),
+    /// An assignment of any other variable, with the span of the whole statement
+    Assignment(String, ScadExpr, Span),
+    Module(ScadModule),
+    Library(LibraryImport),
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    Undef,
+    Vector(Vec<Value>),
+    /// `[start:step:end]`, ranges without a step have a step of 1
+    Range(f64, f64, f64),
+    /// `$preview ? preview : render`
+    Preview(Box<Value>, Box<Value>),
+    /// A variable, only used for the render value of `Preview` which can
+    /// keep the value of a special variable
+    Variable(String),
+    /// An argument that isn't a value, kept as an expression of the object
+    Expression(ScadExpr),
+}
+
+struct Arg {
+    /// Name of the parameter the argument was passed to, empty until the
+    /// argument has been matched to one
+    name: String,
+    value: Value,
+    span: Span,
+}
+
+/// Symbols that can only appear in a value as part of an expression
+const OPERATORS: &str = "+-*/%^?<>=!&|.";
+
+/// The binary operators of expressions by precedence, from loosest to tightest.
+/// `^` binds tighter than the unary operators and is parsed separately
+const BINARY_OPERATORS: [&[BinaryOp]; 6] = [
+    &[BinaryOp::Or],
+    &[BinaryOp::And],
+    &[BinaryOp::Eq, BinaryOp::Ne],
+    &[BinaryOp::Lt, BinaryOp::Le, BinaryOp::Gt, BinaryOp::Ge],
+    &[BinaryOp::Add, BinaryOp::Sub],
+    &[BinaryOp::Mul, BinaryOp::Div, BinaryOp::Mod],
+];
+
+/// Variables that can be set for a file or an object, see `ObjectParams`
+const SPECIAL_VARIABLES: [&str; 8] = ["$fn", "$fa", "$fs", "$t", "$vpr", "$vpt", "$vpd", "$vpf"];
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+    /// Names of the modules defined in the source. OpenSCAD allows calling
+    /// them before their definition, so they are collected up front
+    modules: Vec<String>,
+    /// Set if the source loads libraries, unknown modules could come from them
+    uses_libraries: bool,
+    /// Spans of the comments in the source
+    comments: Vec<Span>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Result<Parser<'a>, ParseError> {
+        let (tokens, comments) = tokenize(source)?;
+        let modules = tokens
+            .windows(2)
+            .filter_map(|pair| match (&pair[0].0, &pair[1].0) {
+                (Token::Ident(keyword), Token::Ident(name)) if keyword == "module" => {
+                    Some(name.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let uses_libraries = tokens
+            .iter()
+            .any(|(token, _)| matches!(token, Token::Path(_)));
+        Ok(Parser {
+            source,
+            tokens,
+            pos: 0,
+            modules,
+            uses_libraries,
+            comments,
+        })
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|t| &t.0)
+    }
+
+    fn peek_span(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(&(_, span)) => span,
+            None => Span {
+                start: self.source.len(),
+                end: self.source.len(),
+            },
+        }
+    }
+
+    fn describe_next(&self) -> String {
+        match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            Some(Token::Number(value)) => value.to_string(),
+            Some(Token::Str(value)) => format!("{:?}", value),
+            Some(Token::Symbol(c)) => format!("'{}'", c),
+            Some(Token::Path(path)) => format!("<{}>", path),
+            None => String::from("end of input"),
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind, span: Span) -> ParseError {
+        error_at(self.source, kind, span)
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        self.error(
+            ParseErrorKind::UnexpectedToken {
+                found: self.describe_next(),
+                expected,
+            },
+            self.peek_span(),
+        )
+    }
+
+    fn unsupported(&self, what: &str, span: Span) -> ParseError {
+        self.error(ParseErrorKind::Unsupported(what.to_string()), span)
+    }
+
+    fn next_is_symbol(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Symbol(c))
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == keyword)
+    }
+
+    /// Reports an else that doesn't follow an if
+    fn check_else(
+        &self,
+        previous: Option<&ScadObject>,
+        object: &ScadObject,
+        span: Span,
+    ) -> Result<(), ParseError> {
+        check_else(previous, object).map_err(|_| {
+            self.error(
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("else"),
+                    expected: "an if before the else",
+                },
+                span,
+            )
+        })
+    }
+
+    fn expect_symbol(&mut self, c: char, expected: &'static str) -> Result<Span, ParseError> {
+        if self.next_is_symbol(c) {
+            self.pos += 1;
+            Ok(self.tokens[self.pos - 1].1)
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    /// Parses a statement, returns `None` for empty statements
+    fn parse_statement(&mut self) -> Result<Option<Statement>, ParseError> {
+        let start = self.peek_span();
+
+        let mut modifier = None;
+        while let Some(&Token::Symbol(c)) = self.peek() {
+            let next = match c {
+                '!' => ScadModifier::Root,
+                '#' => ScadModifier::Debug,
+                '%' => ScadModifier::Background,
+                '*' => ScadModifier::Disable,
+                ';' if modifier.is_none() => {
+                    self.pos += 1;
+                    return Ok(None);
+                }
+                '{' => return Err(self.unsupported("blocks without a parent module", start)),
+                _ => return Err(self.unexpected("a statement")),
+            };
+            if modifier.is_some_and(|modifier| modifier != next) {
+                return Err(self.unsupported("combined modifiers", start.to(self.peek_span())));
+            }
+            modifier = Some(next);
+            self.pos += 1;
+        }
+
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return Err(self.unexpected("a statement")),
+        };
+
+        if self.peek_at(1) == Some(&Token::Symbol('=')) && modifier.is_none() {
+            return self.parse_assignment(name).map(Some);
+        }
+
+        match name.as_str() {
+            "module" if modifier.is_some() => {
+                return Err(self.unexpected("a module call after the modifier"))
+            }
+            "module" => return self.parse_module_definition().map(Some),
+            "function" => return Err(self.unsupported("function definitions", start)),
+            "use" | "include" if modifier.is_some() => {
+                return Err(self.unexpected("a module call after the modifier"))
+            }
+            "use" | "include" => return self.parse_library().map(Some),
+            "each" => return Err(self.unsupported("each statements", start)),
+            "else" if modifier.is_some() => {
+                return Err(self.unexpected("a module call after the modifier"))
+            }
+            _ => {}
+        }
+
+        let mut object = match name.as_str() {
+            "for" | "if" | "else" | "let" | "echo" | "assert" => self.parse_control_flow(name)?,
+            _ if self.modules.contains(&name) => self.parse_call(name)?,
+            _ if self.uses_libraries && !self.is_builtin(&name) => self.parse_call(name)?,
+            _ => self.parse_module_call(name)?,
+        };
+        object.set_modifier(modifier);
+        Ok(Some(Statement::Object(Box::new(object))))
+    }
+
+    fn parse_assignment(&mut self, name: String) -> Result<Statement, ParseError> {
+        let span = self.peek_span();
+        self.pos += 2;
+
+        if !SPECIAL_VARIABLES.contains(&name.as_str()) {
+            let value = self.parse_expression_before(';')?;
+            let end = self.expect_symbol(';', "';' after the assignment")?;
+            return Ok(Statement::Assignment(name, value, span.to(end)));
+        }
+
+        let arg = self.parse_call_arg(';')?;
+        self.expect_symbol(';', "';' after the assignment")?;
+
+        let mut args = Args {
+            source: self.source,
+            module: String::from("file"),
+            span,
+            positional: Vec::new(),
+            named: vec![(name, Some(arg))],
+            expressions: RefCell::new(Vec::new()),
+        };
+        let mut variables = ObjectParams::default();
+        let mut preview = ObjectParams::default();
+        args.special_variables(&mut variables, &mut preview)?;
+        if !preview.is_empty() {
+            variables.preview = Some(Box::new(preview));
+        }
+        variables.expressions = args.expressions.take();
+        Ok(Statement::Variables(variables))
+    }
+
+    /**
+      Builds a customizer variable from the assignment at `span` and the
+      comments around it, like the Customizer of OpenSCAD does. The widget is
+      a line comment behind the assignment, the description a line comment
+      on the line above it and the tab the closest `/* [Tab] */` above it.
+    */
+    fn customizer_variable(
+        &self,
+        name: String,
+        value: CustomizerValue,
+        span: Span,
+    ) -> CustomizerVariable {
+        let source = self.source;
+        let is_line = |comment: &&Span| source[comment.start..].starts_with("//");
+        let text = |comment: &Span| source[comment.start + 2..comment.end].trim();
+
+        let widget = self
+            .comments
+            .iter()
+            .filter(is_line)
+            .find(|comment| comment.start >= span.end)
+            .filter(|comment| {
+                let between = &source[span.end..comment.start];
+                between.trim().is_empty() && !between.contains('\n')
+            })
+            .map(|comment| parse_widget(text(comment), &value))
+            .unwrap_or_default();
+
+        let description = self
+            .comments
+            .iter()
+            .rev()
+            .find(|comment| comment.end <= span.start)
+            .filter(is_line)
+            .filter(|comment| {
+                let line_start = source[..comment.start].rfind('\n').map_or(0, |i| i + 1);
+                let between = &source[comment.end..span.start];
+                source[line_start..comment.start].trim().is_empty()
+                    && between.trim().is_empty()
+                    && between.matches('\n').count() == 1
+            })
+            .map(|comment| text(comment).to_string())
+            .filter(|description| !description.is_empty());
+
+        let tab = self
+            .comments
+            .iter()
+            .rev()
+            .filter(|comment| comment.end <= span.start && !is_line(comment))
+            .find_map(|comment| {
+                let text = source[comment.start + 2..comment.end - 2].trim();
+                text.strip_prefix('[')?
+                    .strip_suffix(']')
+                    .map(str::to_string)
+            });
+
+        CustomizerVariable {
+            name,
+            value,
+            widget,
+            description,
+            tab,
+        }
+    }
+
+    fn parse_module_call(&mut self, name: String) -> Result<ScadObject, ParseError> {
+        let name_span = self.peek_span();
+        self.pos += 1;
+
+        self.expect_symbol('(', "'(' after the module name")?;
+        let mut args = Args {
+            source: self.source,
+            module: name,
+            span: name_span,
+            positional: Vec::new(),
+            named: Vec::new(),
+            expressions: RefCell::new(Vec::new()),
+        };
+        while !self.next_is_symbol(')') {
+            let named = match (self.peek(), self.peek_at(1)) {
+                (Some(Token::Ident(arg_name)), Some(Token::Symbol('='))) => Some(arg_name.clone()),
+                _ => None,
+            };
+            if let Some(arg_name) = named {
+                self.pos += 2;
+                let arg = self.parse_call_arg(')')?;
+                args.named.push((arg_name, Some(arg)));
+            } else {
+                let arg = self.parse_call_arg(')')?;
+                args.positional.push(Some(arg));
+            }
+
+            if !self.next_is_symbol(')') {
+                self.expect_symbol(',', "',' or ')' after the argument")?;
+            }
+        }
+        let end = self.expect_symbol(')', "')'")?;
+        args.span = name_span.to(end);
+
+        let element = self.build_element(&mut args)?;
+        let mut params = args.object_params(&element)?;
+        params.expressions = args.expressions.take();
+        args.finish()?;
+
+        let mut object = ScadObject::new(element);
+        *object.params_mut() = params;
+        self.parse_body(&mut object)?;
+
+        Ok(object)
+    }
+
+    /// True if `build_element` knows the module, whatever its arguments are
+    fn is_builtin(&self, name: &str) -> bool {
+        let mut args = Args {
+            source: self.source,
+            module: name.to_string(),
+            span: self.peek_span(),
+            positional: Vec::new(),
+            named: Vec::new(),
+            expressions: RefCell::new(Vec::new()),
+        };
+        !matches!(
+            self.build_element(&mut args),
+            Err(ParseError {
+                kind: ParseErrorKind::UnknownModule(_),
+                ..
+            })
+        )
+    }
+
+    /// Parses a call of a module that is defined in the source or may come
+    /// from a library. The arguments are kept as expressions
+    fn parse_call(&mut self, name: String) -> Result<ScadObject, ParseError> {
+        self.pos += 1;
+        self.expect_symbol('(', "'(' after the module name")?;
+
+        let call = ScadCall {
+            module: name,
+            args: self.parse_arguments()?,
+        };
+
+        let mut object = ScadObject::new(ScadElement::Call(call));
+        self.parse_body(&mut object)?;
+        Ok(object)
+    }
+
+    /// Parses positional and named arguments up to and including the ')'
+    fn parse_arguments(&mut self) -> Result<Vec<(Option<String>, ScadExpr)>, ParseError> {
+        let mut args = Vec::new();
+        while !self.next_is_symbol(')') {
+            let name = match (self.peek(), self.peek_at(1)) {
+                (Some(Token::Ident(name)), Some(Token::Symbol('='))) => Some(name.clone()),
+                _ => None,
+            };
+            if name.is_some() {
+                self.pos += 2;
+            }
+            args.push((name, self.parse_expression()?));
+
+            if !self.next_is_symbol(')') {
+                self.expect_symbol(',', "',' or ')' after the argument")?;
+            }
+        }
+        self.pos += 1;
+        Ok(args)
+    }
+
+    /// Parses `use <path>` or `include <path>`
+    fn parse_library(&mut self) -> Result<Statement, ParseError> {
+        let keyword = match self.peek() {
+            Some(Token::Ident(keyword)) => keyword.clone(),
+            _ => unreachable!(),
+        };
+        self.pos += 1;
+        let path = match self.peek() {
+            Some(Token::Path(path)) => path.clone(),
+            _ => return Err(self.unexpected("a library path in '<' and '>'")),
+        };
+        self.pos += 1;
+
+        Ok(Statement::Library(match keyword.as_str() {
+            "use" => LibraryImport::Use(path),
+            _ => LibraryImport::Include(path),
+        }))
+    }
+
+    /// Parses `module name(params) body` after the `module` keyword
+    fn parse_module_definition(&mut self) -> Result<Statement, ParseError> {
+        self.pos += 1;
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return Err(self.unexpected("a module name")),
+        };
+        self.pos += 1;
+
+        self.expect_symbol('(', "'(' after the module name")?;
+        let mut params = Vec::new();
+        while !self.next_is_symbol(')') {
+            let param = match self.peek() {
+                Some(Token::Ident(param)) => param.clone(),
+                _ => return Err(self.unexpected("a parameter name")),
+            };
+            self.pos += 1;
+            let default = if self.next_is_symbol('=') {
+                self.pos += 1;
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
+            params.push((param, default));
+
+            if !self.next_is_symbol(')') {
+                self.expect_symbol(',', "',' or ')' after the parameter")?;
+            }
+        }
+        self.pos += 1;
+
+        //The body is parsed like the children of an object
+        let mut body = ScadObject::new(ScadElement::Union);
+        self.parse_body(&mut body)?;
+        Ok(Statement::Module(ScadModule {
+            name,
+            params,
+            body: mem::take(body.children_mut()),
+        }))
+    }
+
+    fn parse_control_flow(&mut self, name: String) -> Result<ScadObject, ParseError> {
+        let name_span = self.peek_span();
+        self.pos += 1;
+
+        let element = match name.as_str() {
+            "for" => {
+                let args = Args {
+                    source: self.source,
+                    module: name,
+                    span: name_span,
+                    positional: Vec::new(),
+                    named: Vec::new(),
+                    expressions: RefCell::new(Vec::new()),
+                };
+                ScadElement::For(self.parse_assignments(|parser, variable| {
+                    let arg = Arg {
+                        name: variable.to_string(),
+                        ..parser.parse_call_arg(')')?
+                    };
+                    args.loop_values(&arg)
+                })?)
+            }
+            "if" => {
+                self.expect_symbol('(', "'(' after if")?;
+                let condition = self.parse_expression()?;
+                self.expect_symbol(')', "')' after the condition")?;
+                ScadElement::If(condition)
+            }
+            "else" => ScadElement::Else,
+            "let" => {
+                ScadElement::Let(self.parse_assignments(|parser, _| parser.parse_expression())?)
+            }
+            "echo" => {
+                self.expect_symbol('(', "'(' after echo")?;
+                ScadElement::Echo(self.parse_arguments()?)
+            }
+            _ => {
+                self.expect_symbol('(', "'(' after assert")?;
+                let condition = self.parse_expression()?;
+                let message = if self.next_is_symbol(',') {
+                    self.pos += 1;
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                self.expect_symbol(')', "')' after the assertion")?;
+                ScadElement::Assert(condition, message)
+            }
+        };
+
+        let mut object = ScadObject::new(element);
+        self.parse_body(&mut object)?;
+        Ok(object)
+    }
+
+    /// Parses `(name = value, ...)` of for and let statements
+    fn parse_assignments<T, F>(
+        &mut self,
+        mut parse_value: F,
+    ) -> Result<Vec<(String, T)>, ParseError>
+    where
+        F: FnMut(&mut Parser<'a>, &str) -> Result<T, ParseError>,
+    {
+        self.expect_symbol('(', "'(' before the assignments")?;
+        let mut assignments = Vec::new();
+        while !self.next_is_symbol(')') {
+            let variable = match self.peek() {
+                Some(Token::Ident(name)) => name.clone(),
+                _ => return Err(self.unexpected("a variable name")),
+            };
+            self.pos += 1;
+            self.expect_symbol('=', "'=' after the variable name")?;
+            let value = parse_value(self, &variable)?;
+            assignments.push((variable, value));
+
+            if !self.next_is_symbol(')') {
+                self.expect_symbol(',', "',' or ')' after the assignment")?;
+            }
+        }
+        self.pos += 1;
+        Ok(assignments)
+    }
+
+    /// Parses an expression that ends at the next ',' or ')'
+    fn parse_expression(&mut self) -> Result<ScadExpr, ParseError> {
+        self.parse_expression_before(')')
+    }
+
+    /**
+      Parses an expression that ends at the next ',' or `close`. Expressions
+      that `ScadExpr` can't represent, like list comprehensions and function
+      literals, are kept as code.
+    */
+    fn parse_expression_before(&mut self, close: char) -> Result<ScadExpr, ParseError> {
+        let start = self.pos;
+        if let Ok(expression) = self.parse_ternary() {
+            if self.next_is_symbol(',') || self.next_is_symbol(close) {
+                return Ok(expression);
+            }
+        }
+        self.pos = start;
+        Ok(ScadExpr::Code(self.parse_code(close)?))
+    }
+
+    /// Reads an expression up to the next ',' or `close` outside of brackets
+    /// and returns its source
+    fn parse_code(&mut self, close: char) -> Result<String, ParseError> {
+        let start = self.peek_span();
+        let mut end = None;
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Some(&Token::Symbol(c)) if depth == 0 && (c == ',' || c == close) => break,
+                Some(Token::Symbol('(')) | Some(Token::Symbol('[')) => depth += 1,
+                Some(Token::Symbol(')')) | Some(Token::Symbol(']')) => depth -= 1,
+                Some(Token::Symbol(';')) | Some(Token::Symbol('{')) | None => {
+                    return Err(self.unexpected(match close {
+                        ';' => "';' after the assignment",
+                        _ => "')' after the expression",
+                    }))
+                }
+                _ => {}
+            }
+            end = Some(self.peek_span());
+            self.pos += 1;
+        }
+
+        match end {
+            Some(end) => Ok(self.source[start.start..end.end].to_string()),
+            None => Err(self.unexpected("an expression")),
+        }
+    }
+
+    /// `condition ? value : otherwise`, or any expression that binds tighter
+    fn parse_ternary(&mut self) -> Result<ScadExpr, ParseError> {
+        let condition = self.parse_binary(0)?;
+        if !self.next_is_symbol('?') {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let value = self.parse_ternary()?;
+        self.expect_symbol(':', "':' in the conditional expression")?;
+        let otherwise = self.parse_ternary()?;
+        Ok(ScadExpr::ternary(condition, value, otherwise))
+    }
+
+    /// Parses the left associative operators of `BINARY_OPERATORS[level]`
+    /// and the ones that bind tighter
+    fn parse_binary(&mut self, level: usize) -> Result<ScadExpr, ParseError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.parse_unary();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some((op, len)) = self.peek_operator() {
+            if !BINARY_OPERATORS[level].contains(&op) {
+                break;
+            }
+            self.pos += len;
+            let right = self.parse_binary(level + 1)?;
+            left = left.binary(op, right);
+        }
+        Ok(left)
+    }
+
+    /// The binary operator at the next token and the number of tokens it
+    /// takes up. Operators like `<=` are one only without space in between
+    fn peek_operator(&self) -> Option<(BinaryOp, usize)> {
+        let first = match self.peek() {
+            Some(&Token::Symbol(c)) => c,
+            _ => return None,
+        };
+        let second = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(&(_, first)), Some(&(Token::Symbol(c), second))) if first.end == second.start => {
+                Some(c)
+            }
+            _ => None,
+        };
+        let op = match (first, second) {
+            ('|', Some('|')) => (BinaryOp::Or, 2),
+            ('&', Some('&')) => (BinaryOp::And, 2),
+            ('=', Some('=')) => (BinaryOp::Eq, 2),
+            ('!', Some('=')) => (BinaryOp::Ne, 2),
+            ('<', Some('=')) => (BinaryOp::Le, 2),
+            ('>', Some('=')) => (BinaryOp::Ge, 2),
+            ('<', _) => (BinaryOp::Lt, 1),
+            ('>', _) => (BinaryOp::Gt, 1),
+            ('+', _) => (BinaryOp::Add, 1),
+            ('-', _) => (BinaryOp::Sub, 1),
+            ('*', _) => (BinaryOp::Mul, 1),
+            ('/', _) => (BinaryOp::Div, 1),
+            ('%', _) => (BinaryOp::Mod, 1),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// `-a` and `!a`. Negated numbers are read as negative numbers and a
+    /// `+` is only allowed in front of numbers
+    fn parse_unary(&mut self) -> Result<ScadExpr, ParseError> {
+        let span = self.peek_span();
+        let op = match self.peek() {
+            Some(Token::Symbol('-')) => UnaryOp::Neg,
+            Some(Token::Symbol('!')) => UnaryOp::Not,
+            Some(Token::Symbol('+')) => {
+                self.pos += 1;
+                return match self.parse_unary()? {
+                    ScadExpr::Number(value) => Ok(ScadExpr::Number(value)),
+                    _ => Err(self.unsupported("unary plus operators", span)),
+                };
+            }
+            _ => return self.parse_power(),
+        };
+        self.pos += 1;
+        Ok(match (op, self.parse_unary()?) {
+            (UnaryOp::Neg, ScadExpr::Number(value)) => ScadExpr::Number(-value),
+            (op, operand) => ScadExpr::Unary(op, Box::new(operand)),
+        })
+    }
+
+    /// `a^b`, which binds tighter than a unary operator in front of it
+    fn parse_power(&mut self) -> Result<ScadExpr, ParseError> {
+        let base = self.parse_postfix()?;
+        if !self.next_is_symbol('^') {
+            return Ok(base);
+        }
+        self.pos += 1;
+        Ok(base.pow(self.parse_unary()?))
+    }
+
+    /// A value followed by any number of indices
+    fn parse_postfix(&mut self) -> Result<ScadExpr, ParseError> {
+        let mut expression = self.parse_primary()?;
+        while self.next_is_symbol('[') {
+            self.pos += 1;
+            let index = self.parse_ternary()?;
+            self.expect_symbol(']', "']' after the index")?;
+            expression = expression.index(index);
+        }
+        Ok(expression)
+    }
+
+    fn parse_primary(&mut self) -> Result<ScadExpr, ParseError> {
+        let span = self.peek_span();
+        let token = match self.tokens.get(self.pos) {
+            Some((token, _)) => token.clone(),
+            None => return Err(self.unexpected("an expression")),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(ScadExpr::Number(value)),
+            Token::Str(value) => Ok(ScadExpr::Str(value)),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(ScadExpr::Bool(true)),
+                "false" => Ok(ScadExpr::Bool(false)),
+                "undef" => Ok(ScadExpr::Undef),
+                _ if self.next_is_symbol('(') => {
+                    self.pos += 1;
+                    let mut args = Vec::new();
+                    while !self.next_is_symbol(')') {
+                        if let (Some(Token::Ident(_)), Some(Token::Symbol('='))) =
+                            (self.peek(), self.peek_at(1))
+                        {
+                            return Err(self.unsupported("named arguments of functions", span));
+                        }
+                        args.push(self.parse_ternary()?);
+                        if !self.next_is_symbol(')') {
+                            self.expect_symbol(',', "',' or ')' after the argument")?;
+                        }
+                    }
+                    self.pos += 1;
+                    Ok(ScadExpr::Call(name, args))
+                }
+                _ => Ok(ScadExpr::Variable(name)),
+            },
+            Token::Symbol('(') => {
+                let expression = self.parse_ternary()?;
+                self.expect_symbol(')', "')' after the expression")?;
+                Ok(expression)
+            }
+            Token::Symbol('[') => {
+                let mut values = Vec::new();
+                while !self.next_is_symbol(']') {
+                    values.push(self.parse_ternary()?);
+                    if self.next_is_symbol(':') && values.len() == 1 {
+                        self.pos += 1;
+                        let start = Box::new(values.remove(0));
+                        let second = Box::new(self.parse_ternary()?);
+                        let range = if self.next_is_symbol(':') {
+                            self.pos += 1;
+                            ScadExpr::Range(start, Some(second), Box::new(self.parse_ternary()?))
+                        } else {
+                            ScadExpr::Range(start, None, second)
+                        };
+                        self.expect_symbol(']', "']' at the end of the range")?;
+                        return Ok(range);
+                    }
+                    if !self.next_is_symbol(']') {
+                        self.expect_symbol(',', "',' or ']' in the vector")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(ScadExpr::Vector(values))
+            }
+            Token::Symbol(_) | Token::Path(_) => {
+                self.pos -= 1;
+                Err(self.unexpected("an expression"))
+            }
+        }
+    }
+
+    /// Parses the children after a module call: nothing, a block or a single child
+    fn parse_body(&mut self, object: &mut ScadObject) -> Result<(), ParseError> {
+        if self.next_is_symbol(';') {
+            self.pos += 1;
+        } else if self.next_is_symbol('{') {
+            self.pos += 1;
+            while !self.next_is_symbol('}') {
+                if self.at_end() {
+                    return Err(self.unexpected("'}' at the end of the block"));
+                }
+                self.parse_child(object)?;
+            }
+            self.pos += 1;
+        } else if self.at_end() {
+            return Err(self.unexpected("';', '{' or a child object"));
+        } else {
+            self.parse_child(object)?;
+            //Like in OpenSCAD, an else belongs to the closest if before it
+            let after_if = matches!(object.children().last(), Some(child) if matches!(child.element(), ScadElement::If(_)));
+            if after_if && self.next_is_keyword("else") {
+                self.parse_child(object)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_child(&mut self, parent: &mut ScadObject) -> Result<(), ParseError> {
+        let span = self.peek_span();
+        match self.parse_statement()? {
+            Some(Statement::Object(child)) => {
+                self.check_else(parent.children().last(), &child, span)?;
+                parent.add_child(*child)
+            }
+            Some(Statement::Variables(_)) | Some(Statement::Assignment(..)) => {
+                return Err(self.unsupported("assignments inside blocks", span))
+            }
+            Some(Statement::Module(_)) => {
+                return Err(self.unsupported("module definitions inside blocks", span))
+            }
+            Some(Statement::Library(_)) => {
+                return Err(self.unsupported("use and include statements inside blocks", span))
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Parses an argument of a call of a built in module that ends at the
+    /// next ',' or `close`. Arguments that aren't values are kept as expressions
+    fn parse_call_arg(&mut self, close: char) -> Result<Arg, ParseError> {
+        let start = self.pos;
+        match self.parse_arg() {
+            Ok(arg) if self.next_is_symbol(',') || self.next_is_symbol(close) => return Ok(arg),
+            Err(e) if !matches!(e.kind, ParseErrorKind::Unsupported(_)) => return Err(e),
+            _ => {}
+        }
+        self.pos = start;
+
+        let span = self.peek_span();
+        let value = Value::Expression(self.parse_expression_before(close)?);
+        Ok(Arg {
+            name: String::new(),
+            value,
+            span: span.to(self.tokens[self.pos - 1].1),
+        })
+    }
+
+    fn parse_arg(&mut self) -> Result<Arg, ParseError> {
+        let start = self.peek_span();
+        let value = match (self.peek(), self.peek_at(1)) {
+            (Some(Token::Ident(name)), Some(Token::Symbol('?'))) if name == "$preview" => {
+                self.parse_preview()?
+            }
+            _ => self.parse_value()?,
+        };
+        let span = start.to(self.tokens[self.pos - 1].1);
+
+        if let Some(&Token::Symbol(c)) = self.peek() {
+            if OPERATORS.contains(c) {
+                return Err(self.unsupported("expressions", span.to(self.peek_span())));
+            }
+        }
+
+        Ok(Arg {
+            name: String::new(),
+            value,
+            span,
+        })
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        let span = self.peek_span();
+        let token = match self.tokens.get(self.pos) {
+            Some((token, _)) => token.clone(),
+            None => return Err(self.unexpected("a value")),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(Value::Number(value)),
+            Token::Str(value) => Ok(Value::Str(value)),
+            Token::Symbol(sign @ '-') | Token::Symbol(sign @ '+') => match self.peek() {
+                Some(&Token::Number(value)) => {
+                    self.pos += 1;
+                    Ok(Value::Number(if sign == '-' { -value } else { value }))
+                }
+                _ => Err(self.unsupported("expressions", span)),
+            },
+            Token::Symbol('[') => {
+                let mut values = Vec::new();
+                while !self.next_is_symbol(']') {
+                    values.push(self.parse_arg()?.value);
+                    if self.next_is_symbol(':') {
+                        if values.len() != 1 {
+                            return Err(self.unexpected("',' or ']' in the vector"));
+                        }
+                        return self.parse_range(values.remove(0), span);
+                    }
+                    if !self.next_is_symbol(']') {
+                        self.expect_symbol(',', "',' or ']' in the vector")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(Value::Vector(values))
+            }
+            Token::Ident(ref name) => match name.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "undef" => Ok(Value::Undef),
+                _ => Err(self.unsupported("variables and function calls", span)),
+            },
+            Token::Symbol(_) | Token::Path(_) => {
+                self.pos -= 1;
+                Err(self.unexpected("a value"))
+            }
+        }
+    }
+
+    /// Parses `$preview ? preview : render`. The render value can be a
+    /// special variable to keep the value it has outside
+    fn parse_preview(&mut self) -> Result<Value, ParseError> {
+        self.pos += 2;
+        let preview = self.parse_arg()?.value;
+        self.expect_symbol(':', "':' after the value for the preview")?;
+        let render = match self.peek() {
+            Some(Token::Ident(name)) if SPECIAL_VARIABLES.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.pos += 1;
+                Value::Variable(name)
+            }
+            _ => self.parse_arg()?.value,
+        };
+        Ok(Value::Preview(Box::new(preview), Box::new(render)))
+    }
+
+    /// Parses the rest of a range after its first value and the ':'
+    fn parse_range(&mut self, start: Value, span: Span) -> Result<Value, ParseError> {
+        let mut bounds = vec![start];
+        while self.next_is_symbol(':') && bounds.len() < 3 {
+            self.pos += 1;
+            bounds.push(self.parse_arg()?.value);
+        }
+        self.expect_symbol(']', "']' at the end of the range")?;
+
+        let bounds = bounds
+            .into_iter()
+            .map(|value| match value {
+                Value::Number(value) => Ok(value),
+                _ => Err(self.unsupported("ranges of values other than numbers", span)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match bounds[..] {
+            [start, end] => Ok(Value::Range(start, 1., end)),
+            [start, step, end] => Ok(Value::Range(start, step, end)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn build_element(&self, args: &mut Args<'a>) -> Result<ScadElement, ParseError> {
+        let element = match args.module.as_str() {
+            "translate" => match args.required("v", 0)? {
+                arg if arg.is_vector(2) => ScadElement::Translate2d(args.vec2(&arg)?),
+                arg => ScadElement::Translate(args.vec3(&arg)?),
+            },
+            "scale" => match args.required("v", 0)? {
+                arg if arg.is_vector(2) => ScadElement::Scale2d(args.vec2(&arg)?),
+                arg => ScadElement::Scale(args.vec3_or_scalar(&arg)?),
+            },
+            "resize" => {
+                let size = args.required("newsize", 0)?;
+                let size = args.vec3_padded(&size)?;
+                let auto = args.optional("auto", 1, Args::boolean, false)?;
+                ScadElement::Resize(size, auto)
+            }
+            "rotate" => {
+                let angle = args.required("a", 0)?;
+                if angle.is_vector(3) {
+                    return Err(self.unsupported("rotations by a vector of angles", angle.span));
+                }
+                let angle = args.number(&angle)?;
+                match args.take("v", 1) {
+                    Some(axis) => ScadElement::Rotate(angle, args.vec3(&axis)?),
+                    None => ScadElement::Rotate2d(angle),
+                }
+            }
+            "mirror" => {
+                let normal = args.required("v", 0)?;
+                ScadElement::Mirror(args.vec3_padded(&normal)?)
+            }
+            "multmatrix" => {
+                let matrix = args.required("m", 0)?;
+                ScadElement::MultMatrix(args.matrix(&matrix)?)
+            }
+            "linear_extrude" => {
+                let default = LinExtrudeParams::default();
+                ScadElement::LinearExtrude(LinExtrudeParams {
+                    height: args.optional("height", 0, Args::number, default.height)?,
+                    center: args.optional("center", 1, Args::boolean, default.center)?,
+                    convexity: args.optional("convexity", 2, Args::integer, default.convexity)?,
+                    twist: args.optional("twist", 3, Args::number, default.twist)?,
+                    slices: args.optional("slices", 4, Args::integer, default.slices)?,
+                    scale: args.optional("scale", 5, Args::vec2_or_scalar, default.scale)?,
+                    v: args.take_named("v").map(|v| args.vec3(&v)).transpose()?,
+                    segments: args
+                        .take_named("segments")
+                        .map(|segments| args.unsigned(&segments).map(|n| n as u64))
+                        .transpose()?,
+                })
+            }
+            "rotate_extrude" => {
+                let default = RotateExtrudeParams::default();
+                ScadElement::RotateExtrude(RotateExtrudeParams {
+                    angle: args.optional("angle", 0, Args::number, default.angle)?,
+                    convexity: args.optional("convexity", 1, Args::unsigned, default.convexity)?,
+                    start: args
+                        .take_named("start")
+                        .map(|start| args.number(&start))
+                        .transpose()?,
+                })
+            }
+
+            "difference" => ScadElement::Difference,
+            "union" => ScadElement::Union,
+            "hull" => ScadElement::Hull,
+            "intersection" => ScadElement::Intersection,
+            "minkowski" => ScadElement::Minkowski,
+            "render" => {
+                let convexity = args.optional("convexity", 0, Args::unsigned, 1)?;
+                ScadElement::Render(convexity as u64)
+            }
+            "intersection_for" => {
+                let (variable, values) = match args.take_variable() {
+                    Some(arg) => (arg.name.clone(), args.loop_values(&arg)?),
+                    None => return Err(args.invalid_at("loop variable", "given", args.span)),
+                };
+                ScadElement::IntersectionFor(variable, values)
+            }
+            "children" => match args.take("index", 0) {
+                None => ScadElement::Children(ChildSelection::All),
+                Some(arg) => ScadElement::Children(match arg.value {
+                    Value::Number(_) | Value::Expression(_) => {
+                        ChildSelection::Index(args.unsigned(&arg)?)
+                    }
+                    Value::Range(first, 1., last) => {
+                        let bounds = Arg {
+                            name: arg.name.clone(),
+                            value: Value::Vector(vec![Value::Number(first), Value::Number(last)]),
+                            span: arg.span,
+                        };
+                        let bounds = args.vector_of(&bounds, Args::unsigned)?;
+                        ChildSelection::Range(bounds[0], bounds[1])
+                    }
+                    Value::Vector(_) => {
+                        ChildSelection::Indices(args.vector_of(&arg, Args::unsigned)?)
+                    }
+                    _ => return Err(args.invalid(&arg, "an index, a vector of indices or a range")),
+                }),
+            },
+
+            "cube" => {
+                let size = na::Vector3::new(1., 1., 1.);
+                ScadElement::Cube(args.optional("size", 0, Args::vec3_or_scalar, size)?)
+            }
+            "cylinder" => {
+                let height = args.optional("h", 0, Args::number, 1.)?;
+                let r1 = args.take("r1", 1);
+                let r2 = args.take("r2", 2);
+                let size = args.circle("r", "d")?;
+                let size1 = args.circle_from(r1, "d1")?;
+                let size2 = args.circle_from(r2, "d2")?;
+
+                match (size, size1, size2) {
+                    (Some(size), None, None) => ScadElement::Cylinder(height, size),
+                    (None, size1, size2) => ScadElement::Cone(
+                        height,
+                        size1.unwrap_or(CircleType::Radius(1.)),
+                        size2.unwrap_or(CircleType::Radius(1.)),
+                    ),
+                    _ => {
+                        return Err(self.unsupported(
+                            "cylinders with both a single radius and individual radii",
+                            args.span,
+                        ))
+                    }
+                }
+            }
+            "sphere" => {
+                let r = args.take("r", 0);
+                ScadElement::Sphere(args.circle_from(r, "d")?.unwrap_or(CircleType::Radius(1.)))
+            }
+            "polyhedron" => {
+                let points = args.required("points", 0)?;
+                let points = args.vector_of(&points, Args::vec3)?;
+                let faces = match args.take("faces", 1) {
+                    Some(faces) => faces,
+                    None => args.required("triangles", 1)?,
+                };
+                let faces =
+                    args.vector_of(&faces, |args, face| args.vector_of(face, Args::index))?;
+                ScadElement::Polyhedron(points, faces)
+            }
+            "import" => {
+                //The file is passed by name when the element was an ImportFile
+                let by_name = args
+                    .named
+                    .iter()
+                    .any(|(name, arg)| name == "file" && arg.is_some());
+                let file = args.required("file", 0)?;
+                let file = args.string(&file)?;
+
+                let convexity = args.take_named("convexity");
+                let layer = args.take_named("layer");
+                let origin = args.take_named("origin");
+                let scale = args.take_named("scale");
+                let dpi = args.take_named("dpi");
+                let id = args.take_named("id");
+                let center = args.take_named("center");
+                let params = ImportParams {
+                    convexity: convexity
+                        .map(|arg| args.unsigned(&arg))
+                        .transpose()?
+                        .map(|c| c as u64),
+                    layer: layer.map(|arg| args.string(&arg)).transpose()?,
+                    origin: origin.map(|arg| args.vec2(&arg)).transpose()?,
+                    scale: scale.map(|arg| args.number(&arg)).transpose()?,
+                    dpi: dpi.map(|arg| args.number(&arg)).transpose()?,
+                    id: id.map(|arg| args.string(&arg)).transpose()?,
+                    center: center.map(|arg| args.boolean(&arg)).transpose()?,
+                    ..ImportParams::new(&file)
+                };
+
+                if by_name || params != ImportParams::new(&file) {
+                    ScadElement::ImportFile(params)
+                } else {
+                    ScadElement::Import(file)
+                }
+            }
+            "surface" => {
+                let file = args.required("file", 0)?;
+                let default = SurfaceParams::new(&args.string(&file)?);
+                ScadElement::Surface(SurfaceParams {
+                    center: args.optional("center", 1, Args::boolean, default.center)?,
+                    invert: args.optional("invert", 2, Args::boolean, default.invert)?,
+                    convexity: args.optional(
+                        "convexity",
+                        3,
+                        Args::unsigned,
+                        default.convexity as usize,
+                    )? as u64,
+                    ..default
+                })
+            }
+
+            "square" => {
+                let size = match args.take("size", 0) {
+                    Some(size) if size.is_vector(2) => args.vec2(&size)?,
+                    Some(size) => {
+                        let side = args.number(&size)?;
+                        na::Vector2::new(side, side)
+                    }
+                    None => na::Vector2::new(1., 1.),
+                };
+                ScadElement::Square(size)
+            }
+            "circle" => {
+                let r = args.take("r", 0);
+                ScadElement::Circle(args.circle_from(r, "d")?.unwrap_or(CircleType::Radius(1.)))
+            }
+            "polygon" => {
+                let points = args.required("points", 0)?;
+                let mut params = PolygonParameters::new(args.vector_of(&points, Args::vec2)?);
+                if let Some(paths) = args.take("paths", 1) {
+                    params = match paths.value {
+                        Value::Undef => params,
+                        Value::Vector(ref values)
+                            if values.iter().all(|v| matches!(v, Value::Vector(_))) =>
+                        {
+                            params.multi_vector_path(args.vector_of(&paths, |args, path| {
+                                args.vector_of(path, Args::unsigned)
+                            })?)
+                        }
+                        _ => params.single_vector_path(args.vector_of(&paths, Args::unsigned)?),
+                    };
+                }
+                let convexity = args.optional("convexity", 2, Args::unsigned, 10)?;
+                ScadElement::Polygon(params.convexity(convexity as u64))
+            }
+            "offset" => {
+                let offset = match (args.take("r", 0), args.take_named("delta")) {
+                    (Some(r), None) => OffsetType::Radius(args.number(&r)?),
+                    (None, Some(delta)) => OffsetType::Delta(args.number(&delta)?),
+                    _ => {
+                        return Err(self.error(
+                            ParseErrorKind::InvalidArgument {
+                                module: args.module.clone(),
+                                argument: String::from("r"),
+                                expected: "given, or delta but not both",
+                            },
+                            args.span,
+                        ))
+                    }
+                };
+                let chamfer = match args.take_named("chamfer") {
+                    Some(chamfer) => args.boolean(&chamfer)?,
+                    None => false,
+                };
+                ScadElement::Offset(offset, chamfer)
+            }
+            "projection" => {
+                ScadElement::Projection(args.optional("cut", 0, Args::boolean, false)?)
+            }
+            "roof" => ScadElement::Roof(args.optional(
+                "method",
+                0,
+                Args::roof_method,
+                RoofMethod::Voronoi,
+            )?),
+            "fill" => ScadElement::Fill,
+            "text" => {
+                let default = TextParams::default();
+                ScadElement::Text(TextParams {
+                    text: args.optional("text", 0, Args::string, default.text)?,
+                    size: args.optional("size", 1, Args::number, default.size)?,
+                    font: args.optional("font", 2, |args, arg| args.string(arg).map(Some), None)?,
+                    halign: args.optional("halign", 3, Args::halign, default.halign)?,
+                    valign: args.optional("valign", 4, Args::valign, default.valign)?,
+                    spacing: args.optional("spacing", 5, Args::number, default.spacing)?,
+                    direction: args.optional(
+                        "direction",
+                        6,
+                        |args, arg| args.direction(arg).map(Some),
+                        None,
+                    )?,
+                    language: args.optional(
+                        "language",
+                        7,
+                        |args, arg| args.string(arg).map(Some),
+                        None,
+                    )?,
+                    script: args.optional(
+                        "script",
+                        8,
+                        |args, arg| args.string(arg).map(Some),
+                        None,
+                    )?,
+                })
+            }
+
+            "color" => {
+                let color = args.required("c", 0)?;
+                let alpha = match args.take("alpha", 1) {
+                    Some(alpha) => Some((args.number(&alpha)?, alpha)),
+                    None => None,
+                };
+                match (&color.value, alpha) {
+                    (Value::Str(name), alpha) => {
+                        if check_color_string(name).is_err() {
+                            return Err(args.invalid(&color, "a colour name or hex colour"));
+                        }
+                        match alpha {
+                            Some((alpha, _)) => ScadElement::NamedColorAlpha(name.clone(), alpha),
+                            None => ScadElement::NamedColor(name.clone()),
+                        }
+                    }
+                    (_, None) if color.is_vector(4) => ScadElement::ColorAlpha(args.vec4(&color)?),
+                    (_, Some((_, alpha))) if color.is_vector(4) => {
+                        return Err(
+                            args.invalid(&alpha, "left out when the colour has 4 components")
+                        )
+                    }
+                    (_, Some((alpha, _))) => {
+                        ScadElement::ColorAlpha(args.vec3(&color)?.push(alpha))
+                    }
+                    (_, None) => ScadElement::Color(args.vec3(&color)?),
+                }
+            }
+
+            _ => {
+                return Err(self.error(
+                    ParseErrorKind::UnknownModule(args.module.clone()),
+                    args.span,
+                ))
+            }
+        };
+
+        Ok(element)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Arguments of a module call. Arguments are removed as they are used so that
+/// unknown ones can be reported afterwards
+struct Args<'a> {
+    source: &'a str,
+    module: String,
+    span: Span,
+    positional: Vec<Option<Arg>>,
+    named: Vec<(String, Option<Arg>)>,
+    /// Expressions of the arguments that were converted, see `expression`
+    expressions: RefCell<Vec<(String, ScadExpr)>>,
+}
+
+impl Arg {
+    fn is_vector(&self, len: usize) -> bool {
+        match self.value {
+            Value::Vector(ref values) => values.len() == len,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Args<'a> {
+    /// Takes the argument called `name` or the positional argument at `position`
+    fn take(&mut self, name: &str, position: usize) -> Option<Arg> {
+        let arg = match self.take_named(name) {
+            Some(arg) => Some(arg),
+            None => self.positional.get_mut(position).and_then(Option::take),
+        };
+        arg.map(|arg| Arg {
+            name: name.to_string(),
+            ..arg
+        })
+    }
+
+    /// Takes the argument called `name`, for parameters that are never passed
+    /// by position
+    fn take_named(&mut self, name: &str) -> Option<Arg> {
+        for named in self.named.iter_mut() {
+            if named.0 == name && named.1.is_some() {
+                return named.1.take().map(|arg| Arg {
+                    name: name.to_string(),
+                    ..arg
+                });
+            }
+        }
+        None
+    }
+
+    fn required(&mut self, name: &str, position: usize) -> Result<Arg, ParseError> {
+        match self.take(name, position) {
+            Some(arg) => Ok(arg),
+            None => Err(self.invalid_at(name, "given", self.span)),
+        }
+    }
+
+    fn optional<T>(
+        &mut self,
+        name: &str,
+        position: usize,
+        convert: fn(&Args<'a>, &Arg) -> Result<T, ParseError>,
+        default: T,
+    ) -> Result<T, ParseError> {
+        match self.take(name, position) {
+            Some(arg) => convert(self, &arg),
+            None => Ok(default),
+        }
+    }
+
+    /// Reads a `CircleType` from the radius argument `r` or the diameter argument `d`
+    fn circle(&mut self, r: &str, d: &str) -> Result<Option<CircleType>, ParseError> {
+        let radius = self.take_named(r);
+        self.circle_from(radius, d)
+    }
+
+    fn circle_from(
+        &mut self,
+        radius: Option<Arg>,
+        d: &str,
+    ) -> Result<Option<CircleType>, ParseError> {
+        let diameter = self.take_named(d);
+        match (radius, diameter) {
+            (Some(radius), None) => Ok(Some(CircleType::Radius(self.number(&radius)?))),
+            (None, Some(diameter)) => Ok(Some(CircleType::Diameter(self.number(&diameter)?))),
+            (None, None) => Ok(None),
+            (Some(_), Some(diameter)) => {
+                Err(self.invalid_at(d, "left out when the radius is given", diameter.span))
+            }
+        }
+    }
+
+    /// Takes the first named argument that isn't one of the `ObjectParams`,
+    /// for modules like `intersection_for` where the name is a loop variable
+    fn take_variable(&mut self) -> Option<Arg> {
+        let name = self
+            .named
+            .iter()
+            .find(|(name, arg)| arg.is_some() && !SPECIAL_VARIABLES.contains(&name.as_str()))
+            .map(|(name, _)| name.clone())?;
+        self.take_named(&name)
+    }
+
+    /**
+      Records the expression of an argument that isn't a value and returns
+      `placeholder` as its value. The expression replaces the placeholder
+      when the element is written.
+    */
+    fn expression<T>(&self, arg: &Arg, placeholder: T) -> Option<T> {
+        match arg.value {
+            Value::Expression(ref expression) => {
+                let mut expressions = self.expressions.borrow_mut();
+                expressions.push((arg.name.clone(), expression.clone()));
+                Some(placeholder)
+            }
+            _ => None,
+        }
+    }
+
+    /// Takes the arguments that any object can have, see `ObjectParams`
+    fn object_params(&mut self, element: &ScadElement) -> Result<ObjectParams, ParseError> {
+        let mut params = ObjectParams::default();
+        let mut preview = ObjectParams::default();
+        if element.accepts_center() {
+            if let Some(arg) = self.take_named("center") {
+                if self.expression(&arg, ()).is_none() {
+                    (params.center, preview.center) = self.preview_values(&arg, Args::boolean)?;
+                }
+            }
+        }
+        self.special_variables(&mut params, &mut preview)?;
+
+        if !preview.is_empty() {
+            params.preview = Some(Box::new(preview));
+        }
+        Ok(params)
+    }
+
+    /// Takes the special variables, the values for `$preview ? a : b` go to `preview`
+    fn special_variables(
+        &mut self,
+        params: &mut ObjectParams,
+        preview: &mut ObjectParams,
+    ) -> Result<(), ParseError> {
+        macro_rules! variable {
+            ($field:ident, $name:expr, $convert:expr) => {
+                match self.take_named($name) {
+                    Some(arg) if self.expression(&arg, ()).is_none() => {
+                        (params.$field, preview.$field) = self.preview_values(&arg, $convert)?;
+                    }
+                    _ => {}
+                }
+            };
+        }
+        variable!(detail, "$fn", Args::fragments);
+        variable!(min_angle, "$fa", Args::number);
+        variable!(min_size, "$fs", Args::number);
+        variable!(time, "$t", Args::number);
+        variable!(viewport_rotation, "$vpr", Args::vec3);
+        variable!(viewport_translation, "$vpt", Args::vec3);
+        variable!(viewport_distance, "$vpd", Args::number);
+        variable!(viewport_fov, "$vpf", Args::number);
+        Ok(())
+    }
+
+    /**
+      Converts the value of an argument for the render and for the preview.
+      Arguments without `$preview` only have a render value, and the render
+      value is `None` if it keeps the value of the variable.
+    */
+    fn preview_values<T>(
+        &self,
+        arg: &Arg,
+        convert: fn(&Self, &Arg) -> Result<T, ParseError>,
+    ) -> Result<(Option<T>, Option<T>), ParseError> {
+        let (preview, render) = match arg.value {
+            Value::Preview(ref preview, ref render) => (preview, render),
+            _ => return Ok((Some(convert(self, arg)?), None)),
+        };
+        let part = |value: &Value| Arg {
+            name: arg.name.clone(),
+            value: value.clone(),
+            span: arg.span,
+        };
+
+        let render = match **render {
+            Value::Variable(ref name) if *name == arg.name => None,
+            Value::Variable(_) => {
+                return Err(self.invalid(arg, "the same variable as the value for the render"))
+            }
+            ref render => Some(convert(self, &part(render))?),
+        };
+        Ok((render, Some(convert(self, &part(preview))?)))
+    }
+
+    /// Reports arguments that weren't used when creating the element
+    fn finish(self) -> Result<(), ParseError> {
+        let unknown = |argument: String, span: Span| {
+            Err(error_at(
+                self.source,
+                ParseErrorKind::UnknownArgument {
+                    module: self.module.clone(),
+                    argument,
+                },
+                span,
+            ))
+        };
+
+        for (name, arg) in &self.named {
+            if let Some(ref arg) = *arg {
+                return unknown(name.clone(), arg.span);
+            }
+        }
+        for (i, arg) in self.positional.iter().enumerate() {
+            if let Some(ref arg) = *arg {
+                return unknown(format!("number {}", i + 1), arg.span);
+            }
+        }
+        Ok(())
+    }
+
+    fn invalid_at(&self, name: &str, expected: &'static str, span: Span) -> ParseError {
+        error_at(
+            self.source,
+            ParseErrorKind::InvalidArgument {
+                module: self.module.clone(),
+                argument: name.to_string(),
+                expected,
+            },
+            span,
+        )
+    }
+
+    fn invalid(&self, arg: &Arg, expected: &'static str) -> ParseError {
+        self.invalid_at(&arg.name, expected, arg.span)
+    }
+
+    fn number(&self, arg: &Arg) -> Result<f64, ParseError> {
+        if let Some(value) = self.expression(arg, 0.) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Number(value) => Ok(value),
+            _ => Err(self.invalid(arg, "a number")),
+        }
+    }
+
+    fn integer(&self, arg: &Arg) -> Result<i32, ParseError> {
+        if let Some(value) = self.expression(arg, 0) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Number(value) if value.fract() == 0. && value.abs() <= f64::from(i32::MAX) => {
+                Ok(value as i32)
+            }
+            _ => Err(self.invalid(arg, "an integer")),
+        }
+    }
+
+    fn unsigned(&self, arg: &Arg) -> Result<usize, ParseError> {
+        if let Some(value) = self.expression(arg, 0) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Number(value) if value.fract() == 0. && value >= 0. => Ok(value as usize),
+            _ => Err(self.invalid(arg, "a non-negative integer")),
+        }
+    }
+
+    fn index(&self, arg: &Arg) -> Result<i32, ParseError> {
+        self.integer(arg)
+    }
+
+    fn boolean(&self, arg: &Arg) -> Result<bool, ParseError> {
+        if let Some(value) = self.expression(arg, false) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Bool(value) => Ok(value),
+            _ => Err(self.invalid(arg, "true or false")),
+        }
+    }
+
+    fn string(&self, arg: &Arg) -> Result<String, ParseError> {
+        if let Some(value) = self.expression(arg, String::new()) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Str(ref value) => Ok(value.clone()),
+            _ => Err(self.invalid(arg, "a string")),
+        }
+    }
+
+    /// Values for `$fn`
+    fn fragments(&self, arg: &Arg) -> Result<u32, ParseError> {
+        if let Some(value) = self.expression(arg, 0) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Number(value)
+                if value.fract() == 0. && value >= 0. && value <= f64::from(u32::MAX) =>
+            {
+                Ok(value as u32)
+            }
+            _ => Err(self.invalid(arg, "a non-negative integer")),
+        }
+    }
+
+    fn loop_values(&self, arg: &Arg) -> Result<LoopValues, ParseError> {
+        let values = match arg.value {
+            Value::Range(start, step, end) => return Ok(LoopValues::Range { start, step, end }),
+            Value::Expression(ref expression) => {
+                return Ok(LoopValues::Expression(expression.clone()))
+            }
+            Value::Vector(ref values) if matches!(values.first(), Some(Value::Vector(_))) => {
+                self.vector_of(arg, Args::vec3).map(LoopValues::Vectors)
+            }
+            Value::Vector(_) => self.vector_of(arg, Args::number).map(LoopValues::Numbers),
+            _ => Err(self.invalid(arg, "")),
+        };
+        values.map_err(|_| self.invalid(arg, "a range or a vector of numbers or 3D vectors"))
+    }
+
+    fn halign(&self, arg: &Arg) -> Result<HorizontalAlign, ParseError> {
+        if let Some(value) = self.expression(arg, HorizontalAlign::Left) {
+            return Ok(value);
+        }
+        match self.string(arg)?.as_str() {
+            "left" => Ok(HorizontalAlign::Left),
+            "center" => Ok(HorizontalAlign::Center),
+            "right" => Ok(HorizontalAlign::Right),
+            _ => Err(self.invalid(arg, "\"left\", \"center\" or \"right\"")),
+        }
+    }
+
+    fn roof_method(&self, arg: &Arg) -> Result<RoofMethod, ParseError> {
+        if let Some(value) = self.expression(arg, RoofMethod::Voronoi) {
+            return Ok(value);
+        }
+        match self.string(arg)?.as_str() {
+            "voronoi" => Ok(RoofMethod::Voronoi),
+            "straight" => Ok(RoofMethod::Straight),
+            _ => Err(self.invalid(arg, "\"voronoi\" or \"straight\"")),
+        }
+    }
+
+    fn valign(&self, arg: &Arg) -> Result<VerticalAlign, ParseError> {
+        if let Some(value) = self.expression(arg, VerticalAlign::Baseline) {
+            return Ok(value);
+        }
+        match self.string(arg)?.as_str() {
+            "top" => Ok(VerticalAlign::Top),
+            "center" => Ok(VerticalAlign::Center),
+            "baseline" => Ok(VerticalAlign::Baseline),
+            "bottom" => Ok(VerticalAlign::Bottom),
+            _ => Err(self.invalid(arg, "\"top\", \"center\", \"baseline\" or \"bottom\"")),
+        }
+    }
+
+    fn direction(&self, arg: &Arg) -> Result<TextDirection, ParseError> {
+        if let Some(value) = self.expression(arg, TextDirection::LeftToRight) {
+            return Ok(value);
+        }
+        match self.string(arg)?.as_str() {
+            "ltr" => Ok(TextDirection::LeftToRight),
+            "rtl" => Ok(TextDirection::RightToLeft),
+            "ttb" => Ok(TextDirection::TopToBottom),
+            "btt" => Ok(TextDirection::BottomToTop),
+            _ => Err(self.invalid(arg, "\"ltr\", \"rtl\", \"ttb\" or \"btt\"")),
+        }
+    }
+
+    fn numbers(
+        &self,
+        arg: &Arg,
+        len: usize,
+        expected: &'static str,
+    ) -> Result<Vec<f64>, ParseError> {
+        if let Some(value) = self.expression(arg, vec![0.; len]) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Vector(ref values) if values.len() == len => values
+                .iter()
+                .map(|value| match *value {
+                    Value::Number(n) => Ok(n),
+                    _ => Err(self.invalid(arg, expected)),
+                })
+                .collect(),
+            _ => Err(self.invalid(arg, expected)),
+        }
+    }
+
+    fn vec3(&self, arg: &Arg) -> Result<na::Vector3<f64>, ParseError> {
+        let v = self.numbers(arg, 3, "a vector of 3 numbers")?;
+        Ok(na::Vector3::new(v[0], v[1], v[2]))
+    }
+
+    fn vec4(&self, arg: &Arg) -> Result<na::Vector4<f64>, ParseError> {
+        let v = self.numbers(arg, 4, "a vector of 4 numbers")?;
+        Ok(na::Vector4::new(v[0], v[1], v[2], v[3]))
+    }
+
+    fn vec2(&self, arg: &Arg) -> Result<na::Vector2<f64>, ParseError> {
+        let v = self.numbers(arg, 2, "a vector of 2 numbers")?;
+        Ok(na::Vector2::new(v[0], v[1]))
+    }
+
+    /// Matrices for multmatrix, the last row can be left out like in OpenSCAD
+    fn matrix(&self, arg: &Arg) -> Result<na::Matrix4<f64>, ParseError> {
+        if let Some(value) = self.expression(arg, na::Matrix4::identity()) {
+            return Ok(value);
+        }
+        let expected = "a 3x4 or 4x4 matrix";
+        let rows = match arg.value {
+            Value::Vector(ref rows) if rows.len() == 3 || rows.len() == 4 => rows,
+            _ => return Err(self.invalid(arg, expected)),
+        };
+
+        let mut matrix = na::Matrix4::identity();
+        for (i, row) in rows.iter().enumerate() {
+            let row = Arg {
+                name: arg.name.clone(),
+                value: row.clone(),
+                span: arg.span,
+            };
+            for (j, value) in self.numbers(&row, 4, expected)?.into_iter().enumerate() {
+                matrix[(i, j)] = value;
+            }
+        }
+        Ok(matrix)
+    }
+
+    fn vec3_or_scalar(&self, arg: &Arg) -> Result<na::Vector3<f64>, ParseError> {
+        match arg.value {
+            Value::Number(value) => Ok(na::Vector3::new(value, value, value)),
+            _ => self.vec3(arg),
+        }
+    }
+
+    fn vec2_or_scalar(&self, arg: &Arg) -> Result<na::Vector2<f64>, ParseError> {
+        match arg.value {
+            Value::Number(value) => Ok(na::Vector2::new(value, value)),
+            _ => self.vec2(arg),
+        }
+    }
+
+    /// 2D vectors are extended with z=0 like OpenSCAD does
+    fn vec3_padded(&self, arg: &Arg) -> Result<na::Vector3<f64>, ParseError> {
+        if arg.is_vector(2) {
+            let v = self.vec2(arg)?;
+            Ok(na::Vector3::new(v.x, v.y, 0.))
+        } else {
+            self.vec3(arg)
+        }
+    }
+
+    fn vector_of<T, F>(&self, arg: &Arg, convert: F) -> Result<Vec<T>, ParseError>
+    where
+        F: Fn(&Args<'a>, &Arg) -> Result<T, ParseError>,
+    {
+        if let Some(value) = self.expression(arg, Vec::new()) {
+            return Ok(value);
+        }
+        match arg.value {
+            Value::Vector(ref values) => values
+                .iter()
+                .map(|value| {
+                    convert(
+                        self,
+                        &Arg {
+                            name: arg.name.clone(),
+                            value: value.clone(),
+                            span: arg.span,
+                        },
+                    )
+                })
+                .collect(),
+            _ => Err(self.invalid(arg, "a vector")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use super::*;
+    use crate::assert_scad_eq;
+    use crate::scad_format::{FormatOptions, OpenScadVersion};
+    use crate::scad_type::ScadType;
+
+    /// One object for every element so the round trip covers the whole enum
+    fn all_elements() -> Vec<ScadElement> {
+        let points = vec![
+            na::Vector3::new(0., 0., 0.),
+            na::Vector3::new(1., 0., 0.),
+            na::Vector3::new(0., 1., 0.),
+            na::Vector3::new(0., 0., 1.),
+        ];
+        let polygon = vec![
+            na::Vector2::new(0., 0.),
+            na::Vector2::new(1.5, 0.),
+            na::Vector2::new(0., -2.25),
+        ];
+
+        vec![
+            ScadElement::Translate(na::Vector3::new(1., -2., 3.5)),
+            ScadElement::Scale(na::Vector3::new(1., 2., 0.5)),
+            ScadElement::Resize(na::Vector3::new(10., 0., 5.), true),
+            ScadElement::Rotate(45., na::Vector3::new(0., 0., 1.)),
+            ScadElement::Mirror(na::Vector3::new(1., 0., 0.)),
+            ScadElement::MultMatrix(na::Matrix4::new(
+                1., 0.5, 0., 10., 0., 1., 0., -2., 0., 0., 2., 0.25, 0., 0., 0., 1.,
+            )),
+            ScadElement::LinearExtrude(LinExtrudeParams {
+                height: 12.5,
+                center: true,
+                convexity: 4,
+                twist: -90.,
+                slices: 20,
+                scale: na::Vector2::new(0.5, 2.),
+                v: Some(na::Vector3::new(1., 0., 2.)),
+                segments: Some(8),
+            }),
+            ScadElement::LinearExtrude(LinExtrudeParams {
+                scale: na::Vector2::new(0.25, 0.25),
+                ..Default::default()
+            }),
+            ScadElement::RotateExtrude(RotateExtrudeParams {
+                angle: 270.,
+                convexity: 3,
+                start: Some(-45.),
+            }),
+            ScadElement::Difference,
+            ScadElement::Union,
+            ScadElement::Hull,
+            ScadElement::Intersection,
+            ScadElement::Minkowski,
+            ScadElement::Render(3),
+            ScadElement::IntersectionFor(
+                String::from("i"),
+                LoopValues::Range {
+                    start: 0.,
+                    step: 0.5,
+                    end: -2.,
+                },
+            ),
+            ScadElement::IntersectionFor(String::from("$a"), LoopValues::Numbers(vec![1., 2.5])),
+            ScadElement::IntersectionFor(
+                String::from("rot"),
+                LoopValues::Vectors(vec![
+                    na::Vector3::new(0., 0., 0.),
+                    na::Vector3::new(90., 0., 0.),
+                ]),
+            ),
+            ScadElement::IntersectionFor(String::from("none"), LoopValues::Numbers(vec![])),
+            ScadElement::Children(ChildSelection::All),
+            ScadElement::Children(ChildSelection::Index(1)),
+            ScadElement::Children(ChildSelection::Indices(vec![0, 2])),
+            ScadElement::Children(ChildSelection::Range(1, 4)),
+            ScadElement::For(vec![
+                (
+                    String::from("x"),
+                    LoopValues::Range {
+                        start: -1.,
+                        step: 1.,
+                        end: 1.,
+                    },
+                ),
+                (
+                    String::from("offset"),
+                    LoopValues::Vectors(vec![na::Vector3::new(1., 2., 3.)]),
+                ),
+            ]),
+            ScadElement::For(vec![]),
+            ScadElement::If(
+                ScadExpr::var("$preview").and(
+                    ScadExpr::call("len", vec![ScadExpr::var("v")])
+                        .gt(ScadExpr::from(vec![1, 2]).index(0)),
+                ),
+            ),
+            ScadElement::Let(vec![
+                (String::from("a"), 1.into()),
+                (
+                    String::from("b"),
+                    ScadExpr::var("a")
+                        * (ScadExpr::from(2) + ScadExpr::call("f", vec![3.into(), 4.into()])),
+                ),
+                (
+                    String::from("c"),
+                    ScadExpr::Code(String::from("[for (i = [0:2]) i * 2]")),
+                ),
+            ]),
+            ScadElement::Echo(vec![]),
+            ScadElement::Echo(vec![
+                (None, "x, y".into()),
+                (Some(String::from("x")), 3.into()),
+            ]),
+            ScadElement::Assert(true.into(), None),
+            ScadElement::Assert(
+                ScadExpr::var("x").gt(0),
+                Some("x \"must\" be, positive".into()),
+            ),
+            ScadElement::Cube(na::Vector3::new(1., 2., 3.)),
+            ScadElement::Cylinder(10., CircleType::Radius(2.)),
+            ScadElement::Cylinder(10., CircleType::Diameter(4.)),
+            ScadElement::Sphere(CircleType::Radius(0.125)),
+            ScadElement::Sphere(CircleType::Diameter(3.)),
+            ScadElement::Cone(5., CircleType::Radius(1.), CircleType::Diameter(7.)),
+            ScadElement::Polyhedron(points, vec![vec![0, 1, 2], vec![0, 3, 1], vec![1, 3, 2]]),
+            ScadElement::Import(String::from("C:\\parts\\\"odd\" näme\n.stl")),
+            ScadElement::ImportFile(ImportParams::new("part.stl")),
+            ScadElement::ImportFile(ImportParams {
+                convexity: Some(4),
+                layer: Some(String::from("cut")),
+                origin: Some(na::Vector2::new(1., 2.)),
+                scale: Some(0.5),
+                ..ImportParams::new("plate.dxf")
+            }),
+            ScadElement::ImportFile(ImportParams {
+                dpi: Some(300.),
+                id: Some(String::from("outline")),
+                center: Some(true),
+                ..ImportParams::new("logo.svg")
+            }),
+            ScadElement::Surface(SurfaceParams::new("terrain.dat")),
+            ScadElement::Surface(SurfaceParams {
+                center: true,
+                invert: true,
+                convexity: 4,
+                ..SurfaceParams::new("logo.png")
+            }),
+            ScadElement::Square(na::Vector2::new(3., 4.)),
+            ScadElement::Circle(CircleType::Radius(1.)),
+            ScadElement::Circle(CircleType::Diameter(1.)),
+            ScadElement::Polygon(PolygonParameters::new(polygon.clone())),
+            ScadElement::Polygon(
+                PolygonParameters::new(polygon.clone())
+                    .single_vector_path(vec![2, 1, 0])
+                    .convexity(2),
+            ),
+            ScadElement::Polygon(
+                PolygonParameters::new(polygon).multi_vector_path(vec![vec![0, 1, 2]]),
+            ),
+            ScadElement::Offset(OffsetType::Delta(1.5), true),
+            ScadElement::Offset(OffsetType::Radius(-0.5), false),
+            ScadElement::Projection(true),
+            ScadElement::Roof(RoofMethod::Straight),
+            ScadElement::Roof(RoofMethod::Voronoi),
+            ScadElement::Fill,
+            ScadElement::Text(TextParams::new("")),
+            ScadElement::Text(TextParams {
+                size: 4.5,
+                font: Some(String::from("Liberation Sans:style=Bold")),
+                halign: HorizontalAlign::Right,
+                valign: VerticalAlign::Center,
+                spacing: 1.2,
+                direction: Some(TextDirection::TopToBottom),
+                language: Some(String::from("ja")),
+                script: Some(String::from("kana")),
+                ..TextParams::new("\"Serial\"\tNº 42")
+            }),
+            ScadElement::Rotate2d(30.),
+            ScadElement::Translate2d(na::Vector2::new(-1., 1.)),
+            ScadElement::Scale2d(na::Vector2::new(2., 2.)),
+            ScadElement::Color(na::Vector3::new(0.25, 0.5, 1.)),
+            ScadElement::NamedColor(String::from("aqua")),
+            ScadElement::NamedColor(String::from("#ff8800cc")),
+            ScadElement::ColorAlpha(na::Vector4::new(0.25, 0.5, 1., 0.75)),
+            ScadElement::NamedColorAlpha(String::from("SteelBlue"), 0.5),
+        ]
+    }
+
+    fn sample_tree() -> ScadObject {
+        let mut root = ScadObject::new(ScadElement::Union).important();
+        for element in all_elements() {
+            let mut object = ScadObject::new(element);
+            object.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+                1., 1., 1.,
+            ))));
+            root.add_child(object);
+            root.add_child(ScadObject::new(ScadElement::Hull));
+        }
+
+        let mut single = ScadObject::new(ScadElement::Translate(na::Vector3::new(0., 0., 1.)));
+        let mut nested = ScadObject::new(ScadElement::Rotate2d(90.));
+        nested.add_child(ScadObject::new(ScadElement::Circle(CircleType::Radius(2.))));
+        nested
+            .add_child(ScadObject::new(ScadElement::Square(na::Vector2::new(1., 1.))).important());
+        single.add_child(nested);
+        root.add_child(single);
+
+        root.add_child(ScadObject::new(ScadElement::Hull).highlight());
+        let mut detailed = ScadObject::new(ScadElement::Union).with_detail(6);
+        detailed.add_child(
+            ScadObject::new(ScadElement::Cylinder(2., CircleType::Diameter(1.)))
+                .centered()
+                .with_detail(64),
+        );
+        let mut sphere = ScadObject::new(ScadElement::Sphere(CircleType::Radius(40.)));
+        sphere.params_mut().min_angle = Some(15.);
+        sphere.params_mut().min_size = Some(1.5);
+        detailed.add_child(sphere);
+        root.add_child(detailed);
+        let cube = || ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.)));
+        let mut condition = ScadObject::new(ScadElement::If(ScadExpr::var("a"))).highlight();
+        condition.add_child(cube());
+        let mut nested = ScadObject::new(ScadElement::If(ScadExpr::var("b")));
+        nested.add_child(cube());
+        let mut otherwise = ScadObject::new(ScadElement::Else);
+        otherwise.add_child(nested);
+        otherwise.add_child(ScadObject::new(ScadElement::Else));
+        root.add_child(condition);
+        root.add_child(otherwise);
+        let mut chain = ScadObject::new(ScadElement::Else);
+        chain.add_child(ScadObject::new(ScadElement::If(ScadExpr::var("c"))));
+        root.add_child(ScadObject::new(ScadElement::If(ScadExpr::var("d"))));
+        root.add_child(chain);
+
+        let mut ghost = ScadObject::new(ScadElement::Union).background();
+        ghost.add_child(ScadObject::new(ScadElement::Sphere(CircleType::Radius(1.))).disable());
+        root.add_child(ghost);
+
+        root
+    }
+
+    #[test]
+    fn element_round_trip() {
+        let options = FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..Default::default()
+        };
+        for element in all_elements() {
+            let code = ScadObject::new(element.clone()).get_code_with(&options);
+            let parsed = parse_object(&code).unwrap();
+
+            assert_eq!(parsed, ScadObject::new(element));
+        }
+    }
+
+    #[test]
+    fn object_round_trip() {
+        let tree = sample_tree();
+        let options = vec![
+            FormatOptions::default(),
+            FormatOptions::spaces(2),
+            FormatOptions::compact(),
+            FormatOptions {
+                elide_single_child_braces: true,
+                escape_non_ascii: true,
+                ..Default::default()
+            },
+        ];
+
+        for options in options {
+            let options = FormatOptions {
+                target: OpenScadVersion::Snapshot,
+                ..options
+            };
+            let code = tree.get_code_with(&options);
+            let parsed = parse_object(&code).unwrap();
+
+            assert_scad_eq!(parsed, tree);
+            assert_eq!(parsed.get_code_with(&options), code);
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let mut file = ScadFile::new();
+        file.set_detail(64);
+        file.add_object(sample_tree());
+        file.add_object(ScadObject::new(ScadElement::Sphere(CircleType::Radius(3.))));
+        file.set_format_options(FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..Default::default()
+        });
+
+        let parsed: ScadFile = file.get_code().parse().unwrap();
+        assert_eq!(parsed, file);
+
+        file.set_format_options(FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..FormatOptions::compact()
+        });
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+    }
+
+    #[test]
+    fn hand_written_test() {
+        let source = "
+            // A comment
+            $fn = 30;
+            /* A block
+               comment */
+            difference() {
+                cube(size = 10);
+                translate(v = [5, 5, -1]) cylinder(12, r = 2.5);
+                cylinder(3, 1, 2);
+                rotate(v = [0, 0, 1], a = 90)
+                    square([.5, 1e1]);
+                ;
+            }
+            sphere(5);
+            scale(2) circle(d = 4);
+            color(\"red\", ) polygon([[0, 0], [1, 0], [0, 1],], [0, 1, 2]);
+            cube();
+            square();
+        ";
+
+        assert_eq!(
+            parse_file(source).unwrap().get_code(),
+            "$fn=30;\n\
+             difference()\n{\n\
+             \tcube([10,10,10]);\n\
+             \ttranslate([5,5,-1])\n\t{\n\t\tcylinder(h=12,r=2.5);\n\t}\n\
+             \tcylinder(h=3,r1=1,r2=2);\n\
+             \trotate(90,[0,0,1])\n\t{\n\t\tsquare([0.5,10]);\n\t}\n\
+             }\n\
+             sphere(r=5);\n\
+             scale([2,2,2])\n{\n\tcircle(d=4);\n}\n\
+             color(\"red\")\n{\n\tpolygon(points=[[0,0],[1,0],[0,1],],paths=[0,1,2,],convexity=10);\n}\n\
+             cube([1,1,1]);\n\
+             square([1,1]);\n"
+        );
+    }
+
+    fn error_kind(source: &str) -> (ParseErrorKind, usize, usize) {
+        let error = parse_file(source).unwrap_err();
+        (error.kind, error.line, error.column)
+    }
+
+    #[test]
+    fn unsupported_test() {
+        assert_eq!(
+            error_kind("union() {\nmodule foo() { cube(1); }\n}"),
+            (
+                ParseErrorKind::Unsupported(String::from("module definitions inside blocks")),
+                2,
+                1
+            )
+        );
+        assert_eq!(
+            error_kind("union() {\n  width = 3;\n}"),
+            (
+                ParseErrorKind::Unsupported(String::from("assignments inside blocks")),
+                2,
+                3
+            )
+        );
+        assert_eq!(
+            parse_object("width = 3;").unwrap_err().kind,
+            ParseErrorKind::Unsupported(String::from("variable assignments"))
+        );
+        assert_eq!(
+            error_kind("union() {\n  function f() = 1;\n}"),
+            (
+                ParseErrorKind::Unsupported(String::from("function definitions")),
+                2,
+                3
+            )
+        );
+        assert_eq!(
+            error_kind("  #%cube(1);"),
+            (
+                ParseErrorKind::Unsupported(String::from("combined modifiers")),
+                1,
+                3
+            )
+        );
+
+        let error = parse_file("union() {\n  function f() = 1;\n}").unwrap_err();
+        assert_eq!(error.span, Span { start: 12, end: 20 });
+        assert_eq!(
+            error.to_string(),
+            "2:3: function definitions are not supported"
+        );
+    }
+
+    #[test]
+    fn invalid_input_test() {
+        assert_eq!(
+            error_kind("frobnicate(1);"),
+            (
+                ParseErrorKind::UnknownModule(String::from("frobnicate")),
+                1,
+                1
+            )
+        );
+        assert_eq!(
+            error_kind("cube(1, fancy = true);"),
+            (
+                ParseErrorKind::UnknownArgument {
+                    module: String::from("cube"),
+                    argument: String::from("fancy")
+                },
+                1,
+                17
+            )
+        );
+        assert_eq!(
+            error_kind("translate([1, \"a\", 3]);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("translate"),
+                    argument: String::from("v"),
+                    expected: "a vector of 3 numbers"
+                },
+                1,
+                11
+            )
+        );
+        assert_eq!(
+            error_kind("union() {\n cube(1);\n"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("end of input"),
+                    expected: "'}' at the end of the block"
+                },
+                3,
+                1
+            )
+        );
+        assert_eq!(
+            error_kind("import(\"abc);"),
+            (ParseErrorKind::UnterminatedString, 1, 8)
+        );
+        assert_eq!(
+            error_kind("import(\"a\\qb\");"),
+            (ParseErrorKind::InvalidEscape(String::from("\\q")), 1, 10)
+        );
+        assert_eq!(
+            error_kind("echo(\"a\\é\");"),
+            (ParseErrorKind::InvalidEscape(String::from("\\é")), 1, 8)
+        );
+        assert_eq!(
+            error_kind("cube(1) @"),
+            (ParseErrorKind::UnexpectedCharacter('@'), 1, 9)
+        );
+        assert!(parse_object("cube(1); cube(2);").is_err());
+    }
+
+    #[test]
+    fn multmatrix_test() {
+        //2D matrices are written as 4x4 matrices, so they come back as 3D ones
+        let skew = ScadElement::MultMatrix2d(na::Matrix3::new(1., 0.5, 3., 0., 1., 4., 0., 0., 1.));
+        assert_eq!(
+            parse_object(&(skew.get_code() + ";")).unwrap(),
+            ScadObject::new(ScadElement::MultMatrix(na::Matrix4::new(
+                1., 0.5, 0., 3., 0., 1., 0., 4., 0., 0., 1., 0., 0., 0., 0., 1.
+            )))
+        );
+
+        assert_eq!(
+            parse_object("multmatrix([[1, 0, 0, 5], [0, 1, 0, 0], [0, 0, 1, 0]]);").unwrap(),
+            ScadObject::new(ScadElement::MultMatrix(na::Matrix4::new_translation(
+                &na::Vector3::new(5., 0., 0.)
+            )))
+        );
+        assert_eq!(
+            error_kind("multmatrix([[1, 0, 0], [0, 1, 0], [0, 0, 1]]);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("multmatrix"),
+                    argument: String::from("m"),
+                    expected: "a 3x4 or 4x4 matrix"
+                },
+                1,
+                12
+            )
+        );
+    }
+
+    #[test]
+    fn module_test() {
+        let file = parse_file(
+            "hole(d = 2 * 3) children();\n\
+             module hole(d, depth = d * 5) {\n\
+                 cylinder(h = 10, d = 3);\n\
+                 children();\n\
+             }",
+        )
+        .unwrap();
+
+        let mut call = ScadObject::new(ScadElement::Call(
+            ScadCall::new("hole").named("d", ScadExpr::from(2) * 3),
+        ));
+        call.add_child(ScadObject::new(ScadElement::Children(ChildSelection::All)));
+        assert_eq!(file.objects(), &[call][..]);
+        assert_eq!(
+            file.modules(),
+            &[ScadModule {
+                name: String::from("hole"),
+                params: vec![
+                    (String::from("d"), None),
+                    (String::from("depth"), Some(ScadExpr::var("d") * 5)),
+                ],
+                body: vec![
+                    ScadObject::new(ScadElement::Cylinder(10., CircleType::Diameter(3.))),
+                    ScadObject::new(ScadElement::Children(ChildSelection::All)),
+                ],
+            }][..]
+        );
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+
+        let file = parse_file("module a(h) cube(1);\na(1);").unwrap();
+        assert_eq!(
+            file.objects(),
+            &[ScadObject::new(ScadElement::Call(
+                ScadCall::new("a").arg(1)
+            ))][..]
+        );
+        assert_eq!(
+            parse_object("a();").unwrap_err().kind,
+            ParseErrorKind::UnknownModule(String::from("a"))
+        );
+    }
+
+    #[test]
+    fn library_test() {
+        let file = parse_file(
+            "include <MCAD/units.scad>\n\
+             use <BOSL2/std.scad>\n\
+             cuboid([20, 10, 5], rounding = M3 / 2) cube(2);\n\
+             translate([1, 0, 0]) screw(\"M3\", length = 10);",
+        )
+        .unwrap();
+
+        assert_eq!(
+            file.libraries(),
+            &[
+                LibraryImport::Include(String::from("MCAD/units.scad")),
+                LibraryImport::Use(String::from("BOSL2/std.scad")),
+            ][..]
+        );
+        let mut cuboid = ScadObject::new(ScadElement::Call(ScadCall {
+            module: String::from("cuboid"),
+            args: vec![
+                (None, ScadExpr::from(na::Vector3::new(20., 10., 5.))),
+                (Some(String::from("rounding")), ScadExpr::var("M3") / 2),
+            ],
+        }));
+        cuboid.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+            2., 2., 2.,
+        ))));
+        let mut screw = ScadObject::new(ScadElement::Translate(na::Vector3::new(1., 0., 0.)));
+        screw.add_child(ScadObject::new(ScadElement::Call(
+            ScadCall::new("screw").arg("M3").named("length", 10),
+        )));
+        assert_eq!(file.objects(), &[cuboid, screw][..]);
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+
+        assert_eq!(
+            error_kind("use <BOSL2/std.scad\ncube(1);"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("end of line"),
+                    expected: "'>' at the end of the library path",
+                },
+                1,
+                5
+            )
+        );
+        assert_eq!(
+            error_kind("use <a.scad>\nunion() {\ninclude <b.scad>\n}"),
+            (
+                ParseErrorKind::Unsupported(String::from(
+                    "use and include statements inside blocks"
+                )),
+                3,
+                1
+            )
+        );
+        assert_eq!(
+            error_kind("use;"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("';'"),
+                    expected: "a library path in '<' and '>'",
+                },
+                1,
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn deduplicate_round_trip() {
+        let mut file = ScadFile::new();
+        file.add_object(sample_tree());
+        file.add_object(sample_tree().background());
+        file.set_format_options(FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..Default::default()
+        });
+
+        let code = file.get_code();
+        assert!(file.deduplicate() > 0);
+        assert!(file.get_code().len() < code.len());
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+    }
+
+    #[test]
+    fn intersection_for_test() {
+        let mut expected = ScadObject::new(ScadElement::IntersectionFor(
+            String::from("n"),
+            LoopValues::Range {
+                start: 1.,
+                step: 1.,
+                end: 6.,
+            },
+        ))
+        .with_detail(6);
+        expected.add_child(ScadObject::new(ScadElement::Cube(na::Vector3::new(
+            1., 1., 1.,
+        ))));
+        assert_eq!(
+            parse_object("intersection_for(n = [1 : 6], $fn = 6) cube(1);").unwrap(),
+            expected
+        );
+
+        let mut expected = ScadObject::new(ScadElement::Render(1));
+        expected.add_child(ScadObject::new(ScadElement::Children(
+            ChildSelection::Range(0, 2),
+        )));
+        assert_eq!(parse_object("render() children([0:2]);").unwrap(), expected);
+
+        assert_eq!(
+            error_kind("intersection_for() cube(1);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("intersection_for"),
+                    argument: String::from("loop variable"),
+                    expected: "given"
+                },
+                1,
+                1
+            )
+        );
+        assert_eq!(
+            error_kind("intersection_for(i = [[1, 2], 3]) cube(1);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("intersection_for"),
+                    argument: String::from("i"),
+                    expected: "a range or a vector of numbers or 3D vectors"
+                },
+                1,
+                22
+            )
+        );
+        assert_eq!(
+            error_kind("children([0:2:4]);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("children"),
+                    argument: String::from("index"),
+                    expected: "an index, a vector of indices or a range"
+                },
+                1,
+                10
+            )
+        );
+        assert_eq!(
+            error_kind("children([0:1:2:3]);"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    expected: "']' at the end of the range",
+                    found: String::from("':'")
+                },
+                1,
+                16
+            )
+        );
+    }
+
+    #[test]
+    fn argument_expressions_test() {
+        //Arguments that aren't values become expressions with a placeholder value
+        let cube = parse_object("cube(width);").unwrap();
+        assert_eq!(
+            cube,
+            ScadObject::new(ScadElement::Cube(na::Vector3::new(0., 0., 0.)))
+                .with_expression("size", ScadExpr::var("width"))
+        );
+        assert_eq!(cube.get_code(), "cube(width);");
+
+        let sources = [
+            "for(i=[0:1:3])\n{\n\tcube(i);\n}",
+            "cube(1+2);",
+            "cylinder(h=h,r=r/2,$fn=n);",
+            "cylinder(h=1,r1=1,d2=d,center=true,$fa=$preview?a:12);",
+            "translate(v)\n{\n\tchildren(i);\n}",
+            "color(\"red\",alpha=a)\n{\n\tsquare([1,1]);\n}",
+            "import(file=path,convexity=c);",
+            "text(label,size=10,halign=align,valign=\"baseline\",spacing=1);",
+            "intersection_for(a=angles)\n{\n\tcube([1,1,1]);\n}",
+        ];
+        for source in sources.iter() {
+            let object = parse_object(source).unwrap();
+            assert_eq!(object.get_code(), *source);
+            assert_eq!(parse_object(&object.get_code()).unwrap(), object);
+        }
+
+        let snapshot = FormatOptions {
+            target: OpenScadVersion::Snapshot,
+            ..Default::default()
+        };
+        let source = "linear_extrude(height=2,v=[0,tilt,1],center=false,convexity=10,twist=0,slices=1,segments=n);";
+        let extrude = parse_object(source).unwrap();
+        assert_eq!(extrude.get_code_with(&snapshot), source);
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let source = "
+            if (x > 1) if (y) cube(1); else sphere(1);
+            else { for (i = [0:2], j = [1, 2]) echo(i, j = j); }
+            let (size = [1, 2], $fn = 3) assert(size[0] > 0, \"too small\") square(size = [1, 2]);
+        ";
+        let file = parse_file(source).unwrap();
+        assert_eq!(file.objects().len(), 3);
+
+        let outer = &file.objects()[0];
+        assert_eq!(*outer.element(), ScadElement::If(ScadExpr::var("x").gt(1)));
+        assert_eq!(outer.children().len(), 2);
+        assert_eq!(*outer.children()[1].element(), ScadElement::Else);
+
+        let otherwise = &file.objects()[1];
+        assert_eq!(*otherwise.element(), ScadElement::Else);
+        assert_eq!(
+            *otherwise.children()[0].element(),
+            ScadElement::For(vec![
+                (
+                    String::from("i"),
+                    LoopValues::Range {
+                        start: 0.,
+                        step: 1.,
+                        end: 2.
+                    }
+                ),
+                (String::from("j"), LoopValues::Numbers(vec![1., 2.])),
+            ])
+        );
+        assert_eq!(
+            *otherwise.children()[0].children()[0].element(),
+            ScadElement::Echo(vec![
+                (None, ScadExpr::var("i")),
+                (Some(String::from("j")), ScadExpr::var("j"))
+            ])
+        );
+
+        let scope = &file.objects()[2];
+        assert_eq!(
+            *scope.element(),
+            ScadElement::Let(vec![
+                (String::from("size"), vec![1, 2].into()),
+                (String::from("$fn"), 3.into()),
+            ])
+        );
+        assert_eq!(
+            *scope.children()[0].element(),
+            ScadElement::Assert(
+                ScadExpr::var("size").index(0).gt(0),
+                Some("too small".into())
+            )
+        );
+        assert_eq!(
+            *parse_object("assert(a, str(\"x\", 1));").unwrap().element(),
+            ScadElement::Assert(
+                ScadExpr::var("a"),
+                Some(ScadExpr::call("str", vec!["x".into(), 1.into()]))
+            )
+        );
+
+        assert_eq!(
+            error_kind("cube(1);\nelse cube(1);"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("else"),
+                    expected: "an if before the else"
+                },
+                2,
+                1
+            )
+        );
+        assert_eq!(
+            error_kind("if (a) cube(1); #else cube(1);"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("else"),
+                    expected: "a module call after the modifier"
+                },
+                1,
+                18
+            )
+        );
+        assert_eq!(
+            error_kind("if (a; cube(1);"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("';'"),
+                    expected: "')' after the expression"
+                },
+                1,
+                6
+            )
+        );
+        assert_eq!(
+            error_kind("if () cube(1);"),
+            (
+                ParseErrorKind::UnexpectedToken {
+                    found: String::from("')'"),
+                    expected: "an expression"
+                },
+                1,
+                5
+            )
+        );
+    }
+
+    #[test]
+    fn expression_test() {
+        let condition = |source: &str| {
+            let object = parse_object(&format!("if ({}) cube(1);", source)).unwrap();
+            match *object.element() {
+                ScadElement::If(ref condition) => condition.clone(),
+                _ => unreachable!(),
+            }
+        };
+        let (a, b, c) = (ScadExpr::var("a"), ScadExpr::var("b"), ScadExpr::var("c"));
+
+        assert_eq!(
+            condition("1 + 2 * 3 - a"),
+            ScadExpr::from(1) + ScadExpr::from(2) * 3 - a.clone()
+        );
+        assert_eq!(
+            condition("!a || b && c"),
+            (!a.clone()).or(b.clone().and(c.clone()))
+        );
+        assert_eq!(
+            condition("a ? b : c ? 1 : -2"),
+            ScadExpr::ternary(a.clone(), b, ScadExpr::ternary(c, 1.into(), (-2).into()))
+        );
+        assert_eq!(condition("a[0][1]"), a.clone().index(0).index(1));
+        assert_eq!(condition("[0 : 0.5 : 2]").get_code(), "[0:0.5:2]");
+        assert_eq!(condition("-2 ^ 2 ^ a").get_code(), "-2^2^a");
+        assert_eq!(condition("(-2) ^ 2").get_code(), "(-2)^2");
+        assert_eq!(
+            condition("(1 + 2) * 3 / (4 % a)").get_code(),
+            "(1+2)*3/(4%a)"
+        );
+        assert_eq!(
+            condition("a < b == a <= b != a>=b").get_code(),
+            "a<b==a<=b!=a>=b"
+        );
+        assert_eq!(condition("norm([1, 2]) > 0").get_code(), "norm([1,2])>0");
+
+        //Anything else is kept as it is
+        for source in &[
+            "[for (i = [0:2]) i]",
+            "a.x",
+            "f(x = 1)",
+            "function(x) x",
+            "+a",
+        ] {
+            assert_eq!(condition(source), ScadExpr::Code(source.to_string()));
+        }
+    }
+
+    #[test]
+    fn params_test() {
+        assert_eq!(
+            parse_object("cube(1, center = true, $fn = 3);").unwrap(),
+            ScadObject::new(ScadElement::Cube(na::Vector3::new(1., 1., 1.)))
+                .centered()
+                .with_detail(3)
+        );
+        assert_eq!(
+            error_kind("sphere(1, center = true);"),
+            (
+                ParseErrorKind::UnknownArgument {
+                    module: String::from("sphere"),
+                    argument: String::from("center")
+                },
+                1,
+                20
+            )
+        );
+        assert_eq!(
+            error_kind("circle(1, $fn = 2.5);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("circle"),
+                    argument: String::from("$fn"),
+                    expected: "a non-negative integer"
+                },
+                1,
+                17
+            )
+        );
+    }
+
+    #[test]
+    fn special_variables_test() {
+        use crate::scad_type::ScadType;
+
+        let file = parse_file(
+            "$fn = $preview ? 16 : 64;\n$vpr = [60, 0, 30];\n$vpd = $preview ? 80 : $vpd;\n\
+             sphere(2, $t = 0.5, $fs = $preview ? 1 : $fs, $vpt = [0, 0, 1], $vpf = 40);",
+        )
+        .unwrap();
+        assert_eq!(file.get_detail(), 64);
+        assert_eq!(
+            *file.variables(),
+            ObjectParams {
+                detail: Some(64),
+                viewport_rotation: Some(na::Vector3::new(60., 0., 30.)),
+                preview: Some(Box::new(ObjectParams {
+                    detail: Some(16),
+                    viewport_distance: Some(80.),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+        );
+
+        let sphere = &file.objects()[0];
+        assert_eq!(sphere.params().time, Some(0.5));
+        assert_eq!(sphere.params().min_size, None);
+        assert_eq!(sphere.params().preview.as_ref().unwrap().min_size, Some(1.));
+        assert_eq!(
+            sphere.get_code(),
+            "sphere(r=2,$fs=$preview?1:$fs,$t=0.5,$vpt=[0,0,1],$vpf=40);"
+        );
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+
+        assert_eq!(
+            error_kind("cube(1, $fn = $preview ? 8 : $fa);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("cube"),
+                    argument: String::from("$fn"),
+                    expected: "the same variable as the value for the render"
+                },
+                1,
+                15
+            )
+        );
+        assert_eq!(
+            error_kind("$t = \"late\";"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("file"),
+                    argument: String::from("$t"),
+                    expected: "a number"
+                },
+                1,
+                6
+            )
+        );
+    }
+
+    #[test]
+    fn customizer_test() {
+        let mut file = ScadFile::new();
+        file.add_customizer_variables(vec![
+            CustomizerVariable::new("wall", &2.)
+                .range(1., 5.)
+                .description("Thickness of the walls"),
+            CustomizerVariable::new("rounded", &true),
+            CustomizerVariable::new("height", &10.)
+                .widget(CustomizerWidget::Step(0.5))
+                .tab("Size"),
+            CustomizerVariable::new("size", &na::Vector3::new(10., 20., 5.))
+                .widget(CustomizerWidget::Slider {
+                    min: 0.,
+                    step: Some(5.),
+                    max: 50.,
+                })
+                .tab("Size"),
+            CustomizerVariable::new("shape", "box")
+                .widget(CustomizerWidget::Strings(vec![
+                    (String::from("box"), Some(String::from("Box"))),
+                    (String::from("tube"), None),
+                ]))
+                .description("Outer shape")
+                .tab("Shape"),
+            CustomizerVariable::new("holes", &3)
+                .widget(CustomizerWidget::Numbers(vec![
+                    (2., None),
+                    (3., Some(String::from("Three"))),
+                ]))
+                .tab("Shape"),
+            CustomizerVariable::new("label", "Part")
+                .widget(CustomizerWidget::MaxLength(12))
+                .tab("Hidden"),
+        ]);
+        file.variables_mut().expressions = vec![
+            (String::from("$fn"), ScadExpr::var("wall") * 8),
+            (
+                String::from("inner"),
+                ScadExpr::var("height") - ScadExpr::var("wall") * 2,
+            ),
+            (
+                String::from("steps"),
+                ScadExpr::Code(String::from("[for (i = [0:3]) i]")),
+            ),
+        ];
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+        file.set_format_options(FormatOptions::compact());
+        assert_eq!(parse_file(&file.get_code()).unwrap(), file);
+
+        let file = parse_file(
+            "// A comment that isn't a description\n\n\
+             width = 20; // the outer width\n\
+             depth = -width; // [0:10]\n\
+             /* [Size] */\n\
+             // Number of parts\n\
+             count = 4; // [1:2:9]\n\
+             name = \"a\"; /* [Other] */ names = [\"a\", 1];",
+        )
+        .unwrap();
+        assert_eq!(
+            file.customizer_variables(),
+            &[
+                CustomizerVariable::new("width", &20.),
+                CustomizerVariable::new("count", &4)
+                    .widget(CustomizerWidget::Slider {
+                        min: 1.,
+                        step: Some(2.),
+                        max: 9.,
+                    })
+                    .description("Number of parts")
+                    .tab("Size"),
+                CustomizerVariable::new("name", "a").tab("Size"),
+            ]
+        );
+        assert_eq!(
+            file.variables().expressions,
+            vec![
+                (String::from("depth"), -ScadExpr::var("width")),
+                (
+                    String::from("names"),
+                    ScadExpr::Vector(vec!["a".into(), 1.into()])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn color_test() {
+        assert_eq!(
+            parse_object("color([1, 0, 0], 0.5);").unwrap(),
+            ScadObject::new(ScadElement::ColorAlpha(na::Vector4::new(1., 0., 0., 0.5)))
+        );
+        assert_eq!(
+            parse_object("color(c = \"red\", alpha = 0.25);").unwrap(),
+            ScadObject::new(ScadElement::NamedColorAlpha(String::from("red"), 0.25))
+        );
+        assert_eq!(
+            error_kind("color(\"bleu\");"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("color"),
+                    argument: String::from("c"),
+                    expected: "a colour name or hex colour"
+                },
+                1,
+                7
+            )
+        );
+        assert_eq!(
+            error_kind("color([1, 0, 0, 1], alpha = 0.5);"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("color"),
+                    argument: String::from("alpha"),
+                    expected: "left out when the colour has 4 components"
+                },
+                1,
+                29
+            )
+        );
+    }
+
+    #[test]
+    fn text_test() {
+        assert_eq!(
+            parse_object("text(\"A\", 3, halign = \"center\", $fn = 8);").unwrap(),
+            ScadObject::new(ScadElement::Text(TextParams {
+                size: 3.,
+                halign: HorizontalAlign::Center,
+                ..TextParams::new("A")
+            }))
+            .with_detail(8)
+        );
+        let text = ScadObject::new(ScadElement::Text(TextParams::new("A"))).with_detail(8);
+        assert_eq!(parse_object(&text.get_code()).unwrap(), text);
+        let text = parse_object("text(\"A\", direction = \"rtl\", script = \"latin\");").unwrap();
+        assert_eq!(
+            *text.element(),
+            ScadElement::Text(TextParams {
+                direction: Some(TextDirection::RightToLeft),
+                script: Some(String::from("latin")),
+                ..TextParams::new("A")
+            })
+        );
+        assert_eq!(
+            text.get_code(),
+            "text(\"A\",size=10,halign=\"left\",valign=\"baseline\",spacing=1,direction=\"rtl\",script=\"latin\");"
+        );
+        assert_eq!(
+            error_kind("text(\"A\", valign = \"middle\");"),
+            (
+                ParseErrorKind::InvalidArgument {
+                    module: String::from("text"),
+                    argument: String::from("valign"),
+                    expected: "\"top\", \"center\", \"baseline\" or \"bottom\""
+                },
+                1,
+                20
+            )
+        );
+    }
+
+    #[test]
+    fn string_escape_test() {
+        let parsed = parse_object("import(\"a\\x41\\u00e5\\U01f980\\\\\\\"\\t\");").unwrap();
+        assert_eq!(
+            parsed,
+            ScadObject::new(ScadElement::Import(String::from("aAå🦀\\\"\t")))
+        );
+    }
+}
@@ -1,77 +1,508 @@
+use crate::scad_error::{ScadError, ScadResult};
+use crate::scad_format::{FormatOptions, IndentStyle, OpenScadVersion};
+use crate::scad_object::ObjectParams;
 use nalgebra as na;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::string::String;
 use std::vec::Vec;
 
-/// Trait for converting from rust types to strings compatible with openscad
-pub trait ScadType {
-    fn get_code(&self) -> String;
+/**
+  Destination for generated scad code.
+
+  Wraps any `fmt::Write` and keeps track of the current indentation level so
+  that nested objects can be written in a single pass instead of re-indenting
+  the code of their children after the fact.
+*/
+pub struct ScadWriter<'a> {
+    out: &'a mut dyn fmt::Write,
+
+    options: FormatOptions,
+
+    indent: usize,
+
+    output_dir: Option<PathBuf>,
+
+    //The params of the object whose element is being written
+    call: Option<PendingCall>,
 }
 
-impl ScadType for na::Vector3<f32> {
-    fn get_code(&self) -> String {
-        String::from("[")
-            + &self.x.get_code()
-            + ","
-            + &self.y.get_code()
-            + ","
-            + &self.z.get_code()
-            + "]"
+/**
+  The params of an object, which are added to the call of its element when
+  the element finishes it with `ScadWriter::end_call`.
+*/
+pub(crate) struct PendingCall {
+    params: ObjectParams,
+    accepts_center: bool,
+    any_argument: bool,
+    //The name of the module, set by begin_call
+    module: String,
+    //The names of the arguments that the element wrote
+    arguments: Vec<String>,
+    //True until something is written after the ( of the call
+    empty: bool,
+}
+
+impl PendingCall {
+    /// `any_argument` allows expressions for arguments that the element
+    /// doesn't write, like the ones of modules from libraries
+    pub(crate) fn new(params: ObjectParams, accepts_center: bool, any_argument: bool) -> Self {
+        PendingCall {
+            params,
+            accepts_center,
+            any_argument,
+            module: String::new(),
+            arguments: Vec::new(),
+            empty: true,
+        }
+    }
+
+    /// Writes the params that the element didn't write as arguments
+    fn write_params(self, w: &mut ScadWriter) -> ScadResult {
+        let PendingCall {
+            mut params,
+            accepts_center,
+            any_argument,
+            module,
+            arguments,
+            mut empty,
+        } = self;
+        let expressions = std::mem::take(&mut params.expressions);
+
+        let centered =
+            params.center.is_some() || expressions.iter().any(|(name, _)| name == "center");
+        if centered && !accepts_center {
+            return Err(ScadError::UnsupportedArgument {
+                module,
+                argument: String::from("center"),
+            });
+        }
+        //Other arguments that the element didn't write are most likely
+        //misspelled, only calls of modules can take any argument
+        let unused = expressions
+            .iter()
+            .find(|(name, _)| !name.starts_with('$') && name != "center");
+        if let (Some((name, _)), false) = (unused, any_argument) {
+            return Err(ScadError::UnsupportedArgument {
+                module,
+                argument: name.clone(),
+            });
+        }
+
+        params.write_each(w, expressions, |w, name| {
+            //Like a $fn argument of a call that is also set with with_detail
+            if arguments.iter().any(|argument| argument == name) {
+                return Err(ScadError::DuplicateArgument {
+                    module: module.clone(),
+                    argument: name.to_string(),
+                });
+            }
+            if !empty {
+                w.write_char(',')?;
+            }
+            empty = false;
+            write_identifier(w, name)?;
+            w.write_char('=')
+        })?;
+        Ok(())
     }
 }
-impl ScadType for na::Vector2<f32> {
+
+impl<'a> ScadWriter<'a> {
+    pub fn new(out: &'a mut dyn fmt::Write) -> ScadWriter<'a> {
+        ScadWriter::with_options(out, FormatOptions::default())
+    }
+
+    pub fn with_options(out: &'a mut dyn fmt::Write, options: FormatOptions) -> ScadWriter<'a> {
+        ScadWriter {
+            out,
+            options,
+            indent: 0,
+            output_dir: None,
+            call: None,
+        }
+    }
+
+    pub fn options(&self) -> &FormatOptions {
+        &self.options
+    }
+
+    /// Fails with `ScadError::UnsupportedVersion` if `feature` needs a newer
+    /// OpenSCAD version than the one the code is generated for
+    pub fn require(&self, feature: &str, version: OpenScadVersion) -> ScadResult {
+        if self.options.target < version {
+            return Err(ScadError::UnsupportedVersion {
+                feature: feature.to_string(),
+                required: version,
+                target: self.options.target,
+            });
+        }
+        Ok(())
+    }
+
+    /// The directory that the code will be saved in, if it is known. Paths of
+    /// imported files are written relative to it
+    pub fn output_dir(&self) -> Option<&Path> {
+        self.output_dir.as_deref()
+    }
+
+    pub fn set_output_dir<P: AsRef<Path>>(&mut self, directory: P) {
+        self.output_dir = Some(directory.as_ref().to_path_buf());
+    }
+
+    /**
+      Writes the value of the argument `name` of the element that is being
+      written. If the object has an expression for the argument in
+      `ObjectParams::expressions`, the expression is written instead.
+      Only the value is written, the caller writes `name=` for named arguments.
+    */
+    pub fn argument(&mut self, name: &str, value: &dyn ScadType) -> ScadResult {
+        let expression = self.call.as_mut().and_then(|call| {
+            call.arguments.push(name.to_string());
+            let expressions = &mut call.params.expressions;
+            let index = expressions.iter().position(|(arg, _)| arg == name)?;
+            Some(expressions.remove(index).1)
+        });
+        match expression {
+            Some(expression) => expression.write_code(self),
+            None => value.write_code(self),
+        }
+    }
+
+    /// True if the object that is being written has an expression for the
+    /// argument `name`. Optional arguments without a value are written when
+    /// they have one
+    pub(crate) fn has_expression(&self, name: &str) -> bool {
+        self.call
+            .as_ref()
+            .is_some_and(|call| call.params.expressions.iter().any(|(arg, _)| arg == name))
+    }
+
+    /// Sets the params that the next call of an element is finished with
+    pub(crate) fn start_call(&mut self, call: Option<PendingCall>) {
+        self.call = call;
+    }
+
+    /// Writes `module(` for the call of an element
+    pub(crate) fn begin_call(&mut self, module: &str) -> ScadResult {
+        write_identifier(self, module)?;
+        self.write_char('(')?;
+        if let Some(ref mut call) = self.call {
+            call.module = module.to_string();
+            call.empty = true;
+        }
+        Ok(())
+    }
+
+    /**
+      Writes the `)` at the end of the call of an element. The params of the
+      object that the element belongs to, like special variables and
+      expressions that the element didn't use, are added before it.
+    */
+    pub(crate) fn end_call(&mut self) -> ScadResult {
+        if let Some(call) = self.call.take() {
+            call.write_params(self)?;
+        }
+        self.write_char(')')
+    }
+
+    //Arguments of a call are separated from the params added by end_call
+    fn mark_written(&mut self) {
+        if let Some(ref mut call) = self.call {
+            call.empty = false;
+        }
+    }
+
+    /// Returns the number of indentation levels that follow each new line
+    pub fn indent_level(&self) -> usize {
+        self.indent
+    }
+
+    pub fn indent(&mut self) {
+        self.indent += 1;
+    }
+
+    pub fn dedent(&mut self) {
+        self.indent = self.indent.saturating_sub(1);
+    }
+
+    pub fn write_str(&mut self, s: &str) -> ScadResult {
+        self.mark_written();
+        Ok(self.out.write_str(s)?)
+    }
+
+    pub fn write_char(&mut self, c: char) -> ScadResult {
+        self.mark_written();
+        Ok(self.out.write_char(c)?)
+    }
+
+    /// Makes `write!` work on the writer
+    pub fn write_fmt(&mut self, args: fmt::Arguments) -> ScadResult {
+        self.mark_written();
+        Ok(self.out.write_fmt(args)?)
+    }
+
+    /// Starts a new line at the current indentation level. Does nothing
+    /// in compact mode
+    pub fn newline(&mut self) -> ScadResult {
+        if self.options.compact {
+            return Ok(());
+        }
+
+        let indent_char = match self.options.indent_style {
+            IndentStyle::Tabs => '\t',
+            IndentStyle::Spaces => ' ',
+        };
+
+        self.out.write_char('\n')?;
+        for _ in 0..self.indent * self.options.indent_width {
+            self.out.write_char(indent_char)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a space unless in compact mode
+    pub fn space(&mut self) -> ScadResult {
+        if self.options.compact {
+            return Ok(());
+        }
+        self.write_char(' ')
+    }
+}
+
+/**
+  Trait for converting from rust types to strings compatible with openscad
+
+  Implementors provide `write_code`, `get_code` and `try_get_code` are
+  convenience wrappers which collect the output into a `String`.
+*/
+pub trait ScadType {
+    /// Writes the scad code for the value to `w`
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult;
+
+    /// Returns the scad code for the value, or the reason why the value
+    /// can't be represented in OpenSCAD
+    fn try_get_code(&self) -> ScadResult<String> {
+        let mut result = String::new();
+        self.write_code(&mut ScadWriter::new(&mut result))?;
+        Ok(result)
+    }
+
+    /**
+      Returns the scad code for the value
+
+      ## Panics
+      Panics if the value can't be represented in OpenSCAD, use `try_get_code`
+      to handle that case.
+    */
     fn get_code(&self) -> String {
-        String::from("[") + &self.x.get_code() + "," + &self.y.get_code() + "]"
+        self.try_get_code().unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<T: ScadType + na::Scalar> ScadType for na::Vector3<T> {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_char('[')?;
+        self.x.write_code(w)?;
+        w.write_char(',')?;
+        self.y.write_code(w)?;
+        w.write_char(',')?;
+        self.z.write_code(w)?;
+        w.write_char(']')
+    }
+}
+impl<T: ScadType + na::Scalar> ScadType for na::Vector4<T> {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_char('[')?;
+        self.x.write_code(w)?;
+        w.write_char(',')?;
+        self.y.write_code(w)?;
+        w.write_char(',')?;
+        self.z.write_code(w)?;
+        w.write_char(',')?;
+        self.w.write_code(w)?;
+        w.write_char(']')
+    }
+}
+/// Matrices are written as a vector of rows
+impl<T: ScadType + na::Scalar> ScadType for na::Matrix4<T> {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_char('[')?;
+        for (i, row) in self.row_iter().enumerate() {
+            if i != 0 {
+                w.write_char(',')?;
+            }
+            w.write_char('[')?;
+            for (j, value) in row.iter().enumerate() {
+                if j != 0 {
+                    w.write_char(',')?;
+                }
+                value.write_code(w)?;
+            }
+            w.write_char(']')?;
+        }
+        w.write_char(']')
+    }
+}
+impl<T: ScadType + na::Scalar> ScadType for na::Vector2<T> {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_char('[')?;
+        self.x.write_code(w)?;
+        w.write_char(',')?;
+        self.y.write_code(w)?;
+        w.write_char(']')
+    }
+}
+
+/**
+  Writes a floating point number rounded to `FormatOptions::precision` decimals
+  without trailing zeros. The output does not depend on the platform, so
+  generated files only change when the values do. Negative zero, including
+  small negative numbers that round to zero, is written as `0`.
+
+  OpenSCAD has no syntax for NaN or infinite numbers, writing them is an error.
+*/
+fn write_float(w: &mut ScadWriter, value: f64) -> ScadResult {
+    if !value.is_finite() {
+        return Err(ScadError::NonFiniteNumber(value));
+    }
+
+    let mut code = format!("{:.*}", w.options().precision, value);
+    if code.contains('.') {
+        let trimmed = code.trim_end_matches('0').trim_end_matches('.').len();
+        code.truncate(trimmed);
+    }
+
+    if code == "-0" {
+        w.write_char('0')
+    } else {
+        w.write_str(&code)
     }
 }
 
 impl ScadType for f32 {
-    fn get_code(&self) -> String {
-        self.to_string()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write_float(w, f64::from(*self))
+    }
+}
+impl ScadType for f64 {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write_float(w, *self)
     }
 }
 impl ScadType for i32 {
-    fn get_code(&self) -> String {
-        self.to_string()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write!(w, "{}", self)
+    }
+}
+impl ScadType for u32 {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write!(w, "{}", self)
     }
 }
 impl ScadType for usize {
-    fn get_code(&self) -> String {
-        self.to_string()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write!(w, "{}", self)
     }
 }
 impl ScadType for u64 {
-    fn get_code(&self) -> String {
-        self.to_string()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write!(w, "{}", self)
     }
 }
 impl ScadType for bool {
-    fn get_code(&self) -> String {
-        self.to_string()
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        write!(w, "{}", self)
+    }
+}
+
+/// Missing values are written as `undef`
+impl<T: ScadType> ScadType for Option<T> {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        match *self {
+            Some(ref value) => value.write_code(w),
+            None => w.write_str("undef"),
+        }
     }
 }
 
 impl<T: ScadType> ScadType for Vec<T> {
-    fn get_code(&self) -> String {
-        let mut result = "[".to_string();
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        w.write_char('[')?;
 
         for elem in self {
-            result = result + &elem.get_code() + ",";
+            elem.write_code(w)?;
+            w.write_char(',')?;
         }
 
-        result += "]";
+        w.write_char(']')
+    }
+}
+
+/**
+  Strings are written as OpenSCAD string literals. Quotes, backslashes and
+  control characters are escaped the way the OpenSCAD lexer expects, other
+  characters are written as they are unless `FormatOptions::escape_non_ascii`
+  is set.
 
-        result
+  OpenSCAD turns `\0` in strings into spaces, so strings containing NUL
+  characters can not be represented and are reported as `ScadError::InvalidString`.
+*/
+impl ScadType for str {
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        let escape_non_ascii = w.options().escape_non_ascii;
+
+        w.write_char('"')?;
+        for c in self.chars() {
+            match c {
+                '"' => w.write_str("\\\"")?,
+                '\\' => w.write_str("\\\\")?,
+                '\n' => w.write_str("\\n")?,
+                '\t' => w.write_str("\\t")?,
+                '\r' => w.write_str("\\r")?,
+                '\0' => return Err(ScadError::InvalidString(self.to_string())),
+                c if c.is_ascii_control() => write!(w, "\\x{:02x}", c as u32)?,
+                c if c.is_ascii() || !(escape_non_ascii || c.is_control()) => w.write_char(c)?,
+                c if (c as u32) <= 0xffff => write!(w, "\\u{:04x}", c as u32)?,
+                c => write!(w, "\\U{:06x}", c as u32)?,
+            }
+        }
+        w.write_char('"')
+    }
+}
+
+/// Words that OpenSCAD reserves and that can't be used as variable names
+const KEYWORDS: [&str; 14] = [
+    "module", "function", "if", "else", "let", "assert", "echo", "for", "each", "true", "false",
+    "undef", "include", "use",
+];
+
+/**
+  Writes a variable name. Names must start with a letter, `_` or `$` and
+  only contain letters, digits and `_`, anything else is reported as
+  `ScadError::InvalidIdentifier`.
+*/
+pub(crate) fn write_identifier(w: &mut ScadWriter, name: &str) -> ScadResult {
+    let rest = name.strip_prefix('$').unwrap_or(name);
+    let valid = rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && rest.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name);
+
+    if valid {
+        w.write_str(name)
+    } else {
+        Err(ScadError::InvalidIdentifier(name.to_string()))
     }
 }
 
 impl ScadType for String {
-    fn get_code(&self) -> String {
-        String::from("\"") + &self.clone() + "\""
+    fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+        self.as_str().write_code(w)
     }
 }
 
 #[cfg(test)]
-mod type_tests {
+pub(crate) mod type_tests {
     use crate::scad_type::*;
 
     #[test]
@@ -86,4 +517,165 @@ mod type_tests {
 
         assert_eq!(vec!(1, 2, 3, 4, 5, 6).get_code(), "[1,2,3,4,5,6,]");
     }
+
+    #[test]
+    fn float_format_test() {
+        assert_eq!(1.0f32.get_code(), "1");
+        assert_eq!((-5.0f32).get_code(), "-5");
+        assert_eq!((0.1f32 + 0.2f32).get_code(), "0.3");
+        assert_eq!((0.1f64 + 0.2f64).get_code(), "0.3");
+        assert_eq!(1.0e7f32.get_code(), "10000000");
+        assert_eq!(0.000_001f64.get_code(), "0.000001");
+        assert_eq!(1.234_567_89f64.get_code(), "1.234568");
+
+        assert_eq!((-0.0f32).get_code(), "0");
+        assert_eq!((-0.000_000_1f64).get_code(), "0");
+        assert_eq!(na::Vector3::new(-0.5f32, -0., -0.).get_code(), "[-0.5,0,0]");
+    }
+
+    #[test]
+    fn float_precision_test() {
+        let options = FormatOptions {
+            precision: 2,
+            ..Default::default()
+        };
+
+        let mut result = String::new();
+        vec![1.005f64, 2.5, 1.0 / 3.0, -0.001]
+            .write_code(&mut ScadWriter::with_options(&mut result, options))
+            .unwrap();
+        assert_eq!(result, "[1,2.5,0.33,0,]");
+    }
+
+    #[test]
+    fn float_non_finite_test() {
+        match f32::NAN.try_get_code() {
+            Err(ScadError::NonFiniteNumber(value)) => assert!(value.is_nan()),
+            _ => panic!("NaN should be rejected"),
+        }
+        match vec![1., f64::NEG_INFINITY].try_get_code() {
+            Err(ScadError::NonFiniteNumber(value)) => assert_eq!(value, f64::NEG_INFINITY),
+            _ => panic!("infinity should be rejected"),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn float_nan_panic_test() {
+        f32::NAN.get_code();
+    }
+
+    #[test]
+    fn writer_indent_test() {
+        let mut result = String::new();
+        let mut w = ScadWriter::new(&mut result);
+
+        w.write_str("a").unwrap();
+        w.indent();
+        w.indent();
+        w.newline().unwrap();
+        w.write_str("b").unwrap();
+        w.dedent();
+        w.newline().unwrap();
+        w.write_str("c").unwrap();
+
+        assert_eq!(result, "a\n\t\tb\n\tc");
+    }
+
+    #[test]
+    fn writer_options_test() {
+        let mut result = String::new();
+        let mut w = ScadWriter::with_options(&mut result, FormatOptions::spaces(2));
+        w.indent();
+        w.newline().unwrap();
+        w.space().unwrap();
+        assert_eq!(result, "\n   ");
+
+        let mut result = String::new();
+        let mut w = ScadWriter::with_options(&mut result, FormatOptions::compact());
+        w.indent();
+        w.newline().unwrap();
+        w.space().unwrap();
+        assert_eq!(result, "");
+    }
+
+    /// Reads a string literal the way the OpenSCAD lexer does
+    pub(crate) fn unescape(literal: &str) -> String {
+        let inner = &literal[1..literal.len() - 1];
+        let mut result = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            let hex = |chars: &mut std::str::Chars, n: usize| {
+                let digits: String = chars.take(n).collect();
+                std::char::from_u32(u32::from_str_radix(&digits, 16).unwrap()).unwrap()
+            };
+            match chars.next().unwrap() {
+                'n' => result.push('\n'),
+                't' => result.push('\t'),
+                'r' => result.push('\r'),
+                'x' => result.push(hex(&mut chars, 2)),
+                'u' => result.push(hex(&mut chars, 4)),
+                'U' => result.push(hex(&mut chars, 6)),
+                other => result.push(other),
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn string_escape_test() {
+        assert_eq!("plain".get_code(), "\"plain\"");
+        assert_eq!(
+            String::from("say \"hi\"\\").get_code(),
+            "\"say \\\"hi\\\"\\\\\""
+        );
+        assert_eq!("a\nb\tc\rd".get_code(), "\"a\\nb\\tc\\rd\"");
+        assert_eq!("bell\u{7}".get_code(), "\"bell\\x07\"");
+        assert_eq!("åäö".get_code(), "\"åäö\"");
+        assert_eq!("\u{85}".get_code(), "\"\\u0085\"");
+    }
+
+    #[test]
+    fn string_escape_non_ascii_test() {
+        let options = FormatOptions {
+            escape_non_ascii: true,
+            ..Default::default()
+        };
+
+        let mut result = String::new();
+        "ö€🦀"
+            .write_code(&mut ScadWriter::with_options(&mut result, options))
+            .unwrap();
+        assert_eq!(result, "\"\\u00f6\\u20ac\\U01f980\"");
+        assert_eq!(unescape(&result), "ö€🦀");
+    }
+
+    #[test]
+    fn string_nul_test() {
+        match "nul\0".try_get_code() {
+            Err(ScadError::InvalidString(value)) => assert_eq!(value, "nul\0"),
+            _ => panic!("NUL should be rejected"),
+        }
+    }
+
+    //Types that only know how to produce a String should still be writable
+    struct OnlyWriteCode;
+    impl ScadType for OnlyWriteCode {
+        fn write_code(&self, w: &mut ScadWriter) -> ScadResult {
+            w.write_str("custom")
+        }
+    }
+
+    #[test]
+    fn write_code_only_test() {
+        assert_eq!(OnlyWriteCode.try_get_code().unwrap(), "custom");
+        assert_eq!(
+            vec!(OnlyWriteCode, OnlyWriteCode).get_code(),
+            "[custom,custom,]"
+        );
+    }
 }
@@ -0,0 +1,319 @@
+use crate::scad_element::*;
+use crate::scad_module::ScadModule;
+use crate::scad_object::*;
+
+use std::mem;
+
+/**
+  Walks over a tree of scad objects without changing it.
+
+  `enter` is called for each object before its children (pre-order) and
+  `leave` after them (post-order). `depth` is 0 for the object that the walk
+  was started on, or for the top level objects of a file. `path` has the same
+  meaning as in `ScadError::At`: the index of the child at each level,
+  starting from the root.
+
+  Files visit the bodies of their modules before their objects. The objects
+  of a body are visited between `enter_module` and `leave_module` of the
+  module, with depth 0 and their index in the body as the path, like errors
+  in `ScadError::InModule`.
+
+  ```
+  # use scad::*;
+  struct CubeCounter(usize);
+
+  impl ScadVisitor for CubeCounter {
+      fn enter(&mut self, object: &ScadObject, _depth: usize, _path: &[usize]) -> bool {
+          if let Cube(_) = object.element() {
+              self.0 += 1;
+          }
+          true
+      }
+  }
+
+  let tree = scad!(Union; {
+      scad!(Cube(vec3(1., 1., 1.))),
+      scad!(Translate(vec3(2., 0., 0.)); scad!(Cube(vec3(1., 1., 1.)))),
+  });
+
+  let mut counter = CubeCounter(0);
+  tree.visit(&mut counter);
+  assert_eq!(counter.0, 2);
+  ```
+*/
+pub trait ScadVisitor {
+    /// Called before the children of `object` are visited. Returning false
+    /// skips the children, `leave` is still called for the object.
+    fn enter(&mut self, _object: &ScadObject, _depth: usize, _path: &[usize]) -> bool {
+        true
+    }
+
+    /// Called after all children of `object` have been visited
+    fn leave(&mut self, _object: &ScadObject, _depth: usize, _path: &[usize]) {}
+
+    /// Called before the body of a module in a file is visited. Returning
+    /// false skips the body, `leave_module` is still called for the module.
+    fn enter_module(&mut self, _module: &ScadModule) -> bool {
+        true
+    }
+
+    /// Called after the body of `module` has been visited
+    fn leave_module(&mut self, _module: &ScadModule) {}
+}
+
+/**
+  Rebuilds a tree of scad objects, bottom up.
+
+  The default methods leave the tree unchanged, override the ones you need.
+  When overriding `fold_object`, call `fold_object_default` to keep folding
+  the children of the object.
+
+  ```
+  # use scad::*;
+  //Paint every cube red and make sure nothing is drawn alone
+  struct RedCubes;
+
+  impl ScadFold for RedCubes {
+      fn fold_object(&mut self, mut object: ScadObject) -> ScadObject {
+          object.set_important(false);
+          let object = fold_object_default(self, object);
+
+          match object.element() {
+              Cube(_) => scad!(Color(vec3(1., 0., 0.)); object),
+              _ => object,
+          }
+      }
+  }
+
+  let tree = scad!(Translate(vec3(1., 0., 0.)); scad!(Cube(vec3(1., 1., 1.))).important());
+
+  assert_eq!(
+      tree.fold(&mut RedCubes),
+      scad!(Translate(vec3(1., 0., 0.)); {
+          scad!(Color(vec3(1., 0., 0.)); scad!(Cube(vec3(1., 1., 1.))))
+      })
+  );
+  ```
+*/
+pub trait ScadFold {
+    fn fold_object(&mut self, object: ScadObject) -> ScadObject {
+        fold_object_default(self, object)
+    }
+
+    /// Folds the children of an object, or the top level objects of a file.
+    /// Override this to remove or add objects.
+    fn fold_children(&mut self, children: Vec<ScadObject>) -> Vec<ScadObject> {
+        children
+            .into_iter()
+            .map(|child| self.fold_object(child))
+            .collect()
+    }
+
+    fn fold_element(&mut self, element: ScadElement) -> ScadElement {
+        element
+    }
+
+    /// Folds a module definition of a file. By default its body is folded
+    /// like the top level objects, with `fold_children`.
+    fn fold_module(&mut self, mut module: ScadModule) -> ScadModule {
+        module.body = self.fold_children(module.body);
+        module
+    }
+}
+
+/**
+  What `ScadFold::fold_object` does unless it's overridden: folds the
+  children of the object with `fold_children`, then its element with
+  `fold_element`.
+*/
+pub fn fold_object_default<F: ScadFold + ?Sized>(
+    folder: &mut F,
+    mut object: ScadObject,
+) -> ScadObject {
+    let children = mem::take(object.children_mut());
+    *object.children_mut() = folder.fold_children(children);
+
+    let element = mem::replace(object.element_mut(), ScadElement::Union);
+    object.set_element(folder.fold_element(element));
+    object
+}
+
+pub(crate) fn walk<V: ScadVisitor + ?Sized>(
+    object: &ScadObject,
+    visitor: &mut V,
+    depth: usize,
+    path: &mut Vec<usize>,
+) {
+    if visitor.enter(object, depth, path) {
+        for (i, child) in object.children().iter().enumerate() {
+            path.push(i);
+            walk(child, visitor, depth + 1, path);
+            path.pop();
+        }
+    }
+    visitor.leave(object, depth, path);
+}
+
+#[cfg(test)]
+mod visit_tests {
+    use super::*;
+    use crate::scad_file::ScadFile;
+    use crate::scad_type::ScadType;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        skip_hull: bool,
+    }
+
+    impl ScadVisitor for Recorder {
+        fn enter(&mut self, object: &ScadObject, depth: usize, path: &[usize]) -> bool {
+            self.events
+                .push(format!("enter {:?} {} {:?}", object.element(), depth, path));
+            !(self.skip_hull && *object.element() == ScadElement::Hull)
+        }
+
+        fn leave(&mut self, object: &ScadObject, depth: usize, path: &[usize]) {
+            self.events
+                .push(format!("leave {:?} {} {:?}", object.element(), depth, path));
+        }
+
+        fn enter_module(&mut self, module: &ScadModule) -> bool {
+            self.events.push(format!("enter module {}", module.name));
+            module.name != "skipped"
+        }
+
+        fn leave_module(&mut self, module: &ScadModule) {
+            self.events.push(format!("leave module {}", module.name));
+        }
+    }
+
+    fn tree() -> ScadObject {
+        let mut hull = ScadObject::new(ScadElement::Hull);
+        hull.add_child(ScadObject::new(ScadElement::Sphere(CircleType::Radius(1.))));
+
+        let mut union = ScadObject::new(ScadElement::Union);
+        union.add_child(hull);
+        union.add_child(ScadObject::new(ScadElement::Minkowski).important());
+        union
+    }
+
+    #[test]
+    fn visit_order_test() {
+        let mut recorder = Recorder::default();
+        tree().visit(&mut recorder);
+
+        assert_eq!(
+            recorder.events,
+            vec![
+                "enter Union 0 []",
+                "enter Hull 1 [0]",
+                "enter Sphere(Radius(1.0)) 2 [0, 0]",
+                "leave Sphere(Radius(1.0)) 2 [0, 0]",
+                "leave Hull 1 [0]",
+                "enter Minkowski 1 [1]",
+                "leave Minkowski 1 [1]",
+                "leave Union 0 []",
+            ]
+        );
+    }
+
+    #[test]
+    fn visit_skip_test() {
+        let mut recorder = Recorder {
+            skip_hull: true,
+            ..Default::default()
+        };
+        tree().visit(&mut recorder);
+
+        assert_eq!(recorder.events.len(), 6);
+        assert_eq!(recorder.events[1], "enter Hull 1 [0]");
+        assert_eq!(recorder.events[2], "leave Hull 1 [0]");
+    }
+
+    #[test]
+    fn visit_file_test() {
+        let mut file = ScadFile::new();
+        file.add_object(ScadObject::new(ScadElement::Union));
+        file.add_object(tree());
+
+        let mut recorder = Recorder::default();
+        file.visit(&mut recorder);
+
+        assert_eq!(recorder.events[0], "enter Union 0 [0]");
+        assert_eq!(recorder.events[2], "enter Union 0 [1]");
+        assert_eq!(recorder.events[3], "enter Hull 1 [1, 0]");
+    }
+
+    #[test]
+    fn visit_module_test() {
+        let mut file = ScadFile::new();
+        file.define_module("part", Vec::new(), vec![tree()]);
+        file.define_module("skipped", Vec::new(), vec![tree()]);
+        file.add_object(ScadObject::new(ScadElement::Minkowski));
+
+        let mut recorder = Recorder::default();
+        file.visit(&mut recorder);
+
+        assert_eq!(recorder.events.len(), 14);
+        assert_eq!(recorder.events[0], "enter module part");
+        assert_eq!(recorder.events[1], "enter Union 0 [0]");
+        assert_eq!(recorder.events[2], "enter Hull 1 [0, 0]");
+        assert_eq!(recorder.events[9], "leave module part");
+        assert_eq!(recorder.events[10], "enter module skipped");
+        assert_eq!(recorder.events[11], "leave module skipped");
+        assert_eq!(recorder.events[12], "enter Minkowski 0 [0]");
+    }
+
+    struct NoHulls;
+
+    impl ScadFold for NoHulls {
+        fn fold_children(&mut self, children: Vec<ScadObject>) -> Vec<ScadObject> {
+            children
+                .into_iter()
+                .filter(|child| *child.element() != ScadElement::Hull)
+                .map(|child| self.fold_object(child))
+                .collect()
+        }
+
+        fn fold_element(&mut self, element: ScadElement) -> ScadElement {
+            match element {
+                ScadElement::Minkowski => ScadElement::Difference,
+                other => other,
+            }
+        }
+    }
+
+    #[test]
+    fn fold_test() {
+        let mut expected = ScadObject::new(ScadElement::Union);
+        expected.add_child(ScadObject::new(ScadElement::Difference).important());
+        assert_eq!(tree().fold(&mut NoHulls), expected);
+
+        let mut file = ScadFile::new();
+        file.set_detail(10);
+        file.add_object(ScadObject::new(ScadElement::Hull));
+        file.add_object(tree());
+
+        file.define_module("part", Vec::new(), vec![tree()]);
+
+        let folded = file.fold(&mut NoHulls);
+        assert_eq!(folded.get_detail(), 10);
+        assert_eq!(folded.objects(), &[expected.clone()][..]);
+        assert_eq!(folded.modules()[0].body, vec![expected]);
+    }
+
+    #[test]
+    fn accessor_test() {
+        let mut object = tree();
+        assert_eq!(object.children().len(), 2);
+        assert!(!object.is_marked_important());
+        assert!(object.children()[1].is_marked_important());
+
+        object.children_mut()[1].set_important(false);
+        object.children_mut().remove(0);
+        *object.element_mut() = ScadElement::Hull;
+
+        assert_eq!(object.get_code(), "hull()\n{\n\tminkowski();\n}");
+    }
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "serde")]
+
+use scad::*;
+
+#[test]
+fn json_shape() {
+    let obj = scad!(Translate(vec3(1., 2., 3.)); {
+        scad!(Cylinder(10., Radius(3.))),
+        scad!(Union).important(),
+        scad!(Hull).background(),
+    });
+
+    assert_eq!(
+        serde_json::to_string(&obj).unwrap(),
+        concat!(
+            r#"{"element":{"Translate":[1.0,2.0,3.0]},"children":["#,
+            r#"{"element":{"Cylinder":[10.0,{"Radius":3.0}]},"children":[]},"#,
+            r#"{"element":"Union","children":[],"modifier":"Root"},"#,
+            r#"{"element":"Hull","children":[],"modifier":"Background"}"#,
+            r#"]}"#
+        )
+    );
+
+    assert_eq!(serde_json::to_string(&ScadFile::new()).unwrap(), r#"{"objects":[]}"#);
+
+    assert_eq!(
+        serde_json::to_string(&LinExtrudeParams::default()).unwrap(),
+        r#"{"height":1.0,"center":false,"convexity":10,"twist":0.0,"slices":1}"#
+    );
+    assert_eq!(
+        serde_json::to_string(
+            &PolygonParameters::new(vec![vec2(0., 1.)]).single_vector_path(vec![0])
+        )
+        .unwrap(),
+        r#"{"points":[[0.0,1.0]],"path":{"SingleVector":[0]},"convexity":10}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&Offset(OffsetType::Delta(1.), true)).unwrap(),
+        r#"{"Offset":[{"Delta":1.0},true]}"#
+    );
+}
+
+#[test]
+fn optional_fields() {
+    let file: ScadFile = serde_json::from_str(
+        r#"{"objects":[{"element":{"Sphere":{"Diameter":2}}},{"element":"Hull","modifier":"Disable"}]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(file.get_code(), "sphere(d=2);\n*hull();\n");
+}
+
+#[test]
+fn legacy_json() {
+    let file: ScadFile = serde_json::from_str(
+        r#"{"objects":[{"element":"Hull","important":true},{"element":"Union","children":[],"important":false}]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(file.get_code(), "!hull();\nunion();\n");
+
+    let file: ScadFile =
+        serde_json::from_str(r#"{"objects":[{"element":"Union"}],"detail":30}"#).unwrap();
+    assert_eq!(file.get_code(), "$fn=30;\nunion();\n");
+    assert_eq!(
+        serde_json::to_string(&file).unwrap(),
+        r#"{"objects":[{"element":"Union","children":[]}],"variables":{"detail":30}}"#
+    );
+}
+
+#[test]
+fn file_round_trip() {
+    let mut file = ScadFile::new();
+    file.set_detail(32);
+    file.add_customizer_variable(
+        CustomizerVariable::new("size", &vec2(1., 2.))
+            .widget(CustomizerWidget::Step(0.5))
+            .tab("Size"),
+    );
+    file.add_object(scad!(Difference; {
+        scad!(RotateExtrude(RotateExtrudeParams { angle: 90., convexity: 2, ..Default::default() }); {
+            scad!(Translate2d(vec2(5., 0.)); scad!(Circle(Diameter(2.))))
+        }),
+        scad!(Polyhedron(
+            vec![vec3(0., 0., 0.), vec3(1., 0., 0.), vec3(0., 1., 0.)],
+            vec![vec![0, 1, 2]]
+        )),
+        scad!(Polygon(
+            PolygonParameters::new(vec![vec2(0., 0.), vec2(1., 0.), vec2(0., 1.)])
+                .multi_vector_path(vec![vec![0, 1, 2]])
+                .convexity(3)
+        )),
+        scad!(NamedColor("teal".to_string()); scad!(Import("part.stl".to_string()))),
+        scad!(Offset(OffsetType::Radius(0.5), false)).important(),
+        scad!(Square(vec2(1., 1.))).highlight(),
+        scad!(Sphere(Radius(1.))).with_expression("r", ScadExpr::var("size").index(0).pow(2)),
+    }));
+
+    let json = serde_json::to_string(&file).unwrap();
+    let parsed: ScadFile = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(parsed, file);
+    assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+}
@@ -55,3 +55,16 @@ fn scad_file_test() {
         file.get_code()
     );
 }
+
+#[test]
+fn parse_round_trip() {
+    let mut file = ScadFile::new();
+    file.set_detail(40);
+    file.add_object(scad!(Difference; {
+        scad!(Cube(vec3(10., 10., 10.))),
+        scad!(Translate(vec3(5., 5., -1.)); scad!(Cylinder(12., Diameter(4.)))),
+    }));
+
+    let parsed: ScadFile = file.get_code().parse().unwrap();
+    assert_eq!(parsed, file);
+}